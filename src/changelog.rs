@@ -0,0 +1,215 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Changelog between two refs of an upstream repository, like two releases of Polkadot.
+//!
+//! Commits come from the compare API, they're mapped to the PRs they come from
+//! and grouped by label categories of the repository.
+
+use crate::filter::Filter;
+use crate::subcribe_commits::BranchCommit;
+use anyhow::Result;
+use chrono::prelude::*;
+use octocrab::Octocrab;
+use std::{collections::HashSet, fmt::Write, sync::Arc};
+
+// Group of entries without any category, it goes last.
+pub const UNCATEGORIZED: &str = "Others";
+
+// A merged PR between the two refs, or a commit pushed without a PR.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangelogEntry {
+    // The first category of its labels.
+    pub category: Option<String>,
+    pub number: Option<u64>,
+    pub title: String,
+    pub author: String,
+    pub labels: Vec<String>,
+    pub merged_at: Option<DateTime<Utc>>,
+    pub html_url: String,
+}
+
+impl ChangelogEntry {
+    pub fn category(&self) -> &str {
+        self.category.as_deref().unwrap_or(UNCATEGORIZED)
+    }
+
+    fn from_commit(commit: &BranchCommit) -> Self {
+        Self {
+            category: None,
+            number: None,
+            title: commit.title().to_owned(),
+            author: commit.author.clone(),
+            labels: vec![],
+            merged_at: commit.date,
+            html_url: commit.html_url.clone(),
+        }
+    }
+}
+
+// Entries in order of categories, the uncategorized ones last, and in order of commits in a category.
+pub async fn generate_changelog(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from_ref: &str,
+    to_ref: &str,
+    filter: &Filter,
+) -> Result<Vec<ChangelogEntry>> {
+    let commits =
+        crate::subcribe_commits::get_commits_between(octocrab.clone(), org, repo, from_ref, to_ref)
+            .await?;
+
+    let numbers = crate::subcribe_commits::get_commit_pr_numbers(
+        octocrab.clone(),
+        org,
+        repo,
+        &commits,
+        crate::subcribe_commits::MAX_PR_LOOKUPS,
+    )
+    .await?;
+
+    let mut entries = vec![];
+    // Commits of a merged PR share it.
+    let mut seen = HashSet::new();
+    for (commit, number) in commits.iter().zip(numbers) {
+        let Some(number) = number else {
+            entries.push(ChangelogEntry::from_commit(commit));
+            continue;
+        };
+        if !seen.insert(number) {
+            continue;
+        }
+
+        let pr = crate::subcribe_prs::get_pr_by_id(octocrab.clone(), org, repo, number).await?;
+        let labels = pr.labels.as_deref().unwrap_or_default();
+        entries.push(ChangelogEntry {
+            category: filter.categories(labels).into_iter().next(),
+            number: Some(number),
+            title: pr.title.clone().unwrap_or_default(),
+            author: pr
+                .user
+                .as_ref()
+                .map(|user| user.login.clone())
+                .unwrap_or_else(|| commit.author.clone()),
+            labels: labels.iter().map(|label| label.name.clone()).collect(),
+            merged_at: pr.merged_at,
+            html_url: pr
+                .html_url
+                .as_ref()
+                .map(|url| url.to_string())
+                .unwrap_or_default(),
+        });
+    }
+    entries.sort_by_key(|entry| (entry.category.is_none(), entry.category.clone()));
+
+    Ok(entries)
+}
+
+/*
+    The example of changelog:
+    # paritytech/polkadot v0.9.32...v0.9.33
+
+    ## Runtime
+    - Fix `MaxNominators` (#6300) by @bkchr
+*/
+pub fn changelog_markdown(
+    org: &str,
+    repo: &str,
+    from_ref: &str,
+    to_ref: &str,
+    entries: &[ChangelogEntry],
+) -> String {
+    let mut markdown = format!("# {org}/{repo} {from_ref}...{to_ref}\n");
+    let mut category = None;
+    for entry in entries {
+        if category != Some(entry.category()) {
+            category = Some(entry.category());
+            let _ = write!(markdown, "\n## {}\n", entry.category());
+        }
+        let _ = match entry.number {
+            Some(number) => writeln!(
+                markdown,
+                "- {} ([#{number}]({})) by @{}",
+                entry.title, entry.html_url, entry.author
+            ),
+            None => writeln!(
+                markdown,
+                "- {} ([commit]({})) by {}",
+                entry.title, entry.html_url, entry.author
+            ),
+        };
+    }
+    if entries.is_empty() {
+        markdown.push_str("\nNothing changed.\n");
+    }
+
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::MockGithub;
+    use crate::utils::Repository;
+
+    #[tokio::test]
+    async fn changelog_should_be_grouped_by_categories() {
+        let mock = MockGithub::start(&["github"]).await;
+        let polkadot = Repository {
+            categories: [
+                ("B5-clientnoteworthy", "Client"),
+                ("B7-runtimenoteworthy", "Runtime"),
+            ]
+            .into_iter()
+            .map(|(label, category)| (label.to_owned(), category.to_owned()))
+            .collect(),
+            ..Default::default()
+        };
+        let filter = Filter::new(&polkadot).unwrap();
+        let (org, repo) = ("paritytech", "polkadot");
+        let entries = generate_changelog(mock.client(), org, repo, "v0.9.32", "v0.9.33", &filter)
+            .await
+            .unwrap();
+
+        // Both commits of #6310 go to it, and the version bump is pushed without a PR.
+        let grouped = entries
+            .iter()
+            .map(|entry| (entry.category(), entry.number))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            grouped,
+            vec![
+                ("Client", Some(6310)),
+                ("Runtime", Some(6300)),
+                (UNCATEGORIZED, Some(6320)),
+                (UNCATEGORIZED, None),
+            ]
+        );
+        assert_eq!(entries[0].author, "Ank4n");
+        assert_eq!(entries[3].title, "Bump spec version to 9330");
+        // All commits are got from both pages.
+        assert!(mock
+            .requests()
+            .iter()
+            .any(|request| request.contains("v0.9.32...v0.9.33?") && request.contains("page=2")));
+
+        let markdown = changelog_markdown(org, repo, "v0.9.32", "v0.9.33", &entries);
+        assert!(markdown.starts_with("# paritytech/polkadot v0.9.32...v0.9.33\n\n## Client\n- Add `FastUnstake` pallet to Westend ([#6310](https://github.com/paritytech/polkadot/pull/6310)) by @Ank4n\n"));
+        assert!(markdown.contains("\n## Others\n"));
+        assert_eq!(markdown.matches("\n## ").count(), 3);
+    }
+}
@@ -14,11 +14,33 @@
 // You should have received a copy of the GNU General Public License
 // along with Manta.  If not, see <http://www.gnu.org/licenses/>.
 
-use anyhow::Result;
+use crate::changelog::ChangelogEntry;
+use crate::divergence::MissingPr;
+use crate::filter::Filter;
+use crate::links::{ClusterMember, Link, Reference};
+use crate::migrations::MigrationCheck;
+use crate::patches::{ExportOptions, PatchFormat};
+use crate::pins::PinStatus;
+use crate::relevance::Touched;
+use crate::subcribe_commits::BranchCommit;
+use crate::subcribe_issues::ClosedIssue;
+use crate::subcribe_releases::Tag;
+use crate::utils::FetchMode;
+use crate::watchlist::WatchedItem;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use clap::{Args, Parser, Subcommand, ValueEnum};
-use octocrab::params;
+use octocrab::{
+    models::{issues::Issue, pulls::PullRequest, repos::Release},
+    params,
+};
 use polars::prelude::*;
-use std::fs::{create_dir_all, File};
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, File},
+    path::PathBuf,
+};
+use toml::Value;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -29,8 +51,20 @@ pub struct DiscordBotCli {
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
-    Issue(Arguments),
-    Pr(Arguments),
+    Issue(IssueArguments),
+    Pr(PrArguments),
+    Sync(SyncArguments),
+    Watch(WatchArguments),
+    Release(ReleaseArguments),
+    Commits(CommitArguments),
+    Changelog(ChangelogArguments),
+    Forks(ForkArguments),
+    Discover(DiscoverArguments),
+    #[command(subcommand)]
+    Status(StatusCommands),
+    Clusters(ClusterArguments),
+    Migrations(MigrationArguments),
+    Patches(PatchArguments),
 }
 
 #[derive(Args, Debug)]
@@ -43,8 +77,161 @@ pub struct Arguments {
     pub from: String,
     #[arg(long, require_equals = true, ignore_case = true)]
     pub to: String,
+}
+
+#[derive(Args, Debug)]
+pub struct IssueArguments {
+    #[command(flatten)]
+    pub arguments: Arguments,
+    #[arg(long, require_equals = true, ignore_case = true, num_args = 0..=1, default_value_t = IssueStatus::Open, value_enum)]
+    pub status: IssueStatus,
+}
+
+#[derive(Args, Debug)]
+pub struct PrArguments {
+    #[command(flatten)]
+    pub arguments: Arguments,
     #[arg(long, require_equals = true, ignore_case = true, num_args = 0..=1, default_value_t = Status::Merged, value_enum)]
     pub status: Status,
+    #[arg(long, require_equals = true, ignore_case = true, num_args = 0..=1, default_value_t = Strategy::Scan, value_enum)]
+    pub strategy: Strategy,
+}
+
+#[derive(Args, Debug)]
+pub struct SyncArguments {
+    // Fall back to `fetch-mode` in config if it's not given.
+    #[arg(long, require_equals = true, ignore_case = true, value_enum)]
+    pub mode: Option<FetchMode>,
+    // Fall back to `concurrency` in section `github` if it's not given, only for rest mode.
+    #[arg(long, require_equals = true)]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct ReleaseArguments {
+    #[command(flatten)]
+    pub arguments: Arguments,
+    // Include pre-releases, or set `prereleases = true` for the repository.
+    #[arg(long)]
+    pub prereleases: bool,
+    // Report tags instead of releases, or set `track-tags = true` for the repository.
+    #[arg(long)]
+    pub tags: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CommitArguments {
+    #[command(flatten)]
+    pub arguments: Arguments,
+    // Like `polkadot-v0.9.33`, fall back to `branches` of the repository in config.
+    #[arg(long, require_equals = true)]
+    pub branch: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ChangelogArguments {
+    #[arg(long, require_equals = true, ignore_case = true)]
+    pub org: String,
+    #[arg(long, require_equals = true, ignore_case = true)]
+    pub repo: String,
+    // Tag, branch or commit, like `v0.9.36`.
+    #[arg(long, require_equals = true)]
+    pub from_ref: String,
+    #[arg(long, require_equals = true)]
+    pub to_ref: String,
+    #[arg(long, require_equals = true, ignore_case = true, num_args = 0..=1, default_value_t = ChangelogFormat::Markdown, value_enum)]
+    pub format: ChangelogFormat,
+}
+
+// Compare every fork in section `forks` with upstream, and report upstream PRs it misses.
+#[derive(Args, Debug)]
+pub struct ForkArguments {
+    // Only this fork, like `Manta-Network/substrate`.
+    #[arg(long, require_equals = true, ignore_case = true)]
+    pub fork: Option<String>,
+}
+
+// Propose subscriptions from git dependencies, or write them into config with `--write`.
+#[derive(Args, Debug)]
+pub struct DiscoverArguments {
+    #[arg(long, require_equals = true, default_value = "Cargo.lock")]
+    pub lock: PathBuf,
+    // Cargo.toml of crates or the workspace, for git dependencies not in the lock file yet.
+    #[arg(long, require_equals = true)]
+    pub manifest: Vec<PathBuf>,
+    #[arg(long)]
+    pub write: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StatusCommands {
+    Pins(PinArguments),
+}
+
+// Compare commits pinned in `lock-file` of section `relevance` or `pins` of repositories with
+// heads of their branches.
+#[derive(Args, Debug)]
+pub struct PinArguments {
+    // Report every stored check instead of checking now, to chart the trend.
+    #[arg(long)]
+    pub history: bool,
+}
+
+// Report clusters of linked prs merged together, from links kept by sync.
+#[derive(Args, Debug)]
+pub struct ClusterArguments {
+    // Only clusters with a pr of this repository, like `paritytech/substrate`.
+    #[arg(long, require_equals = true, ignore_case = true)]
+    pub repo: Option<String>,
+}
+
+// Report merged PRs likely to be runtime migrations or storage changes since a ref.
+#[derive(Args, Debug)]
+pub struct MigrationArguments {
+    #[arg(long, require_equals = true, ignore_case = true)]
+    pub org: String,
+    #[arg(long, require_equals = true, ignore_case = true)]
+    pub repo: String,
+    // Tag, branch or commit, like `v0.9.33` or the commit we're pinned to.
+    #[arg(long, require_equals = true)]
+    pub since: String,
+    // Substrate, polkadot and cumulus are developed on `master`.
+    #[arg(long, require_equals = true, default_value = "master")]
+    pub to_ref: String,
+}
+
+// Save patches of PRs merged in the window, or of given PRs, with scripts applying them in order.
+#[derive(Args, Debug)]
+pub struct PatchArguments {
+    #[arg(long, require_equals = true, ignore_case = true)]
+    pub org: String,
+    #[arg(long, require_equals = true, ignore_case = true)]
+    pub repo: String,
+    #[arg(long, require_equals = true, requires = "to", conflicts_with = "pr")]
+    pub from: Option<String>,
+    #[arg(long, require_equals = true, requires = "from")]
+    pub to: Option<String>,
+    // Like `--pr=6300 --pr=6310`, unmerged ones are skipped.
+    #[arg(long, require_equals = true, required_unless_present = "from")]
+    pub pr: Vec<u64>,
+    #[arg(long, require_equals = true, ignore_case = true, num_args = 0..=1, default_value_t = PatchFormat::Patch, value_enum)]
+    pub format: PatchFormat,
+    // Patches go to `{output}/{org}/{repo}`.
+    #[arg(long, require_equals = true, default_value = "patches")]
+    pub output: PathBuf,
+    // Fetch PRs exported before again.
+    #[arg(long)]
+    pub force: bool,
+}
+
+// Without `--add` or `--remove`, poll all watched items and print their updates.
+#[derive(Args, Debug)]
+pub struct WatchArguments {
+    // Like `paritytech/substrate#12345`.
+    #[arg(long, require_equals = true, conflicts_with = "remove")]
+    pub add: Option<WatchedItem>,
+    #[arg(long, require_equals = true)]
+    pub remove: Option<WatchedItem>,
 }
 
 #[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
@@ -54,45 +241,407 @@ pub enum Status {
     Closed,
 }
 
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IssueStatus {
+    Open,
+    Closed,
+    Reopened,
+}
+
+// How to find out merged PRs in a date window.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    // Scan PRs sorted by updated date until they're older than the window.
+    Scan,
+    // Search PRs by merged date, it costs much less requests on a long window.
+    Search,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChangelogFormat {
+    // Saved as `changelog.md`, a section per category.
+    Markdown,
+    // Saved as `changelog.csv`, a row per PR.
+    Csv,
+}
+
 pub async fn generate_pr_csv_report(
-    Arguments {
-        org,
-        repo,
-        from,
-        to,
+    PrArguments {
+        arguments:
+            Arguments {
+                org,
+                repo,
+                from,
+                to,
+            },
         status,
-    }: &Arguments,
+        strategy,
+    }: &PrArguments,
+    config: &Value,
 ) -> Result<()> {
     let _path = format!("./{repo}/{from} => {to}");
 
-    let status = match status {
-        Status::Open => params::State::Open,
-        Status::Merged | Status::Closed => params::State::Closed,
+    let octocrab = crate::utils::repository_client(config, org, repo)?;
+    let filter = crate::filter::repository_filter(config, org, repo)?;
+    let (from, to) = crate::utils::parse_from_date_and_to_date(from, to)?;
+    let result = match (status, strategy) {
+        (Status::Open, Strategy::Scan) => {
+            crate::subcribe_prs::get_open_prs_by_date(octocrab, org, repo, from, to).await
+        }
+        (Status::Open, Strategy::Search) => {
+            crate::subcribe_prs::search_open_prs_by_date(octocrab, org, repo, from, to).await
+        }
+        (Status::Merged, Strategy::Scan) => {
+            crate::subcribe_prs::get_all_merged_prs_by_date(
+                octocrab,
+                org,
+                repo,
+                params::State::Closed,
+                from,
+                to,
+            )
+            .await
+        }
+        (Status::Merged, Strategy::Search) => {
+            crate::subcribe_prs::search_merged_prs_by_date(octocrab, org, repo, from, to).await
+        }
+        (Status::Closed, Strategy::Scan) => {
+            crate::subcribe_prs::get_closed_prs_by_date(octocrab, org, repo, from, to).await
+        }
+        (Status::Closed, Strategy::Search) => {
+            crate::subcribe_prs::search_closed_prs_by_date(octocrab, org, repo, from, to).await
+        }
     };
+    // Pages fetched before a failure are still reported, but the report is incomplete.
+    let (mut prs, incomplete) = crate::retry::partial(result)?;
+    prs.retain(|pr| filter.matches_pr(pr));
+    // Changed files are only fetched if the repository has paths or tracks relevance,
+    // they're cached in sled unless the running bot holds it.
+    let mut touched = HashMap::new();
+    if filter.has_paths() || filter.tracks_relevance() {
+        let db = match crate::utils::db_config() {
+            Ok(db) => db,
+            Err(e) => {
+                println!("Changed files are fetched without the cache, failed to open sled: {e}");
+                Arc::new(sled::Config::new().temporary(true).open()?)
+            }
+        };
+        let octocrab = crate::utils::repository_client(config, org, repo)?;
+        for pr in prs.iter() {
+            if let Some(touched_by_pr) = crate::relevance::touched_by_pr(
+                octocrab.clone(),
+                db.clone(),
+                org,
+                repo,
+                pr,
+                &filter,
+            )
+            .await?
+            {
+                touched.insert(pr.number, touched_by_pr);
+            }
+        }
+        prs.retain(|pr| touched.contains_key(&pr.number));
+    }
+    // Every status has its own date, and the report is named after the status.
+    let (date_column, date_of, name): (_, fn(&PullRequest) -> _, _) = match status {
+        Status::Open => ("created date", |pr| pr.created_at, "open"),
+        Status::Merged => ("merged date", |pr| pr.merged_at, "merged"),
+        Status::Closed => ("closed date", |pr| pr.closed_at, "closed"),
+    };
+    let mut df = format_prs(&prs, date_column, date_of, &filter, &touched)?;
+
+    println!("{repo}'s {name} prs report: {df}");
+    create_dir_all(&_path)?;
+
+    let csv_path = format!("{_path}/{name}-pr.csv");
+    println!("The report has been generated at: {:?}.", csv_path);
+    let mut file = File::create(&csv_path)?;
+    CsvWriter::new(&mut file).finish(&mut df)?;
+
+    incomplete_report(&csv_path, incomplete)
+}
+
+// One pr per row, with the date of its status, its category, paths and crates we depend on it touches.
+pub fn format_prs(
+    prs: &[PullRequest],
+    date_column: &str,
+    date_of: fn(&PullRequest) -> Option<DateTime<Utc>>,
+    filter: &Filter,
+    touched: &HashMap<u64, Touched>,
+) -> Result<DataFrame> {
+    let mut link_list = Vec::with_capacity(prs.len());
+    let mut title_list = Vec::with_capacity(prs.len());
+    let mut date_list = Vec::with_capacity(prs.len());
+    let mut category_list = Vec::with_capacity(prs.len());
+    let mut touched_list = Vec::with_capacity(prs.len());
+    let mut relevant_list = Vec::with_capacity(prs.len());
+    for pr in prs {
+        link_list.push(pr.html_url.as_ref().map(|s| s.to_string()));
+        title_list.push(pr.title.as_ref().map(|s| format!("**{0}**", s.trim())));
+        date_list.push(date_of(pr).map(|d| d.to_string()));
+        category_list.push(filter.pr_category(pr));
+        let touched = touched.get(&pr.number);
+        touched_list.push(touched.map(|touched| touched.paths.join(", ")));
+        relevant_list.push(touched.map(|touched| touched.crates.join(", ")));
+    }
+    let df = df![
+        date_column  => date_list,
+        "title" => title_list,
+        "category" => category_list,
+        "touched paths" => touched_list,
+        "relevant crates" => relevant_list,
+        "link"  => link_list,
+    ]?;
+
+    Ok(df)
+}
+
+pub async fn generate_issue_csv_report(
+    IssueArguments {
+        arguments:
+            Arguments {
+                org,
+                repo,
+                from,
+                to,
+            },
+        status,
+    }: &IssueArguments,
+    config: &Value,
+) -> Result<()> {
+    let _path = format!("./{repo}/{from} => {to}");
+
+    let octocrab = crate::utils::repository_client(config, org, repo)?;
+    let filter = crate::filter::repository_filter(config, org, repo)?;
+    let (from, to) = crate::utils::parse_from_date_and_to_date(from, to)?;
+    // Pages fetched before a failure are still reported, but the report is incomplete.
+    let incomplete;
+    // Every status has its own date, and the report is named after the status.
+    let (mut df, name) = match status {
+        IssueStatus::Open => {
+            let (mut new_issues, warning) = crate::retry::partial(
+                crate::subcribe_issues::get_open_issues_by_date(octocrab, org, repo, from, to)
+                    .await,
+            )?;
+            incomplete = warning;
+            new_issues.retain(|issue| filter.matches_issue(issue));
+            let new_issues = new_issues
+                .iter()
+                .map(|issue| (Some(issue.created_at), issue))
+                .collect::<Vec<_>>();
+            (format_issues(&new_issues, "created date", &filter)?, "open")
+        }
+        IssueStatus::Closed => {
+            let (mut closed_issues, warning) = crate::retry::partial(
+                crate::subcribe_issues::get_closed_issues_by_date(octocrab, org, repo, from, to)
+                    .await,
+            )?;
+            incomplete = warning;
+            closed_issues.retain(|closed| filter.matches_issue(&closed.issue));
+            format_closed_issues(&closed_issues, &filter).map(|df| (df, "closed"))?
+        }
+        IssueStatus::Reopened => {
+            let (mut reopened_issues, warning) = crate::retry::partial(
+                crate::subcribe_issues::get_reopened_issues_by_date(octocrab, org, repo, from, to)
+                    .await,
+            )?;
+            incomplete = warning;
+            reopened_issues.retain(|(_, issue)| filter.matches_issue(issue));
+            let reopened_issues = reopened_issues
+                .iter()
+                .map(|(reopened_at, issue)| (Some(*reopened_at), issue))
+                .collect::<Vec<_>>();
+            (
+                format_issues(&reopened_issues, "reopened date", &filter)?,
+                "reopened",
+            )
+        }
+    };
+    println!("{repo}'s {name} issues report: {df}");
+    create_dir_all(&_path)?;
+
+    let csv_path = format!("{_path}/{name}-issue.csv");
+    println!("The report has been generated at: {:?}.", csv_path);
+    let mut file = File::create(&csv_path)?;
+    CsvWriter::new(&mut file).finish(&mut df)?;
+
+    incomplete_report(&csv_path, incomplete)
+}
+
+// A report of partial pages is kept, but it fails the command so it's not taken as complete.
+fn incomplete_report(csv_path: &str, incomplete: Option<String>) -> Result<()> {
+    match incomplete {
+        Some(warning) => {
+            println!("Warning: {warning}");
+            Err(anyhow!("The report at {csv_path:?} is incomplete."))
+        }
+        None => Ok(()),
+    }
+}
 
-    let octocrab = octocrab::instance();
+pub async fn generate_release_csv_report(
+    ReleaseArguments {
+        arguments:
+            Arguments {
+                org,
+                repo,
+                from,
+                to,
+            },
+        prereleases,
+        tags,
+    }: &ReleaseArguments,
+    config: &Value,
+) -> Result<()> {
+    let _path = format!("./{repo}/{from} => {to}");
+
+    let octocrab = crate::utils::repository_client(config, org, repo)?;
+    let repository = crate::utils::get_repository(config, org, repo)?.unwrap_or_default();
     let (from, to) = crate::utils::parse_from_date_and_to_date(from, to)?;
-    let merged_prs =
-        crate::subcribe_prs::get_all_merged_prs_by_date(octocrab, org, repo, status, from, to)
+    let (mut df, name) = match *tags || repository.track_tags {
+        true => {
+            let db = crate::utils::db_config()?;
+            let tags =
+                crate::subcribe_releases::get_tags_by_date(octocrab, db, org, repo, from, to)
+                    .await?;
+            (format_tags(&tags)?, "tag")
+        }
+        false => {
+            let prereleases = *prereleases || repository.prereleases;
+            let releases = crate::subcribe_releases::get_releases_by_date(
+                octocrab,
+                org,
+                repo,
+                from,
+                to,
+                prereleases,
+            )
             .await?;
-    let mut link_list = Vec::with_capacity(merged_prs.len());
-    let mut title_list = Vec::with_capacity(merged_prs.len());
-    let mut merged_date_list = Vec::with_capacity(merged_prs.len());
-    for pr in merged_prs {
-        link_list.push(pr.html_url.map(|s| s.to_string()));
-        title_list.push(pr.title.map(|s| format!("**{0}**", s.trim())));
-        merged_date_list.push(pr.merged_at.map(|d| d.to_string()));
-    }
-    let mut df = df![
-        "merged date"  => merged_date_list,
+            (format_releases(&releases)?, "release")
+        }
+    };
+    println!("{repo}'s {name} report: {df}");
+    create_dir_all(&_path)?;
+
+    let csv_path = format!("{_path}/{name}.csv");
+    println!("The report has been generated at: {:?}.", csv_path);
+    let mut file = File::create(csv_path)?;
+    CsvWriter::new(&mut file).finish(&mut df)?;
+
+    Ok(())
+}
+
+// One release per row, pre-releases are told by `kind`,
+// priority, runtimes and runtime changes come from release notes like Polkadot's.
+pub fn format_releases(releases: &[Release]) -> Result<DataFrame> {
+    let mut date_list = Vec::with_capacity(releases.len());
+    let mut tag_list = Vec::with_capacity(releases.len());
+    let mut title_list = Vec::with_capacity(releases.len());
+    let mut kind_list = Vec::with_capacity(releases.len());
+    let mut priority_list = Vec::with_capacity(releases.len());
+    let mut runtimes_list = Vec::with_capacity(releases.len());
+    let mut changes_list = Vec::with_capacity(releases.len());
+    let mut link_list = Vec::with_capacity(releases.len());
+    for release in releases {
+        let notes = crate::release_notes::parse(release.body.as_deref().unwrap_or_default());
+        priority_list.push(notes.priority.map(|priority| priority.to_string()));
+        runtimes_list.push(notes.spec_versions().join(", "));
+        changes_list.push(notes.runtime_changes.join("; "));
+        date_list.push(release.published_at.map(|d| d.to_string()));
+        tag_list.push(release.tag_name.clone());
+        title_list.push(release.name.as_ref().map(|s| format!("**{0}**", s.trim())));
+        kind_list.push(match release.prerelease {
+            true => "pre-release",
+            false => "release",
+        });
+        link_list.push(release.html_url.to_string());
+    }
+    let df = df![
+        "published date" => date_list,
+        "tag" => tag_list,
         "title" => title_list,
-        "link"  => link_list,
+        "kind" => kind_list,
+        "priority" => priority_list,
+        "runtimes" => runtimes_list,
+        "runtime changes" => changes_list,
+        "link" => link_list,
     ]?;
 
-    println!("{repo}'s prs report: {df}");
+    Ok(df)
+}
+
+// One tag per row, dated by the tagged commit.
+pub fn format_tags(tags: &[Tag]) -> Result<DataFrame> {
+    let mut date_list = Vec::with_capacity(tags.len());
+    let mut tag_list = Vec::with_capacity(tags.len());
+    let mut sha_list = Vec::with_capacity(tags.len());
+    let mut link_list = Vec::with_capacity(tags.len());
+    for tag in tags {
+        date_list.push(tag.date.map(|d| d.to_string()));
+        tag_list.push(tag.name.clone());
+        sha_list.push(tag.sha.clone());
+        link_list.push(tag.html_url.clone());
+    }
+    let df = df![
+        "tagged date" => date_list,
+        "tag" => tag_list,
+        "commit" => sha_list,
+        "link" => link_list,
+    ]?;
+
+    Ok(df)
+}
+
+pub async fn generate_commit_csv_report(
+    CommitArguments {
+        arguments:
+            Arguments {
+                org,
+                repo,
+                from,
+                to,
+            },
+        branch,
+    }: &CommitArguments,
+    config: &Value,
+) -> Result<()> {
+    let _path = format!("./{repo}/{from} => {to}");
+
+    let octocrab = crate::utils::repository_client(config, org, repo)?;
+    let branches = match branch {
+        Some(branch) => vec![branch.clone()],
+        None => {
+            crate::utils::get_repository(config, org, repo)?
+                .unwrap_or_default()
+                .branches
+        }
+    };
+    if branches.is_empty() {
+        return Err(anyhow!(
+            "No branch of {org}/{repo}, give one by --branch or set `branches` in config."
+        ));
+    }
+    let (from, to) = crate::utils::parse_from_date_and_to_date(from, to)?;
+    let mut commits = vec![];
+    for branch in branches.iter() {
+        commits.append(
+            &mut crate::subcribe_commits::get_commits_by_date(
+                octocrab.clone(),
+                org,
+                repo,
+                branch,
+                from,
+                to,
+            )
+            .await?,
+        );
+    }
+    let mut df = format_commits(&commits)?;
+    println!("{repo}'s commit report: {df}");
     create_dir_all(&_path)?;
 
-    let csv_path = format!("{_path}/pr.csv");
+    let csv_path = format!("{_path}/commits.csv");
     println!("The report has been generated at: {:?}.", csv_path);
     let mut file = File::create(csv_path)?;
     CsvWriter::new(&mut file).finish(&mut df)?;
@@ -100,41 +649,901 @@ pub async fn generate_pr_csv_report(
     Ok(())
 }
 
-pub async fn generate_issue_csv_report(
-    Arguments {
+// One commit per row, with the pr it comes from.
+pub fn format_commits(commits: &[BranchCommit]) -> Result<DataFrame> {
+    let mut date_list = Vec::with_capacity(commits.len());
+    let mut branch_list = Vec::with_capacity(commits.len());
+    let mut sha_list = Vec::with_capacity(commits.len());
+    let mut author_list = Vec::with_capacity(commits.len());
+    let mut message_list = Vec::with_capacity(commits.len());
+    let mut pr_list = Vec::with_capacity(commits.len());
+    let mut link_list = Vec::with_capacity(commits.len());
+    for commit in commits {
+        date_list.push(commit.date.map(|d| d.to_string()));
+        branch_list.push(commit.branch.clone());
+        sha_list.push(commit.sha.clone());
+        author_list.push(commit.author.clone());
+        message_list.push(commit.title().to_owned());
+        pr_list.push(commit.pr.clone());
+        link_list.push(commit.html_url.clone());
+    }
+    let df = df![
+        "committed date" => date_list,
+        "branch" => branch_list,
+        "commit" => sha_list,
+        "author" => author_list,
+        "message" => message_list,
+        "pr" => pr_list,
+        "link" => link_list,
+    ]?;
+
+    Ok(df)
+}
+
+pub async fn generate_changelog(
+    ChangelogArguments {
+        org,
+        repo,
+        from_ref,
+        to_ref,
+        format,
+    }: &ChangelogArguments,
+    config: &Value,
+) -> Result<()> {
+    let _path = format!("./{repo}/{from_ref} => {to_ref}");
+
+    let octocrab = crate::utils::repository_client(config, org, repo)?;
+    let filter = crate::filter::repository_filter(config, org, repo)?;
+    let entries =
+        crate::changelog::generate_changelog(octocrab, org, repo, from_ref, to_ref, &filter)
+            .await?;
+    let mut df = format_changelog(&entries)?;
+    println!("{repo}'s changelog: {df}");
+    create_dir_all(&_path)?;
+
+    match format {
+        ChangelogFormat::Markdown => {
+            let md_path = format!("{_path}/changelog.md");
+            println!("The changelog has been generated at: {:?}.", md_path);
+            std::fs::write(
+                md_path,
+                crate::changelog::changelog_markdown(org, repo, from_ref, to_ref, &entries),
+            )?;
+        }
+        ChangelogFormat::Csv => {
+            let csv_path = format!("{_path}/changelog.csv");
+            println!("The changelog has been generated at: {:?}.", csv_path);
+            let mut file = File::create(csv_path)?;
+            CsvWriter::new(&mut file).finish(&mut df)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn generate_missing_pr_csv_report(
+    ForkArguments { fork }: &ForkArguments,
+    config: &Value,
+) -> Result<()> {
+    let db = crate::utils::db_config()?;
+    let mut failures = 0;
+    for pair in crate::divergence::get_fork_pairs(config)? {
+        if fork
+            .as_ref()
+            .map_or(false, |fork| !fork.eq_ignore_ascii_case(&pair.fork))
+        {
+            continue;
+        }
+        let (org, repo) = pair.upstream_repo()?;
+        let octocrab = crate::utils::repository_client(config, org, repo)?;
+        let divergence = match crate::divergence::compute_divergence(octocrab, &pair).await {
+            Ok(divergence) => {
+                crate::divergence::store_divergence(&db, &pair, &divergence)?;
+                divergence
+            }
+            Err(e) => {
+                failures += 1;
+                println!("{pair}: failed to compare with {}: {e}", pair.upstream);
+                continue;
+            }
+        };
+        println!(
+            "{pair} is {} commits ahead and {} behind {}:{}, {} upstream PRs missing.",
+            divergence.ahead_by,
+            divergence.behind_by,
+            pair.upstream,
+            pair.upstream_branch(),
+            divergence.missing.len()
+        );
+
+        let (_, repo) = pair.fork_repo()?;
+        let _path = format!("./{repo}/{}", pair.branch);
+        let mut df = format_missing_prs(&divergence.missing)?;
+        println!("{pair}'s missing PR report: {df}");
+        create_dir_all(&_path)?;
+
+        let csv_path = format!("{_path}/missing-prs.csv");
+        println!("The report has been generated at: {:?}.", csv_path);
+        let mut file = File::create(csv_path)?;
+        CsvWriter::new(&mut file).finish(&mut df)?;
+    }
+    if failures > 0 {
+        return Err(anyhow!("{failures} forks failed to compare."));
+    }
+
+    Ok(())
+}
+
+pub async fn generate_pin_csv_report(
+    PinArguments { history }: &PinArguments,
+    config: &Value,
+) -> Result<()> {
+    let db = crate::utils::db_config()?;
+    let mut reports = vec![];
+    let mut failures = 0;
+    match history {
+        true => {
+            for pin in crate::pins::get_pins(config)? {
+                let statuses = crate::pins::get_pin_history(&db, &pin)?;
+                reports.push((pin, statuses));
+            }
+        }
+        false => {
+            for (pin, status) in crate::pins::update_pins(config, db, true).await? {
+                match status {
+                    Ok(status) => {
+                        println!(
+                            "{pin}: pinned {} is {} commits and {} days behind, {} merged PRs in between.",
+                            &status.pin.pinned[..7.min(status.pin.pinned.len())],
+                            status.commits_behind,
+                            status.days_behind,
+                            status.missing.iter().filter(|pr| pr.number.is_some()).count()
+                        );
+                        reports.push((pin, vec![status]));
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        println!("{pin}: failed to compare with the branch: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    let name = match history {
+        true => "pins-history",
+        false => "pins",
+    };
+    for (pin, statuses) in reports {
+        if statuses.is_empty() {
+            continue;
+        }
+        let _path = format!("./{}/{}", pin.repository, pin.branch);
+        let mut df = format_pins(&statuses)?;
+        println!("{pin}'s pin report: {df}");
+        create_dir_all(&_path)?;
+
+        let csv_path = format!("{_path}/{name}.csv");
+        println!("The report has been generated at: {:?}.", csv_path);
+        let mut file = File::create(csv_path)?;
+        CsvWriter::new(&mut file).finish(&mut df)?;
+    }
+    if failures > 0 {
+        return Err(anyhow!("{failures} pins failed to compare."));
+    }
+
+    Ok(())
+}
+
+// One check of a pin per row, with numbers of merged PRs between the pinned commit and the head.
+pub fn format_pins(statuses: &[PinStatus]) -> Result<DataFrame> {
+    let mut date_list = Vec::with_capacity(statuses.len());
+    let mut repo_list = Vec::with_capacity(statuses.len());
+    let mut branch_list = Vec::with_capacity(statuses.len());
+    let mut pinned_list = Vec::with_capacity(statuses.len());
+    let mut head_list = Vec::with_capacity(statuses.len());
+    let mut commits_list = Vec::with_capacity(statuses.len());
+    let mut days_list = Vec::with_capacity(statuses.len());
+    let mut prs_list = Vec::with_capacity(statuses.len());
+    for status in statuses {
+        date_list.push(status.checked_at.to_string());
+        repo_list.push(format!(
+            "{}/{}",
+            status.pin.organization, status.pin.repository
+        ));
+        branch_list.push(status.pin.branch.clone());
+        pinned_list.push(status.pin.pinned.clone());
+        head_list.push(status.head.clone());
+        commits_list.push(status.commits_behind as u64);
+        days_list.push(status.days_behind);
+        prs_list.push(
+            status
+                .missing
+                .iter()
+                .filter_map(|pr| pr.number.map(|number| format!("#{number}")))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+    }
+    let df = df![
+        "checked date" => date_list,
+        "repository" => repo_list,
+        "branch" => branch_list,
+        "pinned" => pinned_list,
+        "head" => head_list,
+        "commits behind" => commits_list,
+        "days behind" => days_list,
+        "prs" => prs_list,
+    ]?;
+
+    Ok(df)
+}
+
+pub fn generate_cluster_csv_report(
+    ClusterArguments { repo }: &ClusterArguments,
+    config: &Value,
+) -> Result<()> {
+    let db = crate::utils::db_config()?;
+    let repositories = crate::utils::get_repositories(config)?;
+    let html_root = crate::utils::html_root(crate::utils::github_client(config)?.base_url.as_str());
+    let mut clusters = crate::links::get_merged_clusters(&db, &repositories, &html_root)?;
+    if let Some(repo) = repo {
+        clusters.retain(|members| {
+            members.iter().any(|member| {
+                let reference = &member.reference;
+                repo.eq_ignore_ascii_case(&format!(
+                    "{}/{}",
+                    reference.organization, reference.repository
+                ))
+            })
+        });
+    }
+    println!("{} clusters of linked PRs merged together.", clusters.len());
+
+    let mut df = format_clusters(&clusters)?;
+    println!("Cluster report: {df}");
+    let csv_path = "./clusters.csv";
+    println!("The report has been generated at: {:?}.", csv_path);
+    let mut file = File::create(csv_path)?;
+    CsvWriter::new(&mut file).finish(&mut df)?;
+
+    Ok(())
+}
+
+// One member per row, clusters are numbered from 1 in order of their first merged pr.
+pub fn format_clusters(clusters: &[Vec<ClusterMember>]) -> Result<DataFrame> {
+    let len = clusters.iter().map(|members| members.len()).sum();
+    let mut cluster_list = Vec::with_capacity(len);
+    let mut reference_list = Vec::with_capacity(len);
+    let mut state_list = Vec::with_capacity(len);
+    let mut title_list = Vec::with_capacity(len);
+    let mut date_list = Vec::with_capacity(len);
+    let mut link_list = Vec::with_capacity(len);
+    for (index, members) in clusters.iter().enumerate() {
+        for member in members {
+            cluster_list.push(index as u64 + 1);
+            reference_list.push(member.reference.to_string());
+            state_list.push(member.state.as_str());
+            title_list.push(member.title.clone());
+            date_list.push(member.merged_at.map(|d| d.to_string()));
+            link_list.push(member.html_url.clone());
+        }
+    }
+    let df = df![
+        "cluster" => cluster_list,
+        "reference" => reference_list,
+        "state" => state_list,
+        "title" => title_list,
+        "merged date" => date_list,
+        "link" => link_list,
+    ]?;
+
+    Ok(df)
+}
+
+pub async fn generate_migration_csv_report(
+    MigrationArguments {
+        org,
+        repo,
+        since,
+        to_ref,
+    }: &MigrationArguments,
+    config: &Value,
+) -> Result<()> {
+    let octocrab = crate::utils::repository_client(config, org, repo)?;
+    let db = crate::utils::db_config()?;
+    let migration_labels = crate::utils::get_repository(config, org, repo)?
+        .map(|repository| repository.migration_labels)
+        .unwrap_or_default();
+    let migrations = crate::migrations::migrations_since(
+        octocrab,
+        &db,
+        org,
+        repo,
+        since,
+        to_ref,
+        &migration_labels,
+    )
+    .await?;
+    println!(
+        "{org}/{repo}: {} merged PRs since {since} are likely migrations.",
+        migrations.len()
+    );
+
+    let _path = format!("./{repo}");
+    let mut df = format_migrations(&migrations)?;
+    println!("{org}/{repo}'s migration report: {df}");
+    create_dir_all(&_path)?;
+
+    // Refs like `release/v1` have slashes.
+    let csv_path = format!("{_path}/migrations-since-{}.csv", since.replace('/', "-"));
+    println!("The report has been generated at: {:?}.", csv_path);
+    let mut file = File::create(csv_path)?;
+    CsvWriter::new(&mut file).finish(&mut df)?;
+
+    Ok(())
+}
+
+// One likely migration per row in order of commits, with why it's likely.
+pub fn format_migrations(migrations: &[MigrationCheck]) -> Result<DataFrame> {
+    let mut date_list = Vec::with_capacity(migrations.len());
+    let mut number_list = Vec::with_capacity(migrations.len());
+    let mut title_list = Vec::with_capacity(migrations.len());
+    let mut author_list = Vec::with_capacity(migrations.len());
+    let mut signals_list = Vec::with_capacity(migrations.len());
+    let mut link_list = Vec::with_capacity(migrations.len());
+    for check in migrations {
+        date_list.push(check.merged_at.map(|d| d.to_string()));
+        number_list.push(check.number);
+        title_list.push(check.title.clone());
+        author_list.push(check.author.clone());
+        signals_list.push(
+            check
+                .signals
+                .iter()
+                .map(|signal| signal.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        link_list.push(check.html_url.clone());
+    }
+    let df = df![
+        "merged date" => date_list,
+        "pr" => number_list,
+        "title" => title_list,
+        "author" => author_list,
+        "signals" => signals_list,
+        "link" => link_list,
+    ]?;
+
+    Ok(df)
+}
+
+pub async fn export_patches(
+    PatchArguments {
         org,
         repo,
         from,
         to,
-        ..
-    }: &Arguments,
+        pr,
+        format,
+        output,
+        force,
+    }: &PatchArguments,
+    config: &Value,
 ) -> Result<()> {
-    let _path = format!("./{repo}/{from} => {to}");
+    let octocrab = crate::utils::repository_client(config, org, repo)?;
+    let db = crate::utils::db_config()?;
+    let prs = match (from, to) {
+        (Some(from), Some(to)) => {
+            let (from, to) = crate::utils::parse_from_date_and_to_date(from, to)?;
+            // Patches are applied in order, an incomplete scan fails rather than leaving a gap.
+            crate::subcribe_prs::get_all_merged_prs_by_date(
+                octocrab.clone(),
+                org,
+                repo,
+                params::State::Closed,
+                from,
+                to,
+            )
+            .await?
+        }
+        _ => {
+            let mut prs = vec![];
+            for number in pr {
+                let pr =
+                    crate::subcribe_prs::get_pr_by_id(octocrab.clone(), org, repo, *number).await?;
+                if pr.merged_at.is_none() {
+                    println!("{org}/{repo}: pr #{number} is not merged, skipped.");
+                    continue;
+                }
+                prs.push(pr);
+            }
+            prs
+        }
+    };
 
-    let octocrab = octocrab::instance();
-    let (from, to) = crate::utils::parse_from_date_and_to_date(from, to)?;
-    let new_issues =
-        crate::subcribe_issues::get_open_issues_by_date(octocrab, org, repo, from, to).await?;
-    let mut link_list = Vec::with_capacity(new_issues.len());
-    let mut title_list = Vec::with_capacity(new_issues.len());
-    let mut date_list = Vec::with_capacity(new_issues.len());
-    for issue in new_issues {
+    let options = ExportOptions {
+        format: *format,
+        dir: output,
+        force: *force,
+    };
+    let exported = crate::patches::export_patches(octocrab, &db, org, repo, prs, &options).await?;
+    for patch in exported.iter() {
+        println!("{org}/{repo}: pr #{} {}", patch.number, patch.file);
+    }
+    let dir = output.join(org).join(repo);
+    println!(
+        "{} patches have been exported to {:?}, apply them in order by {:?} or {:?}.",
+        exported.len(),
+        dir,
+        dir.join(crate::patches::APPLY_SCRIPT),
+        dir.join(crate::patches::CHERRY_PICK_SCRIPT)
+    );
+
+    Ok(())
+}
+
+// One upstream PR per row, oldest first, commits pushed without PRs have no number.
+pub fn format_missing_prs(missing: &[MissingPr]) -> Result<DataFrame> {
+    let mut date_list = Vec::with_capacity(missing.len());
+    let mut number_list = Vec::with_capacity(missing.len());
+    let mut title_list = Vec::with_capacity(missing.len());
+    let mut author_list = Vec::with_capacity(missing.len());
+    let mut link_list = Vec::with_capacity(missing.len());
+    for pr in missing {
+        date_list.push(pr.date.map(|d| d.to_string()));
+        number_list.push(pr.number);
+        title_list.push(pr.title.clone());
+        author_list.push(pr.author.clone());
+        link_list.push(pr.html_url.clone());
+    }
+    let df = df![
+        "committed date" => date_list,
+        "pr" => number_list,
+        "title" => title_list,
+        "author" => author_list,
+        "link" => link_list,
+    ]?;
+
+    Ok(df)
+}
+
+// One PR per row in order of categories, commits pushed without PRs have no number.
+pub fn format_changelog(entries: &[ChangelogEntry]) -> Result<DataFrame> {
+    let mut category_list = Vec::with_capacity(entries.len());
+    let mut number_list = Vec::with_capacity(entries.len());
+    let mut title_list = Vec::with_capacity(entries.len());
+    let mut author_list = Vec::with_capacity(entries.len());
+    let mut labels_list = Vec::with_capacity(entries.len());
+    let mut date_list = Vec::with_capacity(entries.len());
+    let mut link_list = Vec::with_capacity(entries.len());
+    for entry in entries {
+        category_list.push(entry.category().to_owned());
+        number_list.push(entry.number);
+        title_list.push(entry.title.clone());
+        author_list.push(entry.author.clone());
+        labels_list.push(entry.labels.join(", "));
+        date_list.push(entry.merged_at.map(|d| d.to_string()));
+        link_list.push(entry.html_url.clone());
+    }
+    let df = df![
+        "category" => category_list,
+        "pr" => number_list,
+        "title" => title_list,
+        "author" => author_list,
+        "labels" => labels_list,
+        "merged date" => date_list,
+        "link" => link_list,
+    ]?;
+
+    Ok(df)
+}
+
+// One issue per row, with the date of its status and its category.
+pub fn format_issues(
+    issues: &[(Option<DateTime<Utc>>, &Issue)],
+    date_column: &str,
+    filter: &Filter,
+) -> Result<DataFrame> {
+    let mut link_list = Vec::with_capacity(issues.len());
+    let mut title_list = Vec::with_capacity(issues.len());
+    let mut date_list = Vec::with_capacity(issues.len());
+    let mut category_list = Vec::with_capacity(issues.len());
+    for (date, issue) in issues {
         link_list.push(issue.html_url.to_string());
         title_list.push(format!("**{0}**", issue.title));
-        date_list.push(issue.created_at.to_string());
+        date_list.push(date.map(|d| d.to_string()));
+        category_list.push(filter.issue_category(issue));
     }
-    let mut df = df![
-        "created date"  => date_list,
+    let df = df![
+        date_column  => date_list,
         "title" => title_list,
+        "category" => category_list,
         "link"  => link_list,
     ]?;
-    println!("{repo}'s issues report: {df}");
-    create_dir_all(&_path)?;
 
-    let csv_path = format!("{_path}/issue.csv");
-    println!("The report has been generated at: {:?}.", csv_path);
-    let mut file = File::create(csv_path)?;
-    CsvWriter::new(&mut file).finish(&mut df)?;
+    Ok(df)
+}
+
+// One closed issue per row, with why it's closed and which pr closed it.
+pub fn format_closed_issues(closed_issues: &[ClosedIssue], filter: &Filter) -> Result<DataFrame> {
+    let issues = closed_issues
+        .iter()
+        .map(|closed| (closed.issue.closed_at, &closed.issue))
+        .collect::<Vec<_>>();
+    let reason_list = closed_issues
+        .iter()
+        .map(|closed| closed.state_reason.clone())
+        .collect::<Vec<_>>();
+    let closing_pr_list = closed_issues
+        .iter()
+        .map(|closed| closed.closing_pr.clone())
+        .collect::<Vec<_>>();
+    let mut df = format_issues(&issues, "closed date", filter)?;
+    // Before the link.
+    df.insert_at_idx(3, Series::new("reason", reason_list))?;
+    df.insert_at_idx(4, Series::new("closing pr", closing_pr_list))?;
+
+    Ok(df)
+}
+
+pub fn discover(
+    DiscoverArguments {
+        lock,
+        manifest,
+        write,
+    }: &DiscoverArguments,
+) -> Result<()> {
+    let sources = crate::discover::discover_sources(lock, manifest)?;
+    for source in sources.iter() {
+        println!(
+            "{}/{} {} pinned to {}: {} crates",
+            source.organization,
+            source.repository,
+            source.reference,
+            source.pinned.as_deref().unwrap_or("nothing"),
+            source.crates.len()
+        );
+    }
+
+    let repositories = crate::discover::propose_repositories(&sources);
+    match write {
+        true => {
+            let config = std::fs::read_to_string(crate::utils::CONFIG_PATH)?;
+            let config = crate::discover::update_config(&config, &repositories)?;
+            std::fs::write(crate::utils::CONFIG_PATH, config)?;
+            println!(
+                "{} subscriptions have been written into {:?}.",
+                repositories.len(),
+                crate::utils::CONFIG_PATH
+            );
+        }
+        false => {
+            println!("Proposed subscriptions, run with --write to write them into config:");
+            for repo in repositories.iter() {
+                print!("\n{}", crate::discover::repository_section(repo));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn relevant(crates: Option<&Vec<String>>) -> String {
+    crates
+        .map(|crates| format!(" (relevant: {})", crates.join(", ")))
+        .unwrap_or_default()
+}
+
+// Like ` (likely migration: path frame/staking/src/migrations.rs)`, or nothing.
+fn likely_migration(signals: Option<&Vec<crate::migrations::Signal>>) -> String {
+    signals
+        .map(|signals| {
+            let signals = signals.iter().map(|signal| signal.to_string());
+            format!(
+                " (likely migration: {})",
+                signals.collect::<Vec<_>>().join(", ")
+            )
+        })
+        .unwrap_or_default()
+}
+
+// Like ` (companions: paritytech/polkadot#6320)`, or nothing if it has no companion.
+fn companions(reference: &Reference, links: Option<&Vec<Link>>) -> String {
+    let companions = links
+        .map(|links| crate::links::companions(reference, links))
+        .unwrap_or_default();
+    match companions.is_empty() {
+        true => String::new(),
+        false => format!(
+            " (companions: {})",
+            companions
+                .iter()
+                .map(|companion| companion.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+// Sync all subscribed repositories into sled once.
+pub async fn sync_repositories(
+    SyncArguments { mode, concurrency }: &SyncArguments,
+    config: &Value,
+) -> Result<()> {
+    let mode = match mode {
+        Some(mode) => *mode,
+        None => crate::utils::get_fetch_mode(config)?,
+    };
+    let repositories = crate::utils::get_repositories(config)?;
+    let db = crate::utils::db_config()?;
+
+    let concurrency = concurrency.unwrap_or_else(|| crate::utils::get_concurrency(config));
+    let results =
+        crate::sync::sync_repositories(config, db, &repositories, concurrency, mode).await?;
+    let mut failures = 0;
+    for result in results {
+        let (org, repo) = (&result.organization, &result.repository);
+        match result.issues {
+            Ok(events) => {
+                for event in events {
+                    let issue = event.issue();
+                    println!(
+                        "{org}/{repo}: issue #{}{} {}: {}",
+                        issue.number,
+                        crate::filter::category_tag(result.filter.issue_category(issue)),
+                        event.summary(),
+                        issue.title
+                    );
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("{org}/{repo}: failed to sync issues: {e}");
+            }
+        }
+        match result.prs {
+            Ok(events) => {
+                for event in events {
+                    let pr = event.pr();
+                    println!(
+                        "{org}/{repo}: pr #{}{}{}{}{} {}: {}",
+                        pr.number,
+                        crate::filter::category_tag(result.filter.pr_category(pr)),
+                        relevant(result.relevance.get(&pr.number)),
+                        companions(
+                            &Reference::new(org, repo, pr.number),
+                            result.links.get(&pr.number)
+                        ),
+                        likely_migration(result.migrations.get(&pr.number)),
+                        event.summary(),
+                        pr.title.as_deref().unwrap_or_default()
+                    );
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("{org}/{repo}: failed to sync prs: {e}");
+            }
+        }
+        match result.releases {
+            Ok(events) => {
+                for event in events {
+                    println!(
+                        "{org}/{repo}: {} {}: {}",
+                        event.kind(),
+                        event.tag_name(),
+                        event.name()
+                    );
+                    if let Some(notes) = event.notes() {
+                        if let Some(priority) = notes.priority {
+                            println!("    upgrade priority: {priority}");
+                        }
+                        for change in notes.runtime_changes.iter() {
+                            println!("    runtime change: {change}");
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("{org}/{repo}: failed to sync releases: {e}");
+            }
+        }
+        match result.commits {
+            Ok(commits) => {
+                for commit in commits {
+                    println!(
+                        "{org}/{repo}: commit {} on {} by {}: {}{}",
+                        commit.short_sha(),
+                        commit.branch,
+                        commit.author,
+                        commit.title(),
+                        commit
+                            .pr
+                            .as_ref()
+                            .map(|pr| format!(" ({pr})"))
+                            .unwrap_or_default()
+                    );
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("{org}/{repo}: failed to sync commits: {e}");
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(anyhow!("{failures} resources failed to sync."));
+    }
 
     Ok(())
 }
+
+// Add or remove a watched item, or relay updates of all watched items.
+pub async fn watch(WatchArguments { add, remove }: &WatchArguments, config: &Value) -> Result<()> {
+    let db = crate::utils::db_config()?;
+    if let Some(item) = add {
+        match crate::watchlist::watch(&db, item)? {
+            true => println!("Watching {item}."),
+            false => println!("{item} is watched already."),
+        }
+        return Ok(());
+    }
+    if let Some(item) = remove {
+        match crate::watchlist::unwatch(&db, item)? {
+            true => println!("Unwatched {item}."),
+            false => println!("{item} isn't watched, or it's in config."),
+        }
+        return Ok(());
+    }
+
+    let mut failures = 0;
+    for (item, updates) in crate::watchlist::poll_watchlist(config, db).await? {
+        match updates {
+            Ok(updates) => {
+                for update in updates {
+                    println!("{update}: {}", update.url);
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("{item}: failed to poll: {e}");
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(anyhow!("{failures} watched items failed to poll."));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::MockGithub;
+
+    #[tokio::test]
+    async fn closed_prs_report_should_have_closed_date() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-26").unwrap();
+        let prs = crate::subcribe_prs::get_closed_prs_by_date(
+            mock.client(),
+            "paritytech",
+            "substrate",
+            from,
+            to,
+        )
+        .await
+        .unwrap();
+        let df = format_prs(
+            &prs,
+            "closed date",
+            |pr| pr.closed_at,
+            &Filter::default(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            df.get_column_names(),
+            vec![
+                "closed date",
+                "title",
+                "category",
+                "touched paths",
+                "relevant crates",
+                "link"
+            ]
+        );
+        assert_eq!(
+            df.column("closed date").unwrap().utf8().unwrap().get(0),
+            Some("2022-11-26 20:00:00 UTC")
+        );
+    }
+
+    #[tokio::test]
+    async fn closed_issues_report_should_have_reason_and_closing_pr() {
+        let mock = MockGithub::start(&["github-issue-report", "github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-26").unwrap();
+        let closed_issues = crate::subcribe_issues::get_closed_issues_by_date(
+            mock.client(),
+            "Manta-Network",
+            "docs",
+            from,
+            to,
+        )
+        .await
+        .unwrap();
+        let df = format_closed_issues(&closed_issues, &Filter::default()).unwrap();
+        assert_eq!(
+            df.get_column_names(),
+            vec![
+                "closed date",
+                "title",
+                "category",
+                "reason",
+                "closing pr",
+                "link"
+            ]
+        );
+        assert_eq!(
+            df.column("closing pr").unwrap().utf8().unwrap().get(0),
+            Some("https://github.com/Manta-Network/docs/pull/123")
+        );
+        assert_eq!(
+            df.column("reason").unwrap().utf8().unwrap().get(1),
+            Some("not_planned")
+        );
+    }
+
+    #[tokio::test]
+    async fn releases_report_should_tell_pre_releases() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-20", "2022-11-30").unwrap();
+        let releases = crate::subcribe_releases::get_releases_by_date(
+            mock.client(),
+            "paritytech",
+            "polkadot",
+            from,
+            to,
+            true,
+        )
+        .await
+        .unwrap();
+        let df = format_releases(&releases).unwrap();
+        assert_eq!(
+            df.get_column_names(),
+            vec![
+                "published date",
+                "tag",
+                "title",
+                "kind",
+                "priority",
+                "runtimes",
+                "runtime changes",
+                "link"
+            ]
+        );
+        assert_eq!(
+            df.column("priority").unwrap().utf8().unwrap().get(0),
+            Some("High")
+        );
+        assert_eq!(
+            df.column("runtimes").unwrap().utf8().unwrap().get(1),
+            Some("westend-9330, kusama-9330, polkadot-9330")
+        );
+        assert_eq!(
+            df.column("kind").unwrap().utf8().unwrap().get(0),
+            Some("pre-release")
+        );
+        assert_eq!(
+            df.column("tag").unwrap().utf8().unwrap().get(1),
+            Some("v0.9.33")
+        );
+    }
+}
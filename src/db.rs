@@ -94,10 +94,13 @@ pub async fn insert_batch_prs(
 mod tests {
     use super::*;
 
+    use crate::mock_github::{temporary_db, MockGithub};
+
     #[tokio::test]
     async fn get_all_archived_issues_should_work() {
-        let db = crate::utils::db_config().unwrap();
-        let octo = octocrab::instance();
+        let mock = MockGithub::start(&["github"]).await;
+        let db = temporary_db();
+        let octo = mock.client();
         let (org, repo) = ("Manta-Network", "docs");
         let issues = crate::subcribe_issues::get_all_open_issues(octo.clone(), org, repo)
             .await
@@ -112,16 +115,22 @@ mod tests {
         let all_issues = get_all_archived_issues(db.clone(), key_bytes.as_bytes())
             .await
             .unwrap();
-        for issue in all_issues {
-            dbg!(issue.html_url.to_string(), issue.title, issue.body);
-        }
+        // sled sorts keys as bytes, and the pr #117 has been filtered.
+        assert_eq!(
+            all_issues.iter().map(|i| i.number).collect::<Vec<_>>(),
+            vec![110, 118, 120]
+        );
 
         let key_bytes = format!("{org}#{repo}#");
+        let all_issues = get_all_archived_issues(db.clone(), key_bytes.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(all_issues.len(), 3);
+
+        let key_bytes = format!("{org}#{repo}#issues#closed");
         let all_issues = get_all_archived_issues(db, key_bytes.as_bytes())
             .await
             .unwrap();
-        for issue in all_issues {
-            dbg!(issue.html_url.to_string(), issue.title, issue.body);
-        }
+        assert!(all_issues.is_empty());
     }
 }
@@ -0,0 +1,201 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compare the archived and the fresh copy of an issue or a pr field by field.
+
+use octocrab::models::{issues, pulls};
+use std::fmt;
+
+// A field of an issue or a pr changed since the last sync.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldChange {
+    TitleEdited {
+        from: String,
+        to: String,
+    },
+    LabelsAdded(Vec<String>),
+    LabelsRemoved(Vec<String>),
+    AssigneesChanged {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    // None means the milestone is cleared.
+    MilestoneSet(Option<String>),
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TitleEdited { from, .. } => write!(f, "title edited from \"{from}\""),
+            Self::LabelsAdded(labels) => write!(f, "labels added: {}", labels.join(", ")),
+            Self::LabelsRemoved(labels) => write!(f, "labels removed: {}", labels.join(", ")),
+            Self::AssigneesChanged { added, removed } => {
+                let changes = added
+                    .iter()
+                    .map(|a| format!("+{a}"))
+                    .chain(removed.iter().map(|r| format!("-{r}")))
+                    .collect::<Vec<_>>();
+                write!(f, "assignees changed: {}", changes.join(", "))
+            }
+            Self::MilestoneSet(Some(milestone)) => write!(f, "milestone set to {milestone}"),
+            Self::MilestoneSet(None) => write!(f, "milestone cleared"),
+        }
+    }
+}
+
+// Fields worth telling, shared by issues and prs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Fields {
+    title: String,
+    labels: Vec<String>,
+    assignees: Vec<String>,
+    milestone: Option<String>,
+}
+
+impl From<&issues::Issue> for Fields {
+    fn from(issue: &issues::Issue) -> Self {
+        Self {
+            title: issue.title.clone(),
+            labels: issue.labels.iter().map(|l| l.name.clone()).collect(),
+            assignees: issue.assignees.iter().map(|a| a.login.clone()).collect(),
+            milestone: issue.milestone.as_ref().map(|m| m.title.clone()),
+        }
+    }
+}
+
+impl From<&pulls::PullRequest> for Fields {
+    fn from(pr: &pulls::PullRequest) -> Self {
+        Self {
+            title: pr.title.clone().unwrap_or_default(),
+            labels: pr.labels.iter().flatten().map(|l| l.name.clone()).collect(),
+            assignees: pr
+                .assignees
+                .iter()
+                .flatten()
+                .map(|a| a.login.clone())
+                .collect(),
+            milestone: pr.milestone.as_ref().map(|m| m.title.clone()),
+        }
+    }
+}
+
+// Items of `new` not in `old`, in the order of `new`.
+fn added(old: &[String], new: &[String]) -> Vec<String> {
+    new.iter().filter(|n| !old.contains(n)).cloned().collect()
+}
+
+fn diff_fields(old: Fields, new: Fields) -> Vec<FieldChange> {
+    let mut changes = vec![];
+    if old.title != new.title {
+        changes.push(FieldChange::TitleEdited {
+            from: old.title,
+            to: new.title,
+        });
+    }
+    let (labels_added, labels_removed) = (
+        added(&old.labels, &new.labels),
+        added(&new.labels, &old.labels),
+    );
+    if !labels_added.is_empty() {
+        changes.push(FieldChange::LabelsAdded(labels_added));
+    }
+    if !labels_removed.is_empty() {
+        changes.push(FieldChange::LabelsRemoved(labels_removed));
+    }
+    let (assignees_added, assignees_removed) = (
+        added(&old.assignees, &new.assignees),
+        added(&new.assignees, &old.assignees),
+    );
+    if !assignees_added.is_empty() || !assignees_removed.is_empty() {
+        changes.push(FieldChange::AssigneesChanged {
+            added: assignees_added,
+            removed: assignees_removed,
+        });
+    }
+    if old.milestone != new.milestone {
+        changes.push(FieldChange::MilestoneSet(new.milestone));
+    }
+
+    changes
+}
+
+// updated_at, comment count and the like are ignored.
+pub fn issue_changes(old: &issues::Issue, new: &issues::Issue) -> Vec<FieldChange> {
+    diff_fields(old.into(), new.into())
+}
+
+pub fn pr_changes(old: &pulls::PullRequest, new: &pulls::PullRequest) -> Vec<FieldChange> {
+    diff_fields(old.into(), new.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(title: &str, labels: &[&str], assignees: &[&str], milestone: Option<&str>) -> Fields {
+        Fields {
+            title: title.to_owned(),
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            assignees: assignees.iter().map(|a| a.to_string()).collect(),
+            milestone: milestone.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn diff_fields_should_work() {
+        let old = fields(
+            "Fix ci",
+            &["A0-pleasereview", "B0-silent"],
+            &["bkchr"],
+            None,
+        );
+        assert!(diff_fields(old.clone(), old.clone()).is_empty());
+
+        let new = fields(
+            "Fix ci on macos",
+            &["B0-silent", "C1-low"],
+            &["ggwpez"],
+            Some("v0.9.34"),
+        );
+        let changes = diff_fields(old, new);
+        assert_eq!(
+            changes,
+            vec![
+                FieldChange::TitleEdited {
+                    from: "Fix ci".to_owned(),
+                    to: "Fix ci on macos".to_owned()
+                },
+                FieldChange::LabelsAdded(vec!["C1-low".to_owned()]),
+                FieldChange::LabelsRemoved(vec!["A0-pleasereview".to_owned()]),
+                FieldChange::AssigneesChanged {
+                    added: vec!["ggwpez".to_owned()],
+                    removed: vec!["bkchr".to_owned()]
+                },
+                FieldChange::MilestoneSet(Some("v0.9.34".to_owned())),
+            ]
+        );
+        assert_eq!(
+            changes.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "title edited from \"Fix ci\"",
+                "labels added: C1-low",
+                "labels removed: A0-pleasereview",
+                "assignees changed: +ggwpez, -bkchr",
+                "milestone set to v0.9.34",
+            ]
+        );
+    }
+}
@@ -14,12 +14,30 @@
 // You should have received a copy of the GNU General Public License
 // along with Manta.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::types::{Issue, LatestRelease, PullRequest, Repository};
-use crate::utils::{get_discord_token, get_repositories, get_update_frequence};
-use crate::{subcribe_issues, subcribe_prs, subcribe_releases};
+use crate::divergence::{Divergence, ForkPair};
+use crate::links::{Link, Reference};
+use crate::migrations::Signal;
+use crate::pins::{Pin, PinStatus};
+use crate::subcribe_commits::BranchCommit;
+use crate::subcribe_issues::IssueEvent;
+use crate::subcribe_prs::PrEvent;
+use crate::subcribe_releases::ReleaseEvent;
+use crate::utils::{
+    get_discord_token, get_repositories, get_update_frequence, FetchMode, Repository,
+};
+use crate::watchlist::{WatchUpdate, WatchedItem};
 use serenity::{
     async_trait,
-    model::{channel::Message, gateway::Ready},
+    model::{
+        application::{
+            command::{Command, CommandOptionType},
+            interaction::{
+                application_command::CommandDataOptionValue, Interaction, InteractionResponseType,
+            },
+        },
+        channel::Message,
+        gateway::Ready,
+    },
     prelude::*,
     utils::MessageBuilder,
 };
@@ -31,14 +49,28 @@ pub struct BotHandler {
     frequence: Duration,
     db: Arc<sled::Db>,
     repositories: Vec<Repository>,
+    // Clients of repositories are built from it.
+    config: Value,
+    concurrency: usize,
+    mode: FetchMode,
 }
 
 impl BotHandler {
-    pub fn new(secs: u64, db: Arc<sled::Db>, repos: Vec<Repository>) -> Self {
+    pub fn new(
+        secs: u64,
+        db: Arc<sled::Db>,
+        repos: Vec<Repository>,
+        config: Value,
+        concurrency: usize,
+        mode: FetchMode,
+    ) -> Self {
         Self {
             frequence: Duration::from_secs(secs),
             db,
             repositories: repos,
+            config,
+            concurrency,
+            mode,
         }
     }
 }
@@ -57,68 +89,247 @@ impl EventHandler for BotHandler {
         // When to query upstream changes.
         sleep(self.frequence).await;
 
-        for repo in self.repositories.iter() {
-            // Query issues first.
-            if let Ok(new_issues) = subcribe_issues::get_new_issues(
-                self.db.clone(),
-                &repo.organization,
-                &repo.repository,
-            )
-            .await
-            {
-                for issue in new_issues.iter() {
-                    handle_issue_message(&repo.repository, Some(issue), &msg, &context).await;
+        // Sync all repositories at the same time, then send messages repository by repository.
+        let results = match crate::sync::sync_repositories(
+            &self.config,
+            self.db.clone(),
+            &self.repositories,
+            self.concurrency,
+            self.mode,
+        )
+        .await
+        {
+            Ok(results) => results,
+            Err(why) => {
+                println!("Error syncing repositories: {:?}", why);
+                return;
+            }
+        };
+
+        for result in results.iter() {
+            let repo = &result.repository;
+            // Closed and reopened issues first.
+            match result.issues.as_ref() {
+                Ok(events) => {
+                    for event in events.iter() {
+                        let category = result.filter.issue_category(event.issue());
+                        handle_issue_message(repo, Some((event, category)), &msg, &context).await;
+                    }
                 }
-            } else {
-                handle_issue_message(&repo.repository, None, &msg, &context).await;
+                Err(_) => handle_issue_message(repo, None, &msg, &context).await,
             }
 
-            // Query PRs then.
-            if let Ok(new_prs) = subcribe_prs::get_new_pull_requests(
-                self.db.clone(),
-                &repo.organization,
-                &repo.repository,
-            )
-            .await
-            {
-                for pr in new_prs.iter() {
-                    handle_pr_message(&repo.repository, Some(pr), &msg, &context).await;
+            // What happened to PRs then.
+            match result.prs.as_ref() {
+                Ok(events) => {
+                    for event in events.iter() {
+                        let category = result.filter.pr_category(event.pr());
+                        let number = event.pr().number;
+                        let crates = result.relevance.get(&number);
+                        let linked = result.links.get(&number).map(|links| {
+                            links_message(
+                                &Reference::new(&result.organization, repo, number),
+                                links,
+                            )
+                        });
+                        let migration = result.migrations.get(&number);
+                        handle_pr_message(
+                            repo,
+                            Some((event, category)),
+                            crates,
+                            linked,
+                            migration,
+                            &msg,
+                            &context,
+                        )
+                        .await;
+                    }
+                }
+                Err(_) => handle_pr_message(repo, None, None, None, None, &msg, &context).await,
+            }
+
+            // New releases or tags then, nothing if `query-release` is false.
+            match result.releases.as_ref() {
+                Ok(events) => {
+                    for event in events.iter() {
+                        handle_release_message(repo, Some(event), &msg, &context).await;
+                    }
+                }
+                Err(_) => handle_release_message(repo, None, &msg, &context).await,
+            }
+
+            // New commits on the subscribed branches, nothing if `branches` is empty.
+            match result.commits.as_ref() {
+                Ok(commits) => {
+                    for commit in commits.iter() {
+                        handle_commit_message(repo, Some(commit), &msg, &context).await;
+                    }
+                }
+                Err(_) => handle_commit_message(repo, None, &msg, &context).await,
+            }
+        }
+
+        // Updates of watched issues and PRs at last.
+        match crate::watchlist::poll_watchlist(&self.config, self.db.clone()).await {
+            Ok(results) => {
+                for (item, updates) in results.iter() {
+                    match updates {
+                        Ok(updates) => {
+                            for update in updates.iter() {
+                                handle_watch_message(item, Some(update), &msg, &context).await;
+                            }
+                        }
+                        Err(_) => handle_watch_message(item, None, &msg, &context).await,
+                    }
+                }
+            }
+            Err(why) => println!("Error polling watchlist: {:?}", why),
+        }
+
+        // Divergence of forks from upstream, once in `summary-frequence`.
+        match crate::divergence::update_divergences(&self.config, self.db.clone(), false).await {
+            Ok(results) => {
+                for (pair, divergence) in results.iter() {
+                    handle_divergence_message(pair, divergence.as_ref().ok(), &msg, &context).await;
                 }
-            } else {
-                handle_pr_message(&repo.repository, None, &msg, &context).await;
             }
+            Err(why) => println!("Error comparing forks: {:?}", why),
+        }
 
-            // Query latest release then.
-            if let Some(latest_release) =
-                subcribe_releases::get_latest_release(&repo.organization, &repo.repository).await
-            {
-                let latest_release = LatestRelease::from(latest_release);
-                handle_release_message(&repo.repository, Some(&latest_release), &msg, &context)
-                    .await;
-            } else {
-                handle_release_message(&repo.repository, None, &msg, &context).await;
+        // How far pinned commits are behind their branches, once in `summary-frequence`.
+        match crate::pins::update_pins(&self.config, self.db.clone(), false).await {
+            Ok(results) => {
+                for (pin, status) in results.iter() {
+                    handle_pin_message(pin, status.as_ref().ok(), &msg, &context).await;
+                }
             }
+            Err(why) => println!("Error checking pins: {:?}", why),
         }
     }
 
-    async fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, context: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+
+        let commands = Command::set_global_application_commands(&context.http, |commands| {
+            commands
+                .create_application_command(|command| {
+                    command
+                        .name("watch")
+                        .description("Relay every update of an issue or a PR")
+                        .create_option(|option| {
+                            option
+                                .name("item")
+                                .description("Like paritytech/substrate#12345")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("unwatch")
+                        .description("Stop relaying updates of an issue or a PR")
+                        .create_option(|option| {
+                            option
+                                .name("item")
+                                .description("Like paritytech/substrate#12345")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("watchlist")
+                        .description("List watched issues and PRs")
+                })
+        })
+        .await;
+        if let Err(why) = commands {
+            println!("Error creating slash commands: {:?}", why);
+        }
+    }
+
+    async fn interaction_create(&self, context: Context, interaction: Interaction) {
+        if let Interaction::ApplicationCommand(command) = interaction {
+            let item = command
+                .data
+                .options
+                .first()
+                .and_then(|option| option.resolved.as_ref())
+                .and_then(|value| match value {
+                    CommandDataOptionValue::String(item) => Some(item.as_str()),
+                    _ => None,
+                });
+            let content = handle_watch_command(&command.data.name, item, &self.config, &self.db);
+
+            let response = command
+                .create_interaction_response(&context.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| message.content(content))
+                })
+                .await;
+            if let Err(why) = response {
+                println!("Error responding to slash command: {:?}", why);
+            }
+        }
     }
 }
 
-async fn handle_issue_message(repo: &str, issue: Option<&Issue>, msg: &Message, context: &Context) {
-    if let Some(issue) = issue {
+// Reply of slash commands `/watch`, `/unwatch` and `/watchlist`.
+fn handle_watch_command(name: &str, item: Option<&str>, config: &Value, db: &sled::Db) -> String {
+    let item = item.map(|item| item.parse::<WatchedItem>());
+    let reply = match (name, item) {
+        ("watchlist", _) => {
+            crate::watchlist::get_watchlist(config, db).map(|items| match items.is_empty() {
+                true => "Nothing is watched.".to_owned(),
+                false => items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            })
+        }
+        (_, Some(Err(e))) => Err(e.into()),
+        ("watch", Some(Ok(item))) => crate::watchlist::watch(db, &item).map(|new| match new {
+            true => format!("Watching {item}."),
+            false => format!("{item} is watched already."),
+        }),
+        ("unwatch", Some(Ok(item))) => {
+            crate::watchlist::unwatch(db, &item).map(|removed| match removed {
+                true => format!("Unwatched {item}."),
+                false => format!("{item} isn't watched, or it's in config."),
+            })
+        }
+        _ => Ok(format!("Unknown command {name}.")),
+    };
+
+    reply.unwrap_or_else(|e| e.to_string())
+}
+
+async fn handle_issue_message(
+    repo: &str,
+    event: Option<(&IssueEvent, Option<String>)>,
+    msg: &Message,
+    context: &Context,
+) {
+    if let Some((event, category)) = event {
         /*
             The example of message format:
-            **Substrate Issue**: issue's title:
+            **Substrate Issue** [Runtime] (closed as completed by pr's url): issue's title:
             issue's url
         */
-        let response = MessageBuilder::new()
+        let issue = event.issue();
+        let mut builder = MessageBuilder::new();
+        let response = builder
             .push_bold_safe(repo)
-            .push(" **Issue**: ")
+            .push(" **Issue**")
+            .push_safe(crate::filter::category_tag(category))
+            .push(" (")
+            .push(event.summary())
+            .push("): ")
             .push(&issue.title)
             .push(" ")
-            .push(issue.url.as_str())
+            .push(issue.html_url.as_str())
             .build();
 
         if let Err(why) = msg.channel_id.say(&context.http, &response).await {
@@ -126,7 +337,7 @@ async fn handle_issue_message(repo: &str, issue: Option<&Issue>, msg: &Message,
         }
     } else {
         let response = MessageBuilder::new()
-            .push("Failed to query new issue From ")
+            .push("Failed to query issues From ")
             .push_bold_safe(repo)
             .build();
 
@@ -136,27 +347,60 @@ async fn handle_issue_message(repo: &str, issue: Option<&Issue>, msg: &Message,
     }
 }
 
-async fn handle_pr_message(repo: &str, pr: Option<&PullRequest>, msg: &Message, context: &Context) {
-    if let Some(pr) = pr {
+async fn handle_pr_message(
+    repo: &str,
+    event: Option<(&PrEvent, Option<String>)>,
+    // Crates we depend on it touches.
+    crates: Option<&Vec<String>>,
+    // Companions and what it closes, from `links_message`.
+    linked: Option<String>,
+    // Why a merged PR is likely a runtime migration.
+    migration: Option<&Vec<Signal>>,
+    msg: &Message,
+    context: &Context,
+) {
+    if let Some((event, category)) = event {
         /*
             The example of message format:
-            **Substrate PR**: pr's title:
+            **Substrate PR** [Runtime, Breaking] (ready for review): pr's title:
             pr's url
+            Relevant: pallet-balances, sc-consensus-aura
+            Companions: paritytech/polkadot#6320
+            Closes: paritytech/substrate#12690
+            Likely migration: label E0-runtime_migration, code OnRuntimeUpgrade
         */
-        let response = MessageBuilder::new()
+        let pr = event.pr();
+        let mut builder = MessageBuilder::new();
+        builder
             .push_bold_safe(repo)
-            .push(" **PR**: ")
+            .push(" **PR**")
+            .push_safe(crate::filter::category_tag(category))
+            .push(" (")
+            .push(event.summary())
+            .push("): ")
             .push(pr.title.as_deref().unwrap_or("No title"))
             .push(" ")
-            .push(pr.url.as_ref().map(|u| u.as_str()).unwrap_or("No url"))
-            .build();
+            .push(pr.html_url.as_ref().map(|u| u.as_str()).unwrap_or("No url"));
+        if let Some(crates) = crates {
+            builder.push("\nRelevant: ").push_safe(crates.join(", "));
+        }
+        if let Some(linked) = linked {
+            builder.push(linked);
+        }
+        if let Some(signals) = migration {
+            let signals = signals.iter().map(|signal| signal.to_string());
+            builder
+                .push("\nLikely migration: ")
+                .push_safe(signals.collect::<Vec<_>>().join(", "));
+        }
+        let response = builder.build();
 
         if let Err(why) = msg.channel_id.say(&context.http, &response).await {
             println!("Error sending message: {:?}", why);
         }
     } else {
         let response = MessageBuilder::new()
-            .push("Failed to query merged PRs From ")
+            .push("Failed to query PRs From ")
             .push_bold_safe(repo)
             .build();
 
@@ -166,37 +410,43 @@ async fn handle_pr_message(repo: &str, pr: Option<&PullRequest>, msg: &Message,
     }
 }
 
+// Like `\nCompanions: paritytech/polkadot#6320\nCloses: paritytech/substrate#12690`.
+fn links_message(reference: &Reference, links: &[Link]) -> String {
+    let join = |references: Vec<&Reference>| {
+        references
+            .iter()
+            .map(|reference| reference.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut builder = MessageBuilder::new();
+    let companions = crate::links::companions(reference, links);
+    if !companions.is_empty() {
+        builder.push("\nCompanions: ").push_safe(join(companions));
+    }
+    let closes = crate::links::closes(reference, links);
+    if !closes.is_empty() {
+        builder.push("\nCloses: ").push_safe(join(closes));
+    }
+
+    builder.build()
+}
+
 async fn handle_release_message(
     repo: &str,
-    release: Option<&LatestRelease>,
+    event: Option<&ReleaseEvent>,
     msg: &Message,
     context: &Context,
 ) {
-    if let Some(release) = release {
-        /*
-            The example of message format:
-            **Polkadot Latest Release**: Release Title:
-            release's url
-        */
-        let response = MessageBuilder::new()
-            .push_bold_safe(repo)
-            .push(" **Latest Release**: ")
-            .push(
-                release
-                    .release_name
-                    .as_deref()
-                    .unwrap_or("No release title"),
-            )
-            .push(" ")
-            .push(release.url.as_str())
-            .build();
+    if let Some(event) = event {
+        let response = release_message(repo, event);
 
         if let Err(why) = msg.channel_id.say(&context.http, &response).await {
             println!("Error sending message: {:?}", why);
         }
     } else {
         let response = MessageBuilder::new()
-            .push("Failed to query latest release from ")
+            .push("Failed to query releases from ")
             .push_bold_safe(repo)
             .build();
 
@@ -206,6 +456,245 @@ async fn handle_release_message(
     }
 }
 
+// How many items a message lists at most, like runtime changes or missing PRs,
+// a message can't be longer than 2000 chars.
+const MAX_LISTED: usize = 10;
+
+/*
+    The example of message format:
+    **Polkadot Release** (pre-release): Release Title: release's url
+    Upgrade priority: **High**
+    Runtimes: polkadot-9340
+    Runtime changes:
+    - #6359 Polkadot: Fix `MaxNominators` to match the staking limit
+*/
+fn release_message(repo: &str, event: &ReleaseEvent) -> String {
+    let mut builder = MessageBuilder::new();
+    builder
+        .push_bold_safe(repo)
+        .push(" **Release** (")
+        .push(event.kind())
+        .push("): ")
+        .push(event.name())
+        .push(" ")
+        .push(event.html_url());
+    if let Some(notes) = event.notes() {
+        if let Some(priority) = notes.priority {
+            builder
+                .push("\nUpgrade priority: ")
+                .push_bold(priority.to_string());
+        }
+        let spec_versions = notes.spec_versions();
+        if !spec_versions.is_empty() {
+            builder.push("\nRuntimes: ").push(spec_versions.join(", "));
+        }
+        if !notes.runtime_changes.is_empty() {
+            builder.push("\nRuntime changes:");
+            for change in notes.runtime_changes.iter().take(MAX_LISTED) {
+                builder.push("\n- ").push_safe(change);
+            }
+            if notes.runtime_changes.len() > MAX_LISTED {
+                builder.push(format!(
+                    "\n- and {} more",
+                    notes.runtime_changes.len() - MAX_LISTED
+                ));
+            }
+        }
+    }
+
+    builder.build()
+}
+
+async fn handle_commit_message(
+    repo: &str,
+    commit: Option<&BranchCommit>,
+    msg: &Message,
+    context: &Context,
+) {
+    let response = match commit {
+        Some(commit) => commit_message(repo, commit),
+        None => MessageBuilder::new()
+            .push("Failed to query branch commits from ")
+            .push_bold_safe(repo)
+            .build(),
+    };
+
+    if let Err(why) = msg.channel_id.say(&context.http, &response).await {
+        println!("Error sending message: {:?}", why);
+    }
+}
+
+/*
+    The example of message format:
+    **substrate** **Commit** on polkadot-v0.9.33 by bkchr: Fix the weight (#12900) commit's url
+    PR: pr's url
+*/
+fn commit_message(repo: &str, commit: &BranchCommit) -> String {
+    let mut builder = MessageBuilder::new();
+    builder
+        .push_bold_safe(repo)
+        .push(" **Commit** on ")
+        .push_safe(&commit.branch)
+        .push(" by ")
+        .push_safe(&commit.author)
+        .push(": ")
+        .push_safe(commit.title())
+        .push(" ")
+        .push(&commit.html_url);
+    if let Some(pr) = commit.pr.as_ref() {
+        builder.push("\nPR: ").push(pr);
+    }
+
+    builder.build()
+}
+
+async fn handle_divergence_message(
+    pair: &ForkPair,
+    divergence: Option<&Divergence>,
+    msg: &Message,
+    context: &Context,
+) {
+    let response = match divergence {
+        Some(divergence) => divergence_message(pair, divergence),
+        None => MessageBuilder::new()
+            .push("Failed to compare ")
+            .push_bold_safe(pair)
+            .push(" with upstream")
+            .build(),
+    };
+
+    if let Err(why) = msg.channel_id.say(&context.http, &response).await {
+        println!("Error sending message: {:?}", why);
+    }
+}
+
+/*
+    The example of message format:
+    **Manta-Network/substrate:polkadot-v0.9.33** is 2 commits ahead and 3 behind paritytech/substrate:polkadot-v0.9.33, 2 upstream PRs missing:
+    - Fix the weight of `set_code` (#12850) pr's url
+*/
+fn divergence_message(pair: &ForkPair, divergence: &Divergence) -> String {
+    let mut builder = MessageBuilder::new();
+    builder
+        .push_bold_safe(pair)
+        .push(format!(
+            " is {} commits ahead and {} behind ",
+            divergence.ahead_by, divergence.behind_by
+        ))
+        .push_safe(format!("{}:{}", pair.upstream, pair.upstream_branch()));
+    if divergence.missing.is_empty() {
+        return builder.push(", nothing missing").build();
+    }
+
+    builder.push(format!(
+        ", {} upstream PRs missing:",
+        divergence.missing.len()
+    ));
+    for pr in divergence.missing.iter().take(MAX_LISTED) {
+        builder
+            .push("\n- ")
+            .push_safe(&pr.title)
+            .push(" ")
+            .push(&pr.html_url);
+    }
+    if divergence.missing.len() > MAX_LISTED {
+        builder.push(format!(
+            "\n- and {} more",
+            divergence.missing.len() - MAX_LISTED
+        ));
+    }
+
+    builder.build()
+}
+
+async fn handle_pin_message(
+    pin: &Pin,
+    status: Option<&PinStatus>,
+    msg: &Message,
+    context: &Context,
+) {
+    let response = match status {
+        Some(status) => pin_message(status),
+        None => MessageBuilder::new()
+            .push("Failed to compare the pinned commit of ")
+            .push_bold_safe(pin)
+            .push(" with the branch")
+            .build(),
+    };
+
+    if let Err(why) = msg.channel_id.say(&context.http, &response).await {
+        println!("Error sending message: {:?}", why);
+    }
+}
+
+/*
+    The example of message format:
+    **paritytech/substrate:polkadot-v0.9.33**: pinned 2222222 is 2 commits and 10 days behind, 1 merged PRs in between:
+    - [Backport] Fix the weight of `set_code` (#12900) pr's url
+*/
+fn pin_message(status: &PinStatus) -> String {
+    let pinned = &status.pin.pinned;
+    let mut builder = MessageBuilder::new();
+    builder.push_bold_safe(&status.pin).push(format!(
+        ": pinned {} is {} commits and {} days behind",
+        &pinned[..7.min(pinned.len())],
+        status.commits_behind,
+        status.days_behind
+    ));
+    if status.commits_behind == 0 {
+        return builder.push(", up to date").build();
+    }
+
+    let prs = status
+        .missing
+        .iter()
+        .filter(|pr| pr.number.is_some())
+        .collect::<Vec<_>>();
+    builder.push(format!(", {} merged PRs in between:", prs.len()));
+    for pr in prs.iter().take(MAX_LISTED) {
+        builder
+            .push("\n- ")
+            .push_safe(&pr.title)
+            .push(" ")
+            .push(&pr.html_url);
+    }
+    if prs.len() > MAX_LISTED {
+        builder.push(format!("\n- and {} more", prs.len() - MAX_LISTED));
+    }
+
+    builder.build()
+}
+
+async fn handle_watch_message(
+    item: &WatchedItem,
+    update: Option<&WatchUpdate>,
+    msg: &Message,
+    context: &Context,
+) {
+    let response = match update {
+        /*
+            The example of message format:
+            **paritytech/substrate#12345** bkchr added label A0-please_review:
+            event's url
+        */
+        Some(update) => MessageBuilder::new()
+            .push_bold_safe(item)
+            .push(" ")
+            .push_safe(&update.description)
+            .push(" ")
+            .push(&update.url)
+            .build(),
+        None => MessageBuilder::new()
+            .push("Failed to query updates of ")
+            .push_bold_safe(item)
+            .build(),
+    };
+
+    if let Err(why) = msg.channel_id.say(&context.http, &response).await {
+        println!("Error sending message: {:?}", why);
+    }
+}
+
 pub async fn discord_bot(config: &Value) {
     // Get discord bot token.
     let token = get_discord_token(config);
@@ -217,11 +706,22 @@ pub async fn discord_bot(config: &Value) {
     // Get all repositories
     let repositories = get_repositories(config).expect("Failed to get all repositories.");
 
+    // How many repositories and resources are synced at the same time.
+    let concurrency = crate::utils::get_concurrency(config);
+    let mode = crate::utils::get_fetch_mode(config).expect("Invalid fetch-mode.");
+
     // configure bot handler
-    let bot_handler = BotHandler::new(frequence, db, repositories);
+    let bot_handler = BotHandler::new(
+        frequence,
+        db,
+        repositories,
+        config.clone(),
+        concurrency,
+        mode,
+    );
 
     let intents = GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT;
-    let mut client = Client::builder(&token, intents)
+    let mut client = Client::builder(token, intents)
         .event_handler(bot_handler)
         .await
         .expect("Err creating client");
@@ -234,6 +734,7 @@ pub async fn discord_bot(config: &Value) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use octocrab::models::repos;
 
     #[test]
     fn test_message_foramt() {
@@ -252,4 +753,144 @@ mod tests {
             "**Substrate** **PR**: Improve JSON error reporting https://github.com/XAMPPRocky/octocrab/issues/13"
         );
     }
+
+    #[test]
+    fn release_message_should_tell_priority_and_runtime_changes() {
+        let mut release: repos::Release = serde_json::from_str(include_str!(
+            "../fixtures/github/repos/paritytech/polkadot/releases/latest.json"
+        ))
+        .unwrap();
+        let event = ReleaseEvent::Published(Box::new(release.clone().into()));
+        assert_eq!(
+            release_message("polkadot", &event),
+            "**polkadot** **Release** (release): Polkadot v0.9.33 https://github.com/paritytech/polkadot/releases/tag/v0.9.33\n\
+            Upgrade priority: **Low**\n\
+            Runtimes: westend-9330, kusama-9330, polkadot-9330\n\
+            Runtime changes:\n\
+            - #6304 Kusama: Enable nomination pools commission\n\
+            - #6257 Polkadot: Remove \\`Gilt\\` leftovers and unused storage\n\
+            - #6281 Westend: Add \\`FastUnstake\\` pallet"
+        );
+
+        // Nothing but the title if the body doesn't follow the convention.
+        release.body = Some("Bug fixes.".to_owned());
+        let event = ReleaseEvent::Published(Box::new(release.into()));
+        assert!(!release_message("polkadot", &event).contains('\n'));
+    }
+
+    #[test]
+    fn commit_message_should_tell_author_and_pr() {
+        let mut commit = BranchCommit {
+            branch: "polkadot-v0.9.33".to_owned(),
+            sha: "4".repeat(40),
+            author: "bkchr".to_owned(),
+            message: "Fix metadata of `frame-support` (#12900)\n\nDetails.".to_owned(),
+            date: None,
+            html_url: format!(
+                "https://github.com/paritytech/substrate/commit/{}",
+                "4".repeat(40)
+            ),
+            pr: Some("https://github.com/paritytech/substrate/pull/12900".to_owned()),
+        };
+        assert_eq!(
+            commit_message("substrate", &commit),
+            format!(
+                "**substrate** **Commit** on polkadot-v0.9.33 by bkchr: Fix metadata of \\`frame-support\\` (#12900) {}\n\
+                PR: https://github.com/paritytech/substrate/pull/12900",
+                commit.html_url
+            )
+        );
+
+        commit.pr = None;
+        assert!(!commit_message("substrate", &commit).contains('\n'));
+    }
+
+    #[test]
+    fn divergence_message_should_tell_missing_prs() {
+        let pair = ForkPair {
+            fork: "Manta-Network/substrate".to_owned(),
+            upstream: "paritytech/substrate".to_owned(),
+            branch: "polkadot-v0.9.33".to_owned(),
+            upstream_branch: None,
+        };
+        let mut divergence = Divergence {
+            ahead_by: 2,
+            behind_by: 3,
+            missing: vec![crate::divergence::MissingPr {
+                number: Some(12850),
+                title: "Fix the weight (#12850)".to_owned(),
+                author: "ggwpez".to_owned(),
+                date: None,
+                html_url: "https://github.com/paritytech/substrate/pull/12850".to_owned(),
+            }],
+            checked_at: chrono::Utc::now(),
+        };
+        assert_eq!(
+            divergence_message(&pair, &divergence),
+            "**Manta-Network/substrate:polkadot-v0.9.33** is 2 commits ahead and 3 behind paritytech/substrate:polkadot-v0.9.33, 1 upstream PRs missing:\n\
+            - Fix the weight (#12850) https://github.com/paritytech/substrate/pull/12850"
+        );
+
+        divergence.missing.clear();
+        assert!(divergence_message(&pair, &divergence).ends_with(", nothing missing"));
+    }
+
+    #[test]
+    fn links_message_should_tell_companions_and_closed_issues() {
+        let substrate = Reference::new("paritytech", "substrate", 12700);
+        let link = |kind, from: &Reference, to: &Reference| Link {
+            kind,
+            from: from.clone(),
+            to: to.clone(),
+        };
+        let polkadot = Reference::new("paritytech", "polkadot", 6320);
+        let issue = Reference::new("paritytech", "substrate", 12690);
+        let links = vec![
+            link(crate::links::LinkKind::Closes, &substrate, &issue),
+            link(crate::links::LinkKind::Companion, &polkadot, &substrate),
+        ];
+        assert_eq!(
+            links_message(&substrate, &links),
+            "\nCompanions: paritytech/polkadot#6320\nCloses: paritytech/substrate#12690"
+        );
+        // Being closed is not told.
+        assert_eq!(links_message(&issue, &links), "");
+    }
+
+    #[test]
+    fn pin_message_should_tell_merged_prs_only() {
+        let commit = |number: Option<u64>, title: &str| crate::divergence::MissingPr {
+            number,
+            title: title.to_owned(),
+            author: "bkchr".to_owned(),
+            date: None,
+            html_url: "https://github.com/paritytech/substrate/commit/4444444".to_owned(),
+        };
+        let mut status = PinStatus {
+            pin: Pin {
+                organization: "paritytech".to_owned(),
+                repository: "substrate".to_owned(),
+                branch: "polkadot-v0.9.33".to_owned(),
+                pinned: "2".repeat(40),
+            },
+            head: "4".repeat(40),
+            commits_behind: 2,
+            days_behind: 10,
+            missing: vec![
+                commit(None, "Bump spec version"),
+                commit(Some(12900), "[Backport] Fix the weight (#12900)"),
+            ],
+            checked_at: chrono::Utc::now(),
+        };
+        assert_eq!(
+            pin_message(&status),
+            "**paritytech/substrate:polkadot-v0.9.33**: pinned 2222222 is 2 commits and 10 days behind, 1 merged PRs in between:\n\
+            - [Backport] Fix the weight (#12900) https://github.com/paritytech/substrate/commit/4444444"
+        );
+
+        status.commits_behind = 0;
+        status.days_behind = 0;
+        status.missing.clear();
+        assert!(pin_message(&status).ends_with(", up to date"));
+    }
 }
@@ -0,0 +1,522 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Discover subscriptions from git dependencies in Cargo.lock and Cargo.toml.
+//!
+//! A source like `git+https://github.com/paritytech/substrate?branch=polkadot-v0.9.33#a1b2c3`
+//! is turned into a subscription of `paritytech/substrate` following the branch, pinned to the commit.
+
+use crate::utils::Repository;
+use anyhow::{anyhow, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::Path,
+};
+use toml::Value;
+
+// The reference a git dependency follows.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GitReference {
+    Branch(String),
+    Tag(String),
+    Rev(String),
+    // The default branch.
+    Head,
+}
+
+impl GitReference {
+    // Branch or tag, a revision is a commit already.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            GitReference::Branch(name) | GitReference::Tag(name) => Some(name),
+            GitReference::Rev(_) | GitReference::Head => None,
+        }
+    }
+}
+
+impl fmt::Display for GitReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitReference::Branch(branch) => write!(f, "branch {branch}"),
+            GitReference::Tag(tag) => write!(f, "tag {tag}"),
+            GitReference::Rev(rev) => write!(f, "rev {rev}"),
+            GitReference::Head => write!(f, "default branch"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GitSource {
+    pub organization: String,
+    pub repository: String,
+    pub reference: GitReference,
+    // Commit in Cargo.lock, None if it's only in Cargo.toml.
+    pub pinned: Option<String>,
+    // Crates coming from it.
+    pub crates: BTreeSet<String>,
+}
+
+// `https://github.com/paritytech/substrate.git` to (paritytech, substrate).
+fn parse_git_url(url: &str) -> Option<(String, String)> {
+    let path = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let mut segments = path.trim_end_matches('/').split('/').skip(1);
+    let org = segments.next()?;
+    let repo = segments.next()?.trim_end_matches(".git");
+    if org.is_empty() || repo.is_empty() || segments.next().is_some() {
+        return None;
+    }
+
+    Some((org.to_owned(), repo.to_owned()))
+}
+
+// Source of a package in Cargo.lock, like `git+https://github.com/org/repo?branch=b#sha`.
+fn parse_lock_source(source: &str) -> Option<(String, String, GitReference, String)> {
+    let source = source.strip_prefix("git+")?;
+    let (url, sha) = source.split_once('#')?;
+    let (url, query) = url.split_once('?').unwrap_or((url, ""));
+    let (org, repo) = parse_git_url(url)?;
+    let reference = query
+        .split('&')
+        .find_map(|pair| match pair.split_once('=') {
+            Some(("branch", branch)) => Some(GitReference::Branch(branch.to_owned())),
+            Some(("tag", tag)) => Some(GitReference::Tag(tag.to_owned())),
+            Some(("rev", rev)) => Some(GitReference::Rev(rev.to_owned())),
+            _ => None,
+        })
+        .unwrap_or(GitReference::Head);
+
+    Some((org, repo, reference, sha.to_owned()))
+}
+
+fn add_source(
+    sources: &mut Vec<GitSource>,
+    (org, repo, reference): (String, String, GitReference),
+    pinned: Option<String>,
+    name: &str,
+) {
+    let found = sources.iter_mut().find(|source| {
+        source.organization.eq_ignore_ascii_case(&org)
+            && source.repository.eq_ignore_ascii_case(&repo)
+            && source.reference == reference
+    });
+    match found {
+        Some(source) => {
+            source.crates.insert(name.to_owned());
+            if source.pinned.is_none() {
+                source.pinned = pinned;
+            }
+        }
+        None => sources.push(GitSource {
+            organization: org,
+            repository: repo,
+            reference,
+            pinned,
+            crates: [name.to_owned()].into_iter().collect(),
+        }),
+    }
+}
+
+// Git sources of all packages in Cargo.lock.
+pub fn parse_lock(lock: &str) -> Result<Vec<GitSource>> {
+    let lock = lock.parse::<Value>()?;
+    let mut sources = vec![];
+    let packages = lock
+        .get("package")
+        .and_then(|packages| packages.as_array())
+        .cloned()
+        .unwrap_or_default();
+    for package in packages.iter() {
+        let (Some(name), Some(source)) = (
+            package.get("name").and_then(Value::as_str),
+            package.get("source").and_then(Value::as_str),
+        ) else {
+            continue;
+        };
+        if let Some((org, repo, reference, sha)) = parse_lock_source(source) {
+            add_source(&mut sources, (org, repo, reference), Some(sha), name);
+        }
+    }
+
+    Ok(sources)
+}
+
+// Every table with a `git` key is a git dependency, wherever it is,
+// like `dependencies`, `workspace.dependencies`, `target.*.dependencies` or `patch.*`.
+fn collect_manifest_sources(name: &str, value: &Value, sources: &mut Vec<GitSource>) {
+    let Some(table) = value.as_table() else {
+        return;
+    };
+    if let Some((org, repo)) = table
+        .get("git")
+        .and_then(Value::as_str)
+        .and_then(parse_git_url)
+    {
+        let reference = match (table.get("branch"), table.get("tag"), table.get("rev")) {
+            (Some(Value::String(branch)), _, _) => GitReference::Branch(branch.clone()),
+            (_, Some(Value::String(tag)), _) => GitReference::Tag(tag.clone()),
+            (_, _, Some(Value::String(rev))) => GitReference::Rev(rev.clone()),
+            _ => GitReference::Head,
+        };
+        let name = table.get("package").and_then(Value::as_str).unwrap_or(name);
+        add_source(sources, (org, repo, reference), None, name);
+        return;
+    }
+    for (key, value) in table.iter() {
+        collect_manifest_sources(key, value, sources);
+    }
+}
+
+// Git dependencies in Cargo.toml, they're not pinned.
+pub fn parse_manifest(manifest: &str) -> Result<Vec<GitSource>> {
+    let manifest = manifest.parse::<Value>()?;
+    let mut sources = vec![];
+    collect_manifest_sources("", &manifest, &mut sources);
+    Ok(sources)
+}
+
+// Read sources of the lock file and manifests, commits of the lock file pin sources of manifests.
+pub fn discover_sources(lock: &Path, manifests: &[impl AsRef<Path>]) -> Result<Vec<GitSource>> {
+    let mut sources = parse_lock(&std::fs::read_to_string(lock)?)?;
+    for manifest in manifests {
+        for source in parse_manifest(&std::fs::read_to_string(manifest)?)? {
+            for name in source.crates.iter() {
+                add_source(
+                    &mut sources,
+                    (
+                        source.organization.clone(),
+                        source.repository.clone(),
+                        source.reference.clone(),
+                    ),
+                    None,
+                    name,
+                );
+            }
+        }
+    }
+    sources.sort_by(|a, b| {
+        (&a.organization, &a.repository, &a.reference).cmp(&(
+            &b.organization,
+            &b.repository,
+            &b.reference,
+        ))
+    });
+
+    Ok(sources)
+}
+
+// One subscription per repository, following every branch it's depended on by.
+pub fn propose_repositories(sources: &[GitSource]) -> Vec<Repository> {
+    let mut repositories: Vec<Repository> = vec![];
+    for source in sources {
+        let index = match repositories.iter().position(|repo| {
+            repo.organization.eq_ignore_ascii_case(&source.organization)
+                && repo.repository.eq_ignore_ascii_case(&source.repository)
+        }) {
+            Some(index) => index,
+            None => {
+                repositories.push(Repository {
+                    organization: source.organization.clone(),
+                    repository: source.repository.clone(),
+                    ..Default::default()
+                });
+                repositories.len() - 1
+            }
+        };
+        let repository = &mut repositories[index];
+        if let GitReference::Branch(branch) = &source.reference {
+            if !repository.branches.contains(branch) {
+                repository.branches.push(branch.clone());
+            }
+        }
+        if let (Some(name), Some(pinned)) = (source.reference.name(), source.pinned.as_ref()) {
+            repository.pins.insert(name.to_owned(), pinned.clone());
+        }
+    }
+
+    repositories
+}
+
+fn string_array(values: &[String]) -> String {
+    let values = values
+        .iter()
+        .map(|value| Value::from(value.as_str()).to_string())
+        .collect::<Vec<_>>();
+    format!("[{}]", values.join(", "))
+}
+
+fn inline_table(map: &BTreeMap<String, String>) -> String {
+    let pairs = map
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{} = {}",
+                Value::from(key.as_str()),
+                Value::from(value.as_str())
+            )
+        })
+        .collect::<Vec<_>>();
+    match pairs.is_empty() {
+        true => "{}".to_owned(),
+        false => format!("{{ {} }}", pairs.join(", ")),
+    }
+}
+
+// Section of a new subscription in config.
+pub fn repository_section(repo: &Repository) -> String {
+    format!(
+        "[{org}.{name}]\norganization = \"{org}\"\nrepository = \"{name}\"\nquery-release = false\nbranches = {}\npins = {}\n",
+        string_array(&repo.branches),
+        inline_table(&repo.pins),
+        org = repo.organization,
+        name = repo.repository,
+    )
+}
+
+fn is_header_of(line: &str, repo: &Repository) -> bool {
+    let header = line.split('#').next().unwrap_or_default().trim();
+    let Some(header) = header
+        .strip_prefix('[')
+        .and_then(|header| header.strip_suffix(']'))
+    else {
+        return false;
+    };
+    match header.split_once('.') {
+        Some((org, name)) => {
+            org.trim()
+                .trim_matches('"')
+                .eq_ignore_ascii_case(&repo.organization)
+                && name
+                    .trim()
+                    .trim_matches('"')
+                    .eq_ignore_ascii_case(&repo.repository)
+        }
+        None => false,
+    }
+}
+
+// Write `branches` and `pins` of proposed repositories into config, line by line,
+// so comments and everything else are kept. Repositories not subscribed yet are appended.
+pub fn update_config(config: &str, repositories: &[Repository]) -> Result<String> {
+    let mut lines = config.lines().map(ToOwned::to_owned).collect::<Vec<_>>();
+    let mut appended = vec![];
+    for repo in repositories {
+        let Some(start) = lines.iter().position(|line| is_header_of(line, repo)) else {
+            appended.push(repository_section(repo));
+            continue;
+        };
+        let mut last_key = start;
+        for (key, value) in [
+            ("branches", string_array(&repo.branches)),
+            ("pins", inline_table(&repo.pins)),
+        ] {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|line| line.trim_start().starts_with('['))
+                .map_or(lines.len(), |end| start + 1 + end);
+            let found = (start + 1..end).find(|index| {
+                lines[*index]
+                    .split_once('=')
+                    .map_or(false, |(k, _)| k.trim() == key)
+            });
+            match found {
+                Some(index) => {
+                    // Keep the comment of the line.
+                    let comment = lines[index]
+                        .rsplit_once(" # ")
+                        .map(|(_, comment)| format!(" # {comment}"))
+                        .unwrap_or_default();
+                    lines[index] = format!("{key} = {value}{comment}");
+                    last_key = last_key.max(index);
+                }
+                None => {
+                    // Right after the other key, or at the end of the section.
+                    let index = match last_key > start {
+                        true => last_key,
+                        false => (start + 1..end)
+                            .rev()
+                            .find(|index| !lines[*index].trim().is_empty())
+                            .unwrap_or(start),
+                    };
+                    lines.insert(index + 1, format!("{key} = {value}"));
+                    last_key = index + 1;
+                }
+            }
+        }
+    }
+
+    let mut config = lines.join("\n");
+    config.push('\n');
+    for section in appended {
+        config.push('\n');
+        config.push_str(&section);
+    }
+    // It must be still valid.
+    config
+        .parse::<Value>()
+        .map_err(|e| anyhow!("Failed to update config: {e}"))?;
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/cargo/Cargo.lock");
+    const MANIFEST: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/cargo/Cargo.toml");
+
+    fn find<'a>(repositories: &'a [Repository], org: &str, repo: &str) -> &'a Repository {
+        repositories
+            .iter()
+            .find(|r| r.organization == org && r.repository == repo)
+            .unwrap()
+    }
+
+    #[test]
+    fn sources_should_be_discovered() {
+        let sources = discover_sources(Path::new(LOCK), &[MANIFEST]).unwrap();
+        let found = sources
+            .iter()
+            .map(|source| {
+                (
+                    format!("{}/{}", source.organization, source.repository),
+                    source.reference.clone(),
+                    source.pinned.is_some(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let branch = |name: &str| GitReference::Branch(name.to_owned());
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "Manta-Network/substrate".to_owned(),
+                    branch("manta-v0.9.33"),
+                    false
+                ),
+                (
+                    "manta-network/open-runtime-module-library".to_owned(),
+                    GitReference::Rev("abcdef0123456789abcdef0123456789abcdef01".to_owned()),
+                    true
+                ),
+                (
+                    "paritytech/cumulus".to_owned(),
+                    branch("polkadot-v0.9.33"),
+                    true
+                ),
+                (
+                    "paritytech/frontier".to_owned(),
+                    branch("polkadot-v0.9.33"),
+                    false
+                ),
+                (
+                    "paritytech/polkadot".to_owned(),
+                    branch("release-v0.9.33"),
+                    true
+                ),
+                (
+                    "paritytech/polkadot".to_owned(),
+                    GitReference::Tag("v0.9.33".to_owned()),
+                    true
+                ),
+                (
+                    "paritytech/substrate".to_owned(),
+                    branch("polkadot-v0.9.33"),
+                    true
+                ),
+            ]
+        );
+        let substrate = sources.last().unwrap();
+        assert_eq!(
+            substrate.crates.iter().collect::<Vec<_>>(),
+            vec!["frame-support", "pallet-balances"]
+        );
+
+        let repositories = propose_repositories(&sources);
+        assert_eq!(repositories.len(), 6);
+        let polkadot = find(&repositories, "paritytech", "polkadot");
+        assert_eq!(polkadot.branches, vec!["release-v0.9.33"]);
+        assert_eq!(polkadot.pins.len(), 2);
+        assert_eq!(polkadot.pins["v0.9.33"], "3".repeat(40));
+        let orml = find(
+            &repositories,
+            "manta-network",
+            "open-runtime-module-library",
+        );
+        assert!(orml.branches.is_empty() && orml.pins.is_empty());
+    }
+
+    #[test]
+    fn config_should_be_updated_in_place() {
+        let config = r#"[paritytech.substrate]
+query-release = false
+branches = ["polkadot-v0.9.32"] # the release branch we're pinned to
+exclude-labels = ["B0-silent"]
+
+[paritytech.substrate.categories]
+T1-runtime = "Runtime"
+
+[paritytech.cumulus]
+query-release = false
+"#;
+        let sources = parse_lock(&std::fs::read_to_string(LOCK).unwrap()).unwrap();
+        let repositories = propose_repositories(&sources)
+            .into_iter()
+            .filter(|repo| repo.organization == "paritytech")
+            .collect::<Vec<_>>();
+        let updated = update_config(config, &repositories).unwrap();
+        let substrate_pin = "2".repeat(40);
+        assert!(updated.starts_with(&format!(
+            r#"[paritytech.substrate]
+query-release = false
+branches = ["polkadot-v0.9.33"] # the release branch we're pinned to
+pins = {{ "polkadot-v0.9.33" = "{substrate_pin}" }}
+exclude-labels = ["B0-silent"]
+
+[paritytech.substrate.categories]
+T1-runtime = "Runtime"
+
+[paritytech.cumulus]
+query-release = false
+branches = ["polkadot-v0.9.33"]
+pins = "#
+        )));
+        assert!(updated.ends_with(&format!(
+            r#"
+[paritytech.polkadot]
+organization = "paritytech"
+repository = "polkadot"
+query-release = false
+branches = ["release-v0.9.33"]
+pins = {{ "release-v0.9.33" = "{0}", "v0.9.33" = "{0}" }}
+"#,
+            "3".repeat(40)
+        )));
+
+        // Config can be read as before.
+        let config = updated.parse::<Value>().unwrap();
+        let repos = crate::utils::get_repositories(&config).unwrap();
+        assert_eq!(repos.len(), 3);
+        assert_eq!(
+            crate::utils::get_repository(&config, "paritytech", "substrate")
+                .unwrap()
+                .unwrap()
+                .pins["polkadot-v0.9.33"],
+            substrate_pin
+        );
+    }
+}
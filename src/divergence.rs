@@ -0,0 +1,316 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Track how Manta's forks diverge from upstream.
+//!
+//! Every pair in section `forks` compares a branch of the fork with a branch of upstream,
+//! how many commits the fork is ahead and behind and which upstream PRs it misses are kept in sled.
+
+use crate::subcribe_commits::BranchCommit;
+use crate::utils::IntenalError;
+use anyhow::Result;
+use chrono::prelude::*;
+use octocrab::Octocrab;
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use std::{collections::HashSet, fmt, sync::Arc};
+use toml::Value as Config;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ForkPair {
+    // Like `Manta-Network/substrate`.
+    pub fork: String,
+    // Like `paritytech/substrate`, the fork must be in its network.
+    pub upstream: String,
+    pub branch: String,
+    // Falls back to `branch`.
+    pub upstream_branch: Option<String>,
+}
+
+impl ForkPair {
+    pub fn upstream_branch(&self) -> &str {
+        self.upstream_branch.as_deref().unwrap_or(&self.branch)
+    }
+
+    pub fn fork_repo(&self) -> Result<(&str, &str)> {
+        split_repo(&self.fork)
+    }
+
+    pub fn upstream_repo(&self) -> Result<(&str, &str)> {
+        split_repo(&self.upstream)
+    }
+}
+
+impl fmt::Display for ForkPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.fork, self.branch)
+    }
+}
+
+fn split_repo(repo: &str) -> Result<(&str, &str)> {
+    match repo.split_once('/') {
+        Some((org, name)) if !org.is_empty() && !name.is_empty() && !name.contains('/') => {
+            Ok((org, name))
+        }
+        _ => Err(IntenalError::InvalidRepository(repo.to_owned()).into()),
+    }
+}
+
+// An upstream PR the fork misses, or a commit pushed to upstream without a PR.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MissingPr {
+    pub number: Option<u64>,
+    pub title: String,
+    pub author: String,
+    pub date: Option<DateTime<Utc>>,
+    pub html_url: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Divergence {
+    // Commits of the fork not in upstream.
+    pub ahead_by: usize,
+    // Commits of upstream not in the fork.
+    pub behind_by: usize,
+    // Oldest first, PRs cherry-picked to the fork are not missing.
+    pub missing: Vec<MissingPr>,
+    pub checked_at: DateTime<Utc>,
+}
+
+fn divergence_key(pair: &ForkPair) -> String {
+    format!("forks#{}#{}", pair.fork, pair.branch)
+}
+
+pub fn get_fork_pairs(config: &Config) -> Result<Vec<ForkPair>> {
+    match config.get("forks").and_then(|forks| forks.get("pairs")) {
+        Some(pairs) => Ok(pairs.clone().try_into()?),
+        None => Ok(vec![]),
+    }
+}
+
+pub fn get_divergence(db: &Db, pair: &ForkPair) -> Result<Option<Divergence>> {
+    match db.get(divergence_key(pair))? {
+        Some(divergence) => Ok(Some(serde_json::from_slice(&divergence)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn store_divergence(db: &Db, pair: &ForkPair, divergence: &Divergence) -> Result<()> {
+    db.insert(divergence_key(pair), serde_json::to_vec(divergence)?)?;
+    Ok(())
+}
+
+// PRs of upstream commits, oldest first, except ones in `picked`.
+pub async fn missing_prs(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    commits: &[BranchCommit],
+    picked: &HashSet<u64>,
+) -> Result<Vec<MissingPr>> {
+    let numbers = crate::subcribe_commits::get_commit_pr_numbers(
+        octocrab.clone(),
+        org,
+        repo,
+        commits,
+        crate::subcribe_commits::MAX_PR_LOOKUPS,
+    )
+    .await?;
+    let html_root = crate::utils::html_root(octocrab.base_url.as_str());
+    let mut missing: Vec<MissingPr> = vec![];
+    for (commit, number) in commits.iter().zip(numbers) {
+        if let Some(number) = number {
+            if picked.contains(&number) || missing.iter().any(|pr| pr.number == Some(number)) {
+                continue;
+            }
+        }
+        let html_url = match number {
+            Some(number) => match commit.html_url.rsplit_once("/commit/") {
+                Some((repo_url, _)) => format!("{repo_url}/pull/{number}"),
+                None => format!("{html_root}{org}/{repo}/pull/{number}"),
+            },
+            None => commit.html_url.clone(),
+        };
+        missing.push(MissingPr {
+            number,
+            title: commit.title().to_owned(),
+            author: commit.author.clone(),
+            date: commit.date,
+            html_url,
+        });
+    }
+
+    Ok(missing)
+}
+
+// Compare both ways in upstream, the fork branch is referred as `owner:branch`.
+pub async fn compute_divergence(octocrab: Arc<Octocrab>, pair: &ForkPair) -> Result<Divergence> {
+    let (fork_org, _) = pair.fork_repo()?;
+    let (org, repo) = pair.upstream_repo()?;
+    let fork_head = format!("{fork_org}:{}", pair.branch);
+    let upstream_head = pair.upstream_branch();
+
+    let behind = crate::subcribe_commits::compare_refs(
+        octocrab.clone(),
+        org,
+        repo,
+        &fork_head,
+        upstream_head,
+    )
+    .await?;
+    let ahead = crate::subcribe_commits::compare_refs(
+        octocrab.clone(),
+        org,
+        repo,
+        upstream_head,
+        &fork_head,
+    )
+    .await?;
+    let picked: HashSet<u64> = ahead
+        .commits
+        .iter()
+        .filter_map(BranchCommit::squashed_pr_number)
+        .collect();
+
+    Ok(Divergence {
+        ahead_by: behind.behind_by,
+        behind_by: behind.ahead_by,
+        missing: missing_prs(octocrab, org, repo, &behind.commits, &picked).await?,
+        checked_at: Utc::now(),
+    })
+}
+
+// Compute and store divergence of pairs not checked in `summary-frequence`, or all of them with `force`.
+pub async fn update_divergences(
+    config: &Config,
+    db: Arc<Db>,
+    force: bool,
+) -> Result<Vec<(ForkPair, Result<Divergence>)>> {
+    let frequence = crate::utils::get_summary_frequence(config, "forks");
+    let mut results = vec![];
+    for pair in get_fork_pairs(config)? {
+        if !force {
+            if let Some(divergence) = get_divergence(&db, &pair)? {
+                if Utc::now() - divergence.checked_at < frequence {
+                    continue;
+                }
+            }
+        }
+
+        let (org, repo) = pair.upstream_repo()?;
+        let octocrab = crate::utils::repository_client(config, org, repo)?;
+        let divergence = compute_divergence(octocrab, &pair).await;
+        if let Ok(divergence) = divergence.as_ref() {
+            store_divergence(&db, &pair, divergence)?;
+        }
+        results.push((pair, divergence));
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    fn config_of(mock: &MockGithub) -> Config {
+        format!(
+            r#"
+            [github]
+            base-url = "{}"
+
+            [forks]
+            summary-frequence = 3600
+
+            [[forks.pairs]]
+            fork = "Manta-Network/substrate"
+            upstream = "paritytech/substrate"
+            branch = "polkadot-v0.9.33"
+            "#,
+            mock.base_url
+        )
+        .parse::<Config>()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn divergence_should_tell_missing_prs() {
+        let mock = MockGithub::start(&["github"]).await;
+        let config = config_of(&mock);
+        let db = temporary_db();
+        assert_eq!(
+            crate::utils::get_summary_frequence(&config, "forks"),
+            chrono::Duration::hours(1)
+        );
+
+        let results = update_divergences(&config, db.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        let (pair, divergence) = &results[0];
+        assert_eq!(pair.to_string(), "Manta-Network/substrate:polkadot-v0.9.33");
+        assert_eq!(pair.upstream_branch(), "polkadot-v0.9.33");
+        let divergence = divergence.as_ref().unwrap();
+        assert_eq!((divergence.ahead_by, divergence.behind_by), (2, 3));
+        // #12900 is cherry-picked already, and the backport is pushed without a PR.
+        let missing = divergence
+            .missing
+            .iter()
+            .map(|pr| (pr.number, pr.html_url.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            missing,
+            vec![
+                (
+                    Some(12850),
+                    "https://github.com/paritytech/substrate/pull/12850"
+                ),
+                (
+                    None,
+                    "https://github.com/paritytech/substrate/commit/3333333333333333333333333333333333333333"
+                ),
+            ]
+        );
+        assert_eq!(
+            get_divergence(&db, pair).unwrap().as_ref(),
+            Some(divergence)
+        );
+
+        // It's checked just now.
+        assert!(update_divergences(&config, db.clone(), false)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            update_divergences(&config, db, true).await.unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn invalid_fork_should_fail() {
+        let pair = ForkPair {
+            fork: "Manta-Network".to_owned(),
+            upstream: "paritytech/substrate".to_owned(),
+            branch: "polkadot-v0.9.33".to_owned(),
+            upstream_branch: None,
+        };
+        assert!(pair.fork_repo().is_err());
+        assert_eq!(pair.upstream_repo().unwrap(), ("paritytech", "substrate"));
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Filter and classify issues and PRs of a repository by their labels, titles and changed paths.
+//!
+//! Rules come from the section of the repository in config, they apply to both notifications and
+//! CLI reports.
+
+use crate::utils::Repository;
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use octocrab::models::{issues::Issue, pulls::PullRequest, Label};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use toml::Value;
+
+// Everything matches the default filter, and nothing is classified.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    // At least one of them is required if it's not empty.
+    include_labels: Vec<String>,
+    // None of them is allowed.
+    exclude_labels: Vec<String>,
+    title: Option<Regex>,
+    exclude_title: Option<Regex>,
+    // Label to category, like `T1-runtime` to `Runtime`.
+    categories: BTreeMap<String, String>,
+    // Globs of paths PRs must change, they're matched against changed files out of this filter.
+    paths: Vec<Pattern>,
+    // Crates of the repository we depend on, a PR touching any of them is relevant.
+    crates: BTreeSet<String>,
+    relevant_only: bool,
+}
+
+fn compile(pattern: &Option<String>, repo: &Repository) -> Result<Option<Regex>> {
+    pattern
+        .as_deref()
+        .map(|pattern| {
+            Regex::new(pattern).with_context(|| {
+                format!(
+                    "Invalid title regex of {}/{}.",
+                    repo.organization, repo.repository
+                )
+            })
+        })
+        .transpose()
+}
+
+impl Filter {
+    pub fn new(repo: &Repository) -> Result<Self> {
+        Ok(Self {
+            include_labels: repo.include_labels.clone(),
+            exclude_labels: repo.exclude_labels.clone(),
+            title: compile(&repo.title_regex, repo)?,
+            exclude_title: compile(&repo.exclude_title_regex, repo)?,
+            categories: repo.categories.clone(),
+            paths: repo
+                .paths
+                .iter()
+                .map(|path| {
+                    Pattern::new(path).with_context(|| {
+                        format!(
+                            "Invalid path glob {path} of {}/{}.",
+                            repo.organization, repo.repository
+                        )
+                    })
+                })
+                .collect::<Result<_>>()?,
+            crates: BTreeSet::new(),
+            relevant_only: repo.relevant_only,
+        })
+    }
+
+    pub fn with_crates(mut self, crates: BTreeSet<String>) -> Self {
+        self.crates = crates;
+        self
+    }
+
+    // Crates touched by PRs are only found out if it's true.
+    pub fn tracks_relevance(&self) -> bool {
+        !self.crates.is_empty() || self.relevant_only
+    }
+
+    pub fn relevant_only(&self) -> bool {
+        self.relevant_only
+    }
+
+    // Touched crates we depend on.
+    pub fn relevant_crates(&self, touched: &BTreeSet<String>) -> Vec<String> {
+        touched.intersection(&self.crates).cloned().collect()
+    }
+
+    // Changed files of PRs are only fetched if it's true.
+    pub fn has_paths(&self) -> bool {
+        !self.paths.is_empty()
+    }
+
+    // Globs matched by any of the files, in order of config.
+    pub fn touched_paths(&self, files: &[String]) -> Vec<String> {
+        // `*` stops at `/`, only `**` crosses directories.
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        self.paths
+            .iter()
+            .filter(|path| files.iter().any(|file| path.matches_with(file, options)))
+            .map(|path| path.as_str().to_owned())
+            .collect()
+    }
+
+    pub fn matches(&self, title: &str, labels: &[Label]) -> bool {
+        let has = |names: &[String]| labels.iter().any(|label| names.contains(&label.name));
+        (self.include_labels.is_empty() || has(&self.include_labels))
+            && !has(&self.exclude_labels)
+            && self.title.as_ref().map_or(true, |re| re.is_match(title))
+            && !self
+                .exclude_title
+                .as_ref()
+                .map_or(false, |re| re.is_match(title))
+    }
+
+    // Categories of matched labels in order of labels, without duplicates.
+    pub fn categories(&self, labels: &[Label]) -> Vec<String> {
+        let mut categories: Vec<String> = vec![];
+        for category in labels
+            .iter()
+            .filter_map(|label| self.categories.get(&label.name))
+        {
+            if !categories.contains(category) {
+                categories.push(category.clone());
+            }
+        }
+
+        categories
+    }
+
+    // Categories joined, like `Runtime, Breaking`.
+    pub fn category(&self, labels: &[Label]) -> Option<String> {
+        let categories = self.categories(labels);
+        (!categories.is_empty()).then(|| categories.join(", "))
+    }
+
+    pub fn matches_issue(&self, issue: &Issue) -> bool {
+        self.matches(&issue.title, &issue.labels)
+    }
+
+    pub fn matches_pr(&self, pr: &PullRequest) -> bool {
+        self.matches(
+            pr.title.as_deref().unwrap_or_default(),
+            pr.labels.as_deref().unwrap_or_default(),
+        )
+    }
+
+    pub fn issue_category(&self, issue: &Issue) -> Option<String> {
+        self.category(&issue.labels)
+    }
+
+    pub fn pr_category(&self, pr: &PullRequest) -> Option<String> {
+        self.category(pr.labels.as_deref().unwrap_or_default())
+    }
+}
+
+// Like ` [Runtime]`, or nothing if it's not classified.
+pub fn category_tag(category: Option<String>) -> String {
+    category
+        .map(|category| format!(" [{category}]"))
+        .unwrap_or_default()
+}
+
+// Filter of a subscribed repository, or the default one if it's not subscribed.
+pub fn repository_filter(config: &Value, org: &str, repo: &str) -> Result<Filter> {
+    let filter = match crate::utils::get_repository(config, org, repo)? {
+        Some(repository) => Filter::new(&repository)?,
+        None => Filter::default(),
+    };
+    let sources = crate::relevance::lock_sources(config)?;
+
+    Ok(filter.with_crates(crate::relevance::used_crates(&sources, org, repo)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(name: &str) -> Label {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "node_id": "LA_1",
+            "url": format!("https://api.github.com/repos/paritytech/substrate/labels/{name}"),
+            "name": name,
+            "color": "d4c5f9",
+            "default": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn filter_should_work() {
+        let config: Value = toml::from_str(
+            r#"
+            [paritytech.substrate]
+            query-release = false
+            include-labels = ["T1-runtime", "T0-node"]
+            exclude-labels = ["B0-silent"]
+            exclude-title-regex = "^\\[WIP\\]"
+
+            [paritytech.substrate.categories]
+            T1-runtime = "Runtime"
+            T0-node = "Client"
+            D2-breaksapi = "Breaking"
+            "#,
+        )
+        .unwrap();
+        let filter = repository_filter(&config, "paritytech", "substrate").unwrap();
+
+        let runtime = [label("T1-runtime"), label("D2-breaksapi"), label("T0-node")];
+        assert!(filter.matches("Remove Currency", &runtime));
+        assert!(!filter.matches("[WIP] Remove Currency", &runtime));
+        assert!(!filter.matches("Remove Currency", &[label("D2-breaksapi")]));
+        assert!(!filter.matches(
+            "Remove Currency",
+            &[label("T1-runtime"), label("B0-silent")]
+        ));
+        assert_eq!(
+            filter.category(&runtime).as_deref(),
+            Some("Runtime, Breaking, Client")
+        );
+        assert_eq!(filter.category(&[label("B0-silent")]), None);
+        assert_eq!(
+            category_tag(filter.category(&runtime)),
+            " [Runtime, Breaking, Client]"
+        );
+        assert_eq!(category_tag(None), "");
+
+        // Repositories not subscribed are not filtered.
+        let filter = repository_filter(&config, "paritytech", "polkadot").unwrap();
+        assert!(filter.matches("[WIP] Anything", &[]));
+    }
+
+    #[test]
+    fn invalid_title_regex_should_fail() {
+        let repo = Repository {
+            title_regex: Some("(runtime".to_owned()),
+            ..Default::default()
+        };
+        assert!(Filter::new(&repo).is_err());
+    }
+
+    #[test]
+    fn touched_paths_should_work() {
+        let repo = Repository {
+            paths: vec![
+                "frame/balances/**".to_owned(),
+                "client/*/Cargo.toml".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let filter = Filter::new(&repo).unwrap();
+        assert!(filter.has_paths());
+        let files = |files: &[&str]| files.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            filter.touched_paths(&files(&["frame/balances/src/lib.rs", "Cargo.lock"])),
+            vec!["frame/balances/**"]
+        );
+        assert_eq!(
+            filter.touched_paths(&files(&["client/consensus/Cargo.toml"])),
+            vec!["client/*/Cargo.toml"]
+        );
+        // `*` doesn't cross directories.
+        assert!(filter
+            .touched_paths(&files(&[
+                "client/consensus/babe/Cargo.toml",
+                "frame/staking/src/lib.rs"
+            ]))
+            .is_empty());
+        assert!(!Filter::default().has_paths());
+    }
+}
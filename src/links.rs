@@ -0,0 +1,627 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Links between PRs and issues across repositories.
+//!
+//! Companion references like `polkadot companion: paritytech/polkadot#1234` and closing keywords
+//! like `Fixes #123` are parsed from PR bodies, links going out of every PR are kept in sled
+//! with a reverse index of links coming in, and PRs linked to each other directly or not are
+//! gathered into clusters.
+
+use crate::utils::Repository;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use octocrab::models::{issues, pulls};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+// An issue or a PR, names are lowercased so every one is a single node of the graph.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct Reference {
+    pub organization: String,
+    pub repository: String,
+    pub number: u64,
+}
+
+impl Reference {
+    pub fn new(org: &str, repo: &str, number: u64) -> Self {
+        Self {
+            organization: org.to_lowercase(),
+            repository: repo.to_lowercase(),
+            number,
+        }
+    }
+
+    // GitHub redirects it to the PR if the number is a PR, `html_root` is like `https://github.com/`.
+    pub fn html_url(&self, html_root: &str) -> String {
+        format!(
+            "{html_root}{}/{}/issues/{}",
+            self.organization, self.repository, self.number
+        )
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}#{}",
+            self.organization, self.repository, self.number
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    // Companions go both ways, whichever of them mentions the other.
+    Companion,
+    // The PR closes the issue, or the PR, once it's merged.
+    Closes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Link {
+    pub kind: LinkKind,
+    pub from: Reference,
+    pub to: Reference,
+}
+
+impl Link {
+    // The end of the link which isn't `reference`.
+    pub fn other(&self, reference: &Reference) -> &Reference {
+        match self.from == *reference {
+            true => &self.to,
+            false => &self.from,
+        }
+    }
+}
+
+// State of a member of a cluster, from what's archived in sled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemberState {
+    Open,
+    Merged,
+    Closed,
+    Issue,
+    // Not archived, the repository may not be subscribed.
+    Unknown,
+}
+
+impl MemberState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Merged => "merged",
+            Self::Closed => "closed",
+            Self::Issue => "issue",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClusterMember {
+    pub reference: Reference,
+    pub state: MemberState,
+    pub title: Option<String>,
+    pub merged_at: Option<DateTime<Utc>>,
+    pub html_url: String,
+}
+
+// Like `#123`, `substrate#123`, `paritytech/substrate#123` or a link to the issue or the PR
+// on the host, whose web root is put in place of `{html_root}`.
+const REFERENCE: &str = r"(?:{html_root}(?P<url_org>[\w.-]+)/(?P<url_repo>[\w.-]+)/(?:pull|issues)/(?P<url_number>\d+)|(?:(?:(?P<org>[\w.-]+)/)?(?P<repo>[\w.-]+))?#(?P<number>\d+))";
+
+// Parses links of PRs on a host, short references are resolved against repositories it knows.
+pub struct LinkParser {
+    references: Regex,
+    closing: Regex,
+    companion: Regex,
+    // Lowercased names, like `polkadot` of `polkadot companion: #1234`.
+    repositories: BTreeSet<String>,
+}
+
+impl LinkParser {
+    // `html_root` is like `https://github.com/`, repositories are the subscribed ones.
+    pub fn new<'a>(html_root: &str, repositories: impl IntoIterator<Item = &'a str>) -> Self {
+        let reference = REFERENCE.replace("{html_root}", &regex::escape(html_root));
+        Self {
+            references: Regex::new(&reference).expect("Valid regex."),
+            closing: Regex::new(&format!(
+                r"(?i)\b(?:close[sd]?|fix(?:e[sd])?|resolve[sd]?):?\s+{reference}"
+            ))
+            .expect("Valid regex."),
+            // `companion: #1234` or `companion for #1234`, but not `needs a companion` in a sentence.
+            companion: Regex::new(r"(?i)(?:\b([\w.-]+)\s+)?companion(?:\s*:|\s+for\b)")
+                .expect("Valid regex."),
+            repositories: repositories
+                .into_iter()
+                .map(|repo| repo.to_lowercase())
+                .collect(),
+        }
+    }
+
+    // The word names a known repository, `Needs companion` doesn't.
+    fn known(&self, word: &str) -> bool {
+        self.repositories.contains(&word.to_lowercase())
+    }
+
+    fn reference(&self, captures: &Captures, org: &str, repo: &str) -> Option<Reference> {
+        let group = |name: &str| captures.name(name).map(|m| m.as_str());
+        if let Some(number) = group("url_number") {
+            return Some(Reference::new(
+                group("url_org")?,
+                group("url_repo")?,
+                number.parse().ok()?,
+            ));
+        }
+
+        // `substrate#123` is of substrate in the same organization if it's known.
+        let repo = match (group("org"), group("repo")) {
+            (Some(_), Some(repo)) => repo,
+            (None, Some(repo)) if self.known(repo) => repo,
+            _ => repo,
+        };
+        Some(Reference::new(
+            group("org").unwrap_or(org),
+            repo,
+            group("number")?.parse().ok()?,
+        ))
+    }
+
+    // A companion is of another repository, a short reference on a companion line must name
+    // a subscribed one, by itself like `cumulus#1850` or before `companion` like `cumulus companion: #1850`.
+    // References naming no repository we know are dropped rather than taken as of the PR's own.
+    fn companion_reference(
+        &self,
+        captures: &Captures,
+        org: &str,
+        named: Option<&str>,
+    ) -> Option<Reference> {
+        let group = |name: &str| captures.name(name).map(|m| m.as_str());
+        match (group("url_number"), group("org"), group("repo"), named) {
+            (Some(_), ..) | (_, Some(_), ..) => self.reference(captures, org, ""),
+            (_, _, Some(repo), _) if self.known(repo) => self.reference(captures, org, repo),
+            (_, _, None, Some(named)) => self.reference(captures, org, named),
+            _ => None,
+        }
+    }
+
+    // Links going out of PR `number` of `org/repo`, in order of its body.
+    pub fn parse(&self, org: &str, repo: &str, number: u64, body: &str) -> Vec<Link> {
+        let from = Reference::new(org, repo, number);
+        let mut links: Vec<Link> = vec![];
+        for line in body.lines() {
+            if let Some(captures) = self.companion.captures(line) {
+                // `#1234` on `polkadot companion: #1234` is a PR of polkadot in the same organization.
+                let named = captures
+                    .get(1)
+                    .map(|m| m.as_str())
+                    .filter(|word| self.known(word));
+                for captures in self.references.captures_iter(line) {
+                    if let Some(to) = self.companion_reference(&captures, org, named) {
+                        links.push(Link {
+                            kind: LinkKind::Companion,
+                            from: from.clone(),
+                            to,
+                        });
+                    }
+                }
+            }
+            for captures in self.closing.captures_iter(line) {
+                if let Some(to) = self.reference(&captures, org, repo) {
+                    links.push(Link {
+                        kind: LinkKind::Closes,
+                        from: from.clone(),
+                        to,
+                    });
+                }
+            }
+        }
+        let mut seen = vec![];
+        links.retain(|link| {
+            let new = link.to != from && !seen.contains(&(link.kind, link.to.clone()));
+            seen.push((link.kind, link.to.clone()));
+            new
+        });
+
+        links
+    }
+}
+
+fn links_key(from: &Reference) -> String {
+    format!(
+        "links#{}/{}#{}",
+        from.organization, from.repository, from.number
+    )
+}
+
+// Like `backlinks#paritytech/polkadot#6320#`, links coming into the reference are under it.
+fn backlinks_prefix(to: &Reference) -> String {
+    format!(
+        "backlinks#{}/{}#{}#",
+        to.organization, to.repository, to.number
+    )
+}
+
+fn get_outgoing(db: &Db, from: &Reference) -> Result<Vec<Link>> {
+    match db.get(links_key(from))? {
+        Some(links) => Ok(serde_json::from_slice(&links)?),
+        None => Ok(vec![]),
+    }
+}
+
+// Links going out of the PR are replaced, its body may be edited, and so is the reverse index.
+pub fn store_links(db: &Db, from: &Reference, links: &[Link]) -> Result<()> {
+    let mut batch = sled::Batch::default();
+    for link in get_outgoing(db, from)? {
+        batch.remove(format!("{}{from}", backlinks_prefix(&link.to)).as_bytes());
+    }
+    for link in links {
+        batch.insert(
+            format!("{}{from}", backlinks_prefix(&link.to)).as_bytes(),
+            serde_json::to_vec(link)?,
+        );
+    }
+    batch.insert(links_key(from).as_bytes(), serde_json::to_vec(links)?);
+    db.apply_batch(batch)?;
+
+    Ok(())
+}
+
+fn all_links(db: &Db) -> Result<Vec<Link>> {
+    let mut links = vec![];
+    for value in db.scan_prefix("links#").values() {
+        links.extend(serde_json::from_slice::<Vec<Link>>(&value?)?);
+    }
+
+    Ok(links)
+}
+
+// Links going out of the reference and coming into it.
+pub fn get_links(db: &Db, reference: &Reference) -> Result<Vec<Link>> {
+    let mut links = get_outgoing(db, reference)?;
+    for link in db.scan_prefix(backlinks_prefix(reference)).values() {
+        links.push(serde_json::from_slice(&link?)?);
+    }
+
+    Ok(links)
+}
+
+// Parse and store links going out of the PR.
+pub fn archive_links(
+    db: &Db,
+    parser: &LinkParser,
+    org: &str,
+    repo: &str,
+    pr: &pulls::PullRequest,
+) -> Result<()> {
+    let from = Reference::new(org, repo, pr.number);
+    let links = parser.parse(org, repo, pr.number, pr.body.as_deref().unwrap_or_default());
+    store_links(db, &from, &links)
+}
+
+// Parse and store links of the PR, then return all of its links.
+pub fn update_links(
+    db: &Db,
+    parser: &LinkParser,
+    org: &str,
+    repo: &str,
+    pr: &pulls::PullRequest,
+) -> Result<Vec<Link>> {
+    archive_links(db, parser, org, repo, pr)?;
+    get_links(db, &Reference::new(org, repo, pr.number))
+}
+
+// Other ends of companion links of the reference.
+pub fn companions<'a>(reference: &Reference, links: &'a [Link]) -> Vec<&'a Reference> {
+    links
+        .iter()
+        .filter(|link| link.kind == LinkKind::Companion)
+        .filter(|link| link.from == *reference || link.to == *reference)
+        .map(|link| link.other(reference))
+        .collect()
+}
+
+// What the reference closes.
+pub fn closes<'a>(reference: &Reference, links: &'a [Link]) -> Vec<&'a Reference> {
+    links
+        .iter()
+        .filter(|link| link.kind == LinkKind::Closes && link.from == *reference)
+        .map(|link| &link.to)
+        .collect()
+}
+
+// Connected components of the graph with two members at least.
+pub fn get_clusters(db: &Db) -> Result<Vec<BTreeSet<Reference>>> {
+    let mut neighbours: HashMap<Reference, Vec<Reference>> = HashMap::new();
+    for link in all_links(db)? {
+        neighbours
+            .entry(link.from.clone())
+            .or_default()
+            .push(link.to.clone());
+        neighbours.entry(link.to).or_default().push(link.from);
+    }
+
+    let mut clusters = vec![];
+    let mut visited = BTreeSet::new();
+    let mut nodes = neighbours.keys().cloned().collect::<Vec<_>>();
+    nodes.sort();
+    for node in nodes {
+        if visited.contains(&node) {
+            continue;
+        }
+        let mut cluster = BTreeSet::new();
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            if !visited.insert(node.clone()) {
+                continue;
+            }
+            pending.extend(neighbours[&node].iter().cloned());
+            cluster.insert(node);
+        }
+        if cluster.len() > 1 {
+            clusters.push(cluster);
+        }
+    }
+
+    Ok(clusters)
+}
+
+fn get_archived<T: serde::de::DeserializeOwned>(db: &Db, key: &str) -> Result<Option<T>> {
+    match db.get(key)? {
+        Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+        None => Ok(None),
+    }
+}
+
+// Look the reference up in what's archived of subscribed repositories.
+pub fn cluster_member(
+    db: &Db,
+    repositories: &[Repository],
+    html_root: &str,
+    reference: &Reference,
+) -> Result<ClusterMember> {
+    let mut member = ClusterMember {
+        reference: reference.clone(),
+        state: MemberState::Unknown,
+        title: None,
+        merged_at: None,
+        html_url: reference.html_url(html_root),
+    };
+    // Keys in sled are named as repositories in config.
+    let repository = repositories.iter().find(|repo| {
+        repo.organization
+            .eq_ignore_ascii_case(&reference.organization)
+            && repo.repository.eq_ignore_ascii_case(&reference.repository)
+    });
+    let (org, repo) = match repository {
+        Some(repository) => (&repository.organization, &repository.repository),
+        None => return Ok(member),
+    };
+
+    let number = reference.number;
+    for (state, kind) in [
+        ("merged", MemberState::Merged),
+        ("open", MemberState::Open),
+        ("closed", MemberState::Closed),
+    ] {
+        let key = format!("{org}#{repo}#prs#{state}#{number}");
+        if let Some(pr) = get_archived::<pulls::PullRequest>(db, &key)? {
+            member.state = kind;
+            member.title = pr.title;
+            member.merged_at = pr.merged_at;
+            if let Some(url) = pr.html_url {
+                member.html_url = url.to_string();
+            }
+            return Ok(member);
+        }
+    }
+    for state in ["open", "closed"] {
+        let key = format!("{org}#{repo}#issues#{state}#{number}");
+        if let Some(issue) = get_archived::<issues::Issue>(db, &key)? {
+            member.state = MemberState::Issue;
+            member.title = Some(issue.title);
+            member.html_url = issue.html_url.to_string();
+            return Ok(member);
+        }
+    }
+
+    Ok(member)
+}
+
+// Clusters with two merged PRs at least, by when their first PR is merged.
+pub fn get_merged_clusters(
+    db: &Db,
+    repositories: &[Repository],
+    html_root: &str,
+) -> Result<Vec<Vec<ClusterMember>>> {
+    let mut clusters = vec![];
+    for cluster in get_clusters(db)? {
+        let members = cluster
+            .iter()
+            .map(|reference| cluster_member(db, repositories, html_root, reference))
+            .collect::<Result<Vec<_>>>()?;
+        let merged = members
+            .iter()
+            .filter(|member| member.state == MemberState::Merged)
+            .count();
+        if merged > 1 {
+            clusters.push(members);
+        }
+    }
+    clusters.sort_by_key(|members| members.iter().filter_map(|member| member.merged_at).min());
+
+    Ok(clusters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    fn parser() -> LinkParser {
+        LinkParser::new("https://github.com/", ["substrate", "Polkadot", "cumulus"])
+    }
+
+    #[test]
+    fn links_should_be_parsed_from_bodies() {
+        let parser = parser();
+        let body = "Fixes #12690, closes paritytech/polkadot#6100\n\n\
+            polkadot companion: paritytech/polkadot#6320\n\
+            cumulus companion: #1850\n\
+            The companion for https://github.com/paritytech/substrate/pull/12600\n\
+            Mentions #12000, fixes #12690 again.";
+        let links = parser
+            .parse("paritytech", "substrate", 12700, body)
+            .into_iter()
+            .map(|link| (link.kind, link.to.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            vec![
+                (LinkKind::Closes, "paritytech/substrate#12690".to_owned()),
+                (LinkKind::Closes, "paritytech/polkadot#6100".to_owned()),
+                (LinkKind::Companion, "paritytech/polkadot#6320".to_owned()),
+                (LinkKind::Companion, "paritytech/cumulus#1850".to_owned()),
+                (LinkKind::Companion, "paritytech/substrate#12600".to_owned()),
+            ]
+        );
+
+        // `substrate#12700` on polkadot is a PR of substrate.
+        let links = parser.parse(
+            "paritytech",
+            "polkadot",
+            6320,
+            "Companion for substrate#12700",
+        );
+        assert_eq!(
+            links[0].to,
+            Reference::new("paritytech", "substrate", 12700)
+        );
+        assert!(parser
+            .parse("paritytech", "polkadot", 6320, "Fixes #6320")
+            .is_empty());
+
+        // Words which aren't subscribed repositories are no repositories, and companion
+        // references naming none of them are dropped.
+        let links = parser.parse(
+            "paritytech",
+            "polkadot",
+            6320,
+            "Needs companion #6330\n\
+            frontier companion: #6331\n\
+            Companion for #6332 and unknown#6333\n\
+            Fixes PR#6350",
+        );
+        let targets = links
+            .iter()
+            .map(|link| link.to.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(targets, vec!["paritytech/polkadot#6350"]);
+
+        // Links are of the configured host.
+        let parser = LinkParser::new("https://git.example.com/", ["substrate"]);
+        let links = parser.parse(
+            "paritytech",
+            "polkadot",
+            6320,
+            "Companion for https://git.example.com/paritytech/substrate/pull/12700 \
+            and https://github.com/paritytech/substrate/pull/12800",
+        );
+        assert_eq!(links.len(), 1);
+        assert_eq!(
+            links[0].to.html_url("https://git.example.com/"),
+            "https://git.example.com/paritytech/substrate/issues/12700"
+        );
+    }
+
+    #[tokio::test]
+    async fn linked_prs_should_be_clustered() {
+        let mock = MockGithub::start(&["github"]).await;
+        let db = temporary_db();
+        let parser = parser();
+        let repositories = ["substrate", "polkadot", "cumulus"]
+            .iter()
+            .map(|repo| Repository {
+                organization: "paritytech".to_owned(),
+                repository: repo.to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        for (repo, number) in [("substrate", 12700), ("polkadot", 6320), ("cumulus", 1850)] {
+            let pr = crate::subcribe_prs::get_pr_by_id(mock.client(), "paritytech", repo, number)
+                .await
+                .unwrap();
+            crate::db::insert_one_pr(db.clone(), &format!("paritytech#{repo}#prs#merged"), &pr)
+                .await
+                .unwrap();
+            update_links(&db, &parser, "paritytech", repo, &pr).unwrap();
+        }
+
+        // Both companions mention substrate#12700, it's linked to either of them once.
+        let substrate = Reference::new("paritytech", "substrate", 12700);
+        let links = get_links(&db, &substrate).unwrap();
+        let linked = companions(&substrate, &links)
+            .into_iter()
+            .map(|reference| reference.to_string())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            linked,
+            BTreeSet::from([
+                "paritytech/cumulus#1850".to_owned(),
+                "paritytech/polkadot#6320".to_owned()
+            ])
+        );
+        assert_eq!(
+            closes(&substrate, &links),
+            vec![&Reference::new("paritytech", "substrate", 12690)]
+        );
+
+        let clusters = get_merged_clusters(&db, &repositories, "https://github.com/").unwrap();
+        assert_eq!(clusters.len(), 1);
+        let states = clusters[0]
+            .iter()
+            .map(|member| (member.reference.to_string(), member.state))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            vec![
+                ("paritytech/cumulus#1850".to_owned(), MemberState::Merged),
+                ("paritytech/polkadot#6320".to_owned(), MemberState::Merged),
+                (
+                    "paritytech/substrate#12690".to_owned(),
+                    MemberState::Unknown
+                ),
+                ("paritytech/substrate#12700".to_owned(), MemberState::Merged),
+            ]
+        );
+
+        // Editing the body of a companion drops its link coming into substrate#12700.
+        let polkadot = Reference::new("paritytech", "polkadot", 6320);
+        let incoming = |links: &[Link]| links.iter().filter(|link| link.from == polkadot).count();
+        assert_eq!(incoming(&links), 1);
+        store_links(&db, &polkadot, &[]).unwrap();
+        assert_eq!(incoming(&get_links(&db, &substrate).unwrap()), 0);
+    }
+}
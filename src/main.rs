@@ -19,30 +19,81 @@
 use anyhow::Result;
 use clap::Parser;
 
+mod changelog;
 mod cli;
 mod db;
-// mod discord_bot;
+mod diff;
+mod discord_bot;
+mod discover;
+mod divergence;
+mod filter;
+mod links;
+mod migrations;
+#[cfg(test)]
+mod mock_github;
+mod patches;
+mod pins;
+mod release_notes;
+mod relevance;
+mod retry;
+mod subcribe_commits;
+mod subcribe_graphql;
 mod subcribe_issues;
 mod subcribe_prs;
 mod subcribe_releases;
+mod sync;
 mod utils;
+mod watchlist;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = cli::DiscordBotCli::parse();
     let config = utils::read_config()?;
+    retry::initialise(utils::get_retry_policy(&config)?);
 
     match cli.get {
         Some(cli::Commands::Issue(args)) => {
-            crate::cli::generate_issue_csv_report(&args).await?;
+            crate::cli::generate_issue_csv_report(&args, &config).await?;
         }
         Some(cli::Commands::Pr(args)) => {
-            crate::cli::generate_pr_csv_report(&args).await?;
+            crate::cli::generate_pr_csv_report(&args, &config).await?;
         }
-        None => (),
+        Some(cli::Commands::Sync(args)) => {
+            crate::cli::sync_repositories(&args, &config).await?;
+        }
+        Some(cli::Commands::Release(args)) => {
+            crate::cli::generate_release_csv_report(&args, &config).await?;
+        }
+        Some(cli::Commands::Commits(args)) => {
+            crate::cli::generate_commit_csv_report(&args, &config).await?;
+        }
+        Some(cli::Commands::Changelog(args)) => {
+            crate::cli::generate_changelog(&args, &config).await?;
+        }
+        Some(cli::Commands::Forks(args)) => {
+            crate::cli::generate_missing_pr_csv_report(&args, &config).await?;
+        }
+        Some(cli::Commands::Discover(args)) => {
+            crate::cli::discover(&args)?;
+        }
+        Some(cli::Commands::Status(cli::StatusCommands::Pins(args))) => {
+            crate::cli::generate_pin_csv_report(&args, &config).await?;
+        }
+        Some(cli::Commands::Clusters(args)) => {
+            crate::cli::generate_cluster_csv_report(&args, &config)?;
+        }
+        Some(cli::Commands::Migrations(args)) => {
+            crate::cli::generate_migration_csv_report(&args, &config).await?;
+        }
+        Some(cli::Commands::Patches(args)) => {
+            crate::cli::export_patches(&args, &config).await?;
+        }
+        Some(cli::Commands::Watch(args)) => {
+            crate::cli::watch(&args, &config).await?;
+        }
+        // Without a subcommand, it runs as the bot.
+        None => discord_bot::discord_bot(&config).await,
     }
 
-    // discord_bot::discord_bot(&config).await;
-
     Ok(())
 }
@@ -0,0 +1,358 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Likely runtime migrations and storage changes of merged PRs.
+//!
+//! Labels, changed paths and added lines of a merged PR are checked by heuristics.
+//! Signals of changed files are kept in sled, files of a merged PR never change,
+//! but its labels may, so they're checked again every time.
+
+use crate::subcribe_prs::ChangedFile;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use octocrab::{
+    models::{pulls, Label},
+    Octocrab,
+};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use std::{collections::HashSet, fmt, sync::Arc};
+
+// Added lines with any of them implement or bump something run at runtime upgrades.
+pub const CODE_KEYWORDS: [&str; 4] = [
+    "StorageVersion",
+    "storage_version",
+    "OnRuntimeUpgrade",
+    "on_runtime_upgrade",
+];
+
+// Why a PR is a likely migration.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum Signal {
+    // Like `E0-runtime_migration`.
+    Label(String),
+    // Like `frame/staking/src/migrations.rs`.
+    Path(String),
+    // One of `CODE_KEYWORDS` on added lines.
+    Code(String),
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Label(label) => write!(f, "label {label}"),
+            Self::Path(path) => write!(f, "path {path}"),
+            Self::Code(keyword) => write!(f, "code {keyword}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationCheck {
+    pub number: u64,
+    pub title: String,
+    pub author: String,
+    pub merged_at: Option<DateTime<Utc>>,
+    pub html_url: String,
+    // Nothing if it's unlikely a migration.
+    pub signals: Vec<Signal>,
+}
+
+impl MigrationCheck {
+    pub fn is_likely(&self) -> bool {
+        !self.signals.is_empty()
+    }
+}
+
+// Labels mentioning migrations, or in `migration-labels` of the repository.
+pub fn label_signals(labels: &[Label], migration_labels: &[String]) -> Vec<Signal> {
+    labels
+        .iter()
+        .filter(|label| {
+            label.name.to_lowercase().contains("migration")
+                || migration_labels.contains(&label.name)
+        })
+        .map(|label| Signal::Label(label.name.clone()))
+        .collect()
+}
+
+fn is_migration_path(path: &str) -> bool {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    matches!(file, "migration.rs" | "migrations.rs")
+        || path
+            .split('/')
+            .any(|dir| dir == "migration" || dir == "migrations")
+}
+
+// Like `migrations.rs`, or anything under `migration/`, renamed files count by both names.
+pub fn path_signals(files: &[ChangedFile]) -> Vec<Signal> {
+    let mut signals = vec![];
+    for file in files {
+        for path in file.previous_filename.iter().chain([&file.filename]) {
+            let signal = Signal::Path(path.clone());
+            if is_migration_path(path) && !signals.contains(&signal) {
+                signals.push(signal);
+            }
+        }
+    }
+
+    signals
+}
+
+// Keywords of `CODE_KEYWORDS` found on added lines, removed ones are not migrations.
+pub fn code_signals(files: &[ChangedFile]) -> Vec<Signal> {
+    let mut found = HashSet::new();
+    for patch in files.iter().filter_map(|file| file.patch.as_deref()) {
+        for line in patch.lines() {
+            if !line.starts_with('+') || line.starts_with("+++") {
+                continue;
+            }
+            for keyword in CODE_KEYWORDS {
+                if line.contains(keyword) {
+                    found.insert(keyword);
+                }
+            }
+        }
+    }
+
+    CODE_KEYWORDS
+        .iter()
+        .filter(|keyword| found.contains(*keyword))
+        .map(|keyword| Signal::Code(keyword.to_string()))
+        .collect()
+}
+
+fn file_signals_key(org: &str, repo: &str, number: u64) -> String {
+    format!("{org}#{repo}#migrations#{number}")
+}
+
+// Path and code signals of the PR, None if its changed files are not checked yet.
+pub fn get_file_signals(
+    db: &Db,
+    org: &str,
+    repo: &str,
+    number: u64,
+) -> Result<Option<Vec<Signal>>> {
+    match db.get(file_signals_key(org, repo, number))? {
+        Some(signals) => Ok(Some(serde_json::from_slice(&signals)?)),
+        None => Ok(None),
+    }
+}
+
+// Changed files are fetched only once per PR, labels come from the PR as it is now.
+pub async fn check_pr(
+    octocrab: Arc<Octocrab>,
+    db: &Db,
+    org: &str,
+    repo: &str,
+    pr: &pulls::PullRequest,
+    migration_labels: &[String],
+) -> Result<MigrationCheck> {
+    let mut signals = label_signals(pr.labels.as_deref().unwrap_or_default(), migration_labels);
+    match get_file_signals(db, org, repo, pr.number)? {
+        Some(mut file_signals) => signals.append(&mut file_signals),
+        None => {
+            let files = crate::subcribe_prs::get_pr_files(octocrab, org, repo, pr.number).await?;
+            let mut file_signals = path_signals(&files);
+            file_signals.append(&mut code_signals(&files));
+            db.insert(
+                file_signals_key(org, repo, pr.number),
+                serde_json::to_vec(&file_signals)?,
+            )?;
+            signals.append(&mut file_signals);
+        }
+    }
+    let check = MigrationCheck {
+        number: pr.number,
+        title: pr.title.clone().unwrap_or_default(),
+        author: pr
+            .user
+            .as_ref()
+            .map(|user| user.login.clone())
+            .unwrap_or_default(),
+        merged_at: pr.merged_at,
+        html_url: pr
+            .html_url
+            .as_ref()
+            .map(|url| url.to_string())
+            .unwrap_or_default(),
+        signals,
+    };
+
+    Ok(check)
+}
+
+// Likely migrations merged between `since` and `to_ref`, in order of commits.
+pub async fn migrations_since(
+    octocrab: Arc<Octocrab>,
+    db: &Db,
+    org: &str,
+    repo: &str,
+    since: &str,
+    to_ref: &str,
+    migration_labels: &[String],
+) -> Result<Vec<MigrationCheck>> {
+    let commits =
+        crate::subcribe_commits::get_commits_between(octocrab.clone(), org, repo, since, to_ref)
+            .await?;
+
+    let numbers = crate::subcribe_commits::get_commit_pr_numbers(
+        octocrab.clone(),
+        org,
+        repo,
+        &commits,
+        crate::subcribe_commits::MAX_PR_LOOKUPS,
+    )
+    .await?;
+
+    let mut migrations = vec![];
+    // Commits of a merged PR share it.
+    let mut seen = HashSet::new();
+    for number in numbers.into_iter().flatten() {
+        if !seen.insert(number) {
+            continue;
+        }
+
+        let pr = crate::subcribe_prs::get_pr_by_id(octocrab.clone(), org, repo, number).await?;
+        let check = check_pr(octocrab.clone(), db, org, repo, &pr, migration_labels).await?;
+        if check.is_likely() {
+            migrations.push(check);
+        }
+    }
+
+    Ok(migrations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    #[test]
+    fn paths_and_added_lines_should_be_signals() {
+        let file = |filename: &str, previous: Option<&str>, patch: &str| ChangedFile {
+            filename: filename.to_owned(),
+            previous_filename: previous.map(str::to_owned),
+            patch: Some(patch.to_owned()),
+        };
+        let files = [
+            file(
+                "frame/staking/src/migrations.rs",
+                None,
+                "@@ -1,1 +1,2 @@\n+const STORAGE_VERSION: StorageVersion = StorageVersion::new(13);",
+            ),
+            file(
+                "frame/nis/src/migration/v1.rs",
+                Some("frame/gilt/src/migration/v1.rs"),
+                "@@ -1,2 +1,1 @@\n-impl OnRuntimeUpgrade for MigrateToV1 {}",
+            ),
+            file("frame/migrationless/src/lib.rs", None, "+++ b/OnRuntimeUpgrade"),
+        ];
+        assert_eq!(
+            path_signals(&files),
+            vec![
+                Signal::Path("frame/staking/src/migrations.rs".to_owned()),
+                Signal::Path("frame/gilt/src/migration/v1.rs".to_owned()),
+                Signal::Path("frame/nis/src/migration/v1.rs".to_owned()),
+            ]
+        );
+        // Removed lines and file headers are not migrations.
+        assert_eq!(
+            code_signals(&files),
+            vec![Signal::Code("StorageVersion".to_owned())]
+        );
+    }
+
+    #[tokio::test]
+    async fn migrations_since_ref_should_be_reported() {
+        let mock = MockGithub::start(&["github"]).await;
+        let db = temporary_db();
+        let migrations = migrations_since(
+            mock.client(),
+            &db,
+            "paritytech",
+            "polkadot",
+            "v0.9.32",
+            "v0.9.33",
+            &["T1-runtime".to_owned()],
+        )
+        .await
+        .unwrap();
+        let signals = migrations
+            .iter()
+            .map(|check| (check.number, check.signals.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            signals,
+            vec![
+                (
+                    6300,
+                    vec![
+                        Signal::Label("T1-runtime".to_owned()),
+                        Signal::Code("OnRuntimeUpgrade".to_owned()),
+                        Signal::Code("on_runtime_upgrade".to_owned()),
+                    ]
+                ),
+                (
+                    6320,
+                    vec![
+                        Signal::Path("runtime/common/src/crowdloan/migrations.rs".to_owned()),
+                        Signal::Path("runtime/common/src/crowdloan/migration.rs".to_owned()),
+                    ]
+                ),
+            ]
+        );
+
+        // Signals of changed files are kept, empty ones too, but labels are not.
+        assert_eq!(
+            get_file_signals(&db, "paritytech", "polkadot", 6310).unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(
+            get_file_signals(&db, "paritytech", "polkadot", 6300)
+                .unwrap()
+                .unwrap()
+                .len(),
+            2
+        );
+        let requests = mock.requests().len();
+        let migrations = migrations_since(
+            mock.client(),
+            &db,
+            "paritytech",
+            "polkadot",
+            "v0.9.32",
+            "v0.9.33",
+            &[],
+        )
+        .await
+        .unwrap();
+        // `T1-runtime` is no migration label any more, and changed files are not fetched again.
+        assert_eq!(
+            migrations[0].signals,
+            vec![
+                Signal::Code("OnRuntimeUpgrade".to_owned()),
+                Signal::Code("on_runtime_upgrade".to_owned()),
+            ]
+        );
+        assert!(mock
+            .requests()
+            .iter()
+            .skip(requests)
+            .all(|request| !request.contains("/files")));
+    }
+}
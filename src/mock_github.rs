@@ -0,0 +1,444 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A local stand-in of GitHub API for tests, it replays JSON fixtures under `fixtures/`.
+//!
+//! `GET /repos/org/repo/pulls` is answered by `repos/org/repo/pulls.json`,
+//! `GET /repos/org/repo/pulls?page=2` by `repos/org/repo/pulls.page-2.json`,
+//! and a `Link` header points to the next page if it exists.
+//! `GET /repos/org/repo/pulls/1` accepting `application/vnd.github.v3.patch` is answered by
+//! `repos/org/repo/pulls/1.patch`, and so is `.diff`.
+//! `GET /search/issues?q=is:pr merged:2022-11-24` is answered by
+//! `search/issues/is_pr_merged_2022-11-24.json`, so every query has its own results.
+//! `POST /graphql` is answered by `graphql.json`.
+//!
+//! Fixtures are looked up in layers, so a test can override some of them for the next round.
+//! A test can also make a page fail several times by `MockGithub::fail`.
+//! Run tests with `RECORD_GITHUB_FIXTURES=1` to record missing fixtures from api.github.com.
+
+use octocrab::Octocrab;
+use sled::Db;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+const GITHUB_API: &str = "https://api.github.com/";
+
+pub struct MockGithub {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+    failures: Arc<Mutex<Vec<Failure>>>,
+    server: JoinHandle<()>,
+}
+
+struct Failure {
+    path: String,
+    page: u32,
+    status: u16,
+    times: u32,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    // `json`, or `patch` and `diff` if they're accepted.
+    extension: &'static str,
+}
+
+impl MockGithub {
+    // Layers are directories under `fixtures/`, the first one goes first.
+    pub async fn start(layers: &[&str]) -> Self {
+        let layers = layers
+            .iter()
+            .map(|layer| {
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("fixtures")
+                    .join(layer)
+            })
+            .collect::<Vec<_>>();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let failures = Arc::new(Mutex::new(vec![]));
+
+        let server = {
+            let (base_url, requests, failures) =
+                (base_url.clone(), requests.clone(), failures.clone());
+            let layers = Arc::new(layers);
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let (base_url, requests, failures, layers) = (
+                        base_url.clone(),
+                        requests.clone(),
+                        failures.clone(),
+                        layers.clone(),
+                    );
+                    tokio::spawn(async move {
+                        let _ = serve(stream, &base_url, &layers, &requests, &failures).await;
+                    });
+                }
+            })
+        };
+
+        Self {
+            base_url,
+            requests,
+            failures,
+            server,
+        }
+    }
+
+    // Answer the page of `path`(like `repos/org/repo/pulls`) with `status` for the next `times` requests.
+    pub fn fail(&self, path: &str, page: u32, status: u16, times: u32) {
+        self.failures.lock().unwrap().push(Failure {
+            path: path.to_owned(),
+            page,
+            status,
+            times,
+        });
+    }
+
+    pub fn client(&self) -> Arc<Octocrab> {
+        crate::utils::build_github_client(Some(&self.base_url), None).unwrap()
+    }
+
+    // Every request as `METHOD path?query`.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockGithub {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+// A sled db in a temporary directory, it's removed once dropped.
+pub fn temporary_db() -> Arc<Db> {
+    Arc::new(sled::Config::default().temporary(true).open().unwrap())
+}
+
+fn fixture_path(layer: &Path, path: &str, page: u32, extension: &str) -> PathBuf {
+    match page {
+        1 => layer.join(format!("{path}.{extension}")),
+        page => layer.join(format!("{path}.page-{page}.{extension}")),
+    }
+}
+
+// Search results depend on `q`, it's kept in the name with characters other than
+// letters, digits, `-` and `.` replaced by `_`.
+fn fixture_name(path: &str, query: &str) -> String {
+    let q = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("q="))
+        .filter(|_| path.starts_with("search/"));
+    match q {
+        Some(q) => {
+            let q = decode_query(q)
+                .chars()
+                .map(
+                    |c| match c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                        true => c,
+                        false => '_',
+                    },
+                )
+                .collect::<String>();
+            format!("{path}/{q}")
+        }
+        None => path.to_owned(),
+    }
+}
+
+// Undo `application/x-www-form-urlencoded`, like `+` for spaces and `%3A` for `:`.
+fn decode_query(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match value
+                .get(i + 1..i + 3)
+                .map(|hex| u8::from_str_radix(hex, 16))
+            {
+                Some(Ok(byte)) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn page_of(query: &str) -> u32 {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("page="))
+        .and_then(|page| page.parse().ok())
+        .unwrap_or(1)
+}
+
+fn with_page(query: &str, page: u32) -> String {
+    let mut pairs = query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("page="))
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    pairs.push(format!("page={page}"));
+    pairs.join("&")
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Request> {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or_default();
+    // Drain the body, no fixture depends on it.
+    let mut body_len = buf.len() - header_end;
+    while body_len < content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        body_len += n;
+    }
+
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_owned();
+    let target = request_line.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let accept = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("accept"))
+        .map(|(_, value)| value.trim())
+        .unwrap_or_default();
+    let extension = match accept {
+        accept if accept.ends_with(".patch") => "patch",
+        accept if accept.ends_with(".diff") => "diff",
+        _ => "json",
+    };
+
+    Ok(Request {
+        method,
+        path: path.trim_matches('/').to_owned(),
+        query: query.to_owned(),
+        extension,
+    })
+}
+
+async fn serve(
+    mut stream: TcpStream,
+    base_url: &str,
+    layers: &[PathBuf],
+    requests: &Mutex<Vec<String>>,
+    failures: &Mutex<Vec<Failure>>,
+) -> std::io::Result<()> {
+    let Request {
+        method,
+        path,
+        query,
+        extension,
+    } = read_request(&mut stream).await?;
+    requests
+        .lock()
+        .unwrap()
+        .push(format!("{method} /{path}?{query}"));
+
+    let page = page_of(&query);
+    let fixture = fixture_name(&path, &query);
+    let failure = failures
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|failure| failure.path == path && failure.page == page && failure.times > 0)
+        .map(|failure| {
+            failure.times -= 1;
+            failure.status
+        });
+    let found = layers
+        .iter()
+        .map(|layer| fixture_path(layer, &fixture, page, extension))
+        .find(|file| file.exists());
+
+    let (status, body, link) = match (failure, found) {
+        (Some(status), _) => (
+            status,
+            format!(r#"{{"message":"Injected failure {status}"}}"#),
+            None,
+        ),
+        (None, Some(file)) => {
+            let body = std::fs::read_to_string(file)?;
+            let link = layers
+                .iter()
+                .any(|layer| fixture_path(layer, &fixture, page + 1, extension).exists())
+                .then(|| {
+                    format!(
+                        "<{base_url}{path}?{}>; rel=\"next\"",
+                        with_page(&query, page + 1)
+                    )
+                });
+            (200, body, link)
+        }
+        // Only JSON fixtures are recorded.
+        (None, None)
+            if std::env::var("RECORD_GITHUB_FIXTURES").is_ok()
+                && method == "GET"
+                && extension == "json" =>
+        {
+            record(base_url, &layers[0], &path, &fixture, &query, page).await
+        }
+        (None, None) => (
+            404,
+            r#"{"message":"Not Found","documentation_url":"https://docs.github.com/rest"}"#
+                .to_owned(),
+            None,
+        ),
+    };
+
+    let mut response = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        match status {
+            200 => "OK",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            422 => "Unprocessable Entity",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
+            _ => "Bad Gateway",
+        },
+        body.len()
+    );
+    if let Some(link) = link {
+        response.push_str(&format!("Link: {link}\r\n"));
+    }
+    response.push_str("\r\n");
+    response.push_str(&body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// Fetch the missing fixture from GitHub and save it into the first layer.
+async fn record(
+    base_url: &str,
+    layer: &Path,
+    path: &str,
+    fixture: &str,
+    query: &str,
+    page: u32,
+) -> (u16, String, Option<String>) {
+    let token = std::env::var("GITHUB_TOKEN").ok();
+    let octocrab = crate::utils::build_github_client(None, token).unwrap();
+    let url = format!("{GITHUB_API}{path}?{query}");
+    let response = match octocrab._get(url, None::<&()>).await {
+        Ok(response) => response,
+        Err(e) => return (502, format!(r#"{{"message":"{e}"}}"#), None),
+    };
+    let link = response
+        .headers()
+        .get("link")
+        .and_then(|link| link.to_str().ok())
+        .map(|link| link.replace(GITHUB_API, base_url));
+    let body = response.text().await.unwrap_or_default();
+
+    // Pretty print fixtures so they can be reviewed and edited by hand.
+    let pretty = serde_json::from_str::<serde_json::Value>(&body)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| body.clone());
+    let file = fixture_path(layer, fixture, page, "json");
+    if let Some(dir) = file.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let _ = std::fs::write(&file, pretty + "\n");
+    println!("Recorded fixture: {file:?}");
+
+    (200, body, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mock_github_should_serve_pages() {
+        let mock = MockGithub::start(&["github"]).await;
+        let octocrab = mock.client();
+
+        let page = octocrab
+            .pulls("paritytech", "substrate")
+            .list()
+            .per_page(100)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(page.items.len(), 4);
+        let next = page.next.clone().unwrap();
+        assert!(next.as_str().starts_with(&mock.base_url));
+
+        let page = octocrab
+            .get_page::<octocrab::models::pulls::PullRequest>(&Some(next))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(page.items.len(), 4);
+        assert!(page.next.is_none());
+
+        assert!(octocrab
+            .pulls("paritytech", "substrate")
+            .get(1)
+            .await
+            .is_err());
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[test]
+    fn page_query_should_work() {
+        assert_eq!(page_of("state=closed&per_page=100"), 1);
+        assert_eq!(page_of("state=closed&page=3"), 3);
+        assert_eq!(
+            with_page("state=closed&page=3&per_page=100", 4),
+            "state=closed&per_page=100&page=4"
+        );
+    }
+}
@@ -0,0 +1,336 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Patches of merged upstream PRs, to port them to our forks.
+//!
+//! Every patch is saved like `6300-fix-maxnominators-to-match-the-staking-limit.patch`,
+//! next to scripts applying them in order of merging, and every export is recorded in sled.
+
+use crate::retry::retry;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use octocrab::{models::pulls, Octocrab};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use std::{fmt::Write, path::Path, sync::Arc};
+
+// Applies patches with `git am`, or diffs with `git apply` and a commit each.
+pub const APPLY_SCRIPT: &str = "apply.sh";
+// Cherry-picks merge commits, upstream should be fetched first.
+pub const CHERRY_PICK_SCRIPT: &str = "cherry-pick.sh";
+
+// Titles are cut in file names, like `git format-patch` does.
+const MAX_SLUG_LEN: usize = 52;
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchFormat {
+    // Commits of the PR with their messages and authors, for `git am`.
+    #[default]
+    Patch,
+    // The whole change of the PR in one diff, for `git apply`.
+    Diff,
+}
+
+impl PatchFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Patch => "patch",
+            Self::Diff => "diff",
+        }
+    }
+}
+
+pub struct ExportOptions<'a> {
+    pub format: PatchFormat,
+    // Patches go to `{dir}/{org}/{repo}`.
+    pub dir: &'a Path,
+    // Fetch PRs exported already again, their files are reused otherwise.
+    pub force: bool,
+}
+
+// What's recorded in sled for an exported PR.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExportedPatch {
+    pub number: u64,
+    pub title: String,
+    pub merged_at: Option<DateTime<Utc>>,
+    pub merge_commit_sha: Option<String>,
+    pub format: PatchFormat,
+    // Under the output directory, like `paritytech/polkadot/6300-fix-maxnominators.patch`.
+    pub file: String,
+    pub exported_at: DateTime<Utc>,
+}
+
+// Like `fix-maxnominators-to-match-the-staking-limit`.
+pub fn slug(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        match c.is_ascii_alphanumeric() {
+            true => slug.push(c.to_ascii_lowercase()),
+            false if !slug.is_empty() && !slug.ends_with('-') => slug.push('-'),
+            false => (),
+        }
+    }
+    slug.truncate(MAX_SLUG_LEN);
+
+    slug.trim_end_matches('-').to_owned()
+}
+
+fn export_key(org: &str, repo: &str, number: u64) -> String {
+    format!("{org}#{repo}#patches#{number}")
+}
+
+pub fn get_exported(db: &Db, org: &str, repo: &str, number: u64) -> Result<Option<ExportedPatch>> {
+    match db.get(export_key(org, repo, number))? {
+        Some(exported) => Ok(Some(serde_json::from_slice(&exported)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn get_patch(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    number: u64,
+    format: PatchFormat,
+) -> Result<String> {
+    let what = format!("{org}/{repo} pr {number} {}", format.extension());
+    let pulls = octocrab.pulls(org, repo);
+    let patch = match format {
+        PatchFormat::Patch => retry(what, || pulls.get_patch(number)).await?,
+        PatchFormat::Diff => retry(what, || pulls.get_diff(number)).await?,
+    };
+
+    Ok(patch)
+}
+
+/*
+    The example of apply.sh:
+    #!/bin/sh
+    # Merged PRs of paritytech/polkadot in order of merging, run it in the fork.
+    set -e
+    PATCHES="$(cd "$(dirname "$0")" && pwd)"
+    cd "$(git rev-parse --show-toplevel)"
+    # #6300 Fix `MaxNominators` to match the staking limit
+    git am --3way "$PATCHES/6300-fix-maxnominators-to-match-the-staking-limit.patch"
+*/
+pub fn apply_script(org: &str, repo: &str, patches: &[ExportedPatch]) -> String {
+    let mut script = script_header(org, repo);
+    script.push_str("PATCHES=\"$(cd \"$(dirname \"$0\")\" && pwd)\"\n");
+    script.push_str("cd \"$(git rev-parse --show-toplevel)\"\n");
+    for patch in patches {
+        let file = Path::new(&patch.file)
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let _ = writeln!(script, "# #{} {}", patch.number, patch.title);
+        match patch.format {
+            PatchFormat::Patch => {
+                let _ = writeln!(script, "git am --3way \"$PATCHES/{file}\"");
+            }
+            PatchFormat::Diff => {
+                let _ = writeln!(script, "git apply --3way --index \"$PATCHES/{file}\"");
+                let message = format!("{} ({org}/{repo}#{})", patch.title, patch.number);
+                let _ = writeln!(script, "git commit -m '{}'", message.replace('\'', r"'\''"));
+            }
+        }
+    }
+
+    script
+}
+
+// PRs without a merge commit are left out, there's nothing to pick.
+pub fn cherry_pick_script(org: &str, repo: &str, patches: &[ExportedPatch]) -> String {
+    let mut script = script_header(org, repo);
+    let _ = writeln!(
+        script,
+        "# Fetch upstream first, like `git fetch https://github.com/{org}/{repo}.git`."
+    );
+    for patch in patches {
+        if let Some(sha) = patch.merge_commit_sha.as_deref() {
+            let _ = writeln!(script, "# #{} {}", patch.number, patch.title);
+            let _ = writeln!(script, "git cherry-pick -x {sha}");
+        }
+    }
+
+    script
+}
+
+fn script_header(org: &str, repo: &str) -> String {
+    format!("#!/bin/sh\n# Merged PRs of {org}/{repo} in order of merging, run it in the fork.\nset -e\n")
+}
+
+// Patches of merged PRs in order of merging, unmerged ones are left out.
+// PRs exported already are only fetched again with `force`, or if their files are gone.
+pub async fn export_patches(
+    octocrab: Arc<Octocrab>,
+    db: &Db,
+    org: &str,
+    repo: &str,
+    mut prs: Vec<pulls::PullRequest>,
+    ExportOptions { format, dir, force }: &ExportOptions<'_>,
+) -> Result<Vec<ExportedPatch>> {
+    let (format, force) = (*format, *force);
+    prs.retain(|pr| pr.merged_at.is_some());
+    prs.sort_by_key(|pr| (pr.merged_at, pr.number));
+    let repo_dir = dir.join(org).join(repo);
+    std::fs::create_dir_all(&repo_dir)?;
+
+    let mut exported = vec![];
+    for pr in prs.iter() {
+        let title = pr.title.clone().unwrap_or_default();
+        let name = format!("{}-{}.{}", pr.number, slug(&title), format.extension());
+        let file = format!("{org}/{repo}/{name}");
+        let path = repo_dir.join(&name);
+
+        let previous = get_exported(db, org, repo, pr.number)?;
+        let fetched = previous.filter(|previous| !force && previous.format == format);
+        match fetched.map(|previous| dir.join(previous.file)) {
+            Some(previous) if previous.exists() => {
+                // The title may be edited since.
+                if previous != path {
+                    std::fs::rename(previous, &path)?;
+                }
+            }
+            _ => {
+                let patch = get_patch(octocrab.clone(), org, repo, pr.number, format).await?;
+                std::fs::write(&path, patch)?;
+            }
+        }
+
+        let patch = ExportedPatch {
+            number: pr.number,
+            title,
+            merged_at: pr.merged_at,
+            merge_commit_sha: pr.merge_commit_sha.clone(),
+            format,
+            file,
+            exported_at: Utc::now(),
+        };
+        db.insert(
+            export_key(org, repo, pr.number),
+            serde_json::to_vec(&patch)?,
+        )?;
+        exported.push(patch);
+    }
+
+    std::fs::write(
+        repo_dir.join(APPLY_SCRIPT),
+        apply_script(org, repo, &exported),
+    )?;
+    std::fs::write(
+        repo_dir.join(CHERRY_PICK_SCRIPT),
+        cherry_pick_script(org, repo, &exported),
+    )?;
+
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    #[test]
+    fn slug_should_be_like_format_patch() {
+        assert_eq!(
+            slug("Fix `MaxNominators` to match the staking limit"),
+            "fix-maxnominators-to-match-the-staking-limit"
+        );
+        assert_eq!(
+            slug("[Backport] Companion for #12700"),
+            "backport-companion-for-12700"
+        );
+    }
+
+    #[tokio::test]
+    async fn patches_should_be_exported_in_order_of_merging() {
+        let mock = MockGithub::start(&["github"]).await;
+        let db = temporary_db();
+        let dir = std::env::temp_dir().join(format!("patches-{}", std::process::id()));
+        let (org, repo) = ("paritytech", "polkadot");
+        let mut prs = vec![];
+        for number in [6320, 6300, 6310] {
+            prs.push(
+                crate::subcribe_prs::get_pr_by_id(mock.client(), org, repo, number)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let options = ExportOptions {
+            format: PatchFormat::Patch,
+            dir: &dir,
+            force: false,
+        };
+        let exported = export_patches(mock.client(), &db, org, repo, prs.clone(), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            exported
+                .iter()
+                .map(|patch| patch.file.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "paritytech/polkadot/6300-fix-maxnominators-to-match-the-staking-limit.patch",
+                "paritytech/polkadot/6310-add-fastunstake-pallet-to-westend.patch",
+                "paritytech/polkadot/6320-companion-for-substrate-12700.patch",
+            ]
+        );
+        let patch = std::fs::read_to_string(dir.join(&exported[0].file)).unwrap();
+        assert!(patch.starts_with(&format!("From {}", "6".repeat(40))));
+        let script = std::fs::read_to_string(dir.join(org).join(repo).join(APPLY_SCRIPT)).unwrap();
+        assert!(script.ends_with(
+            "# #6320 Companion for substrate#12700\n\
+            git am --3way \"$PATCHES/6320-companion-for-substrate-12700.patch\"\n"
+        ));
+        let script =
+            std::fs::read_to_string(dir.join(org).join(repo).join(CHERRY_PICK_SCRIPT)).unwrap();
+        assert!(script.contains(&format!("git cherry-pick -x {:040x}\n", 6300)));
+        assert_eq!(
+            get_exported(&db, org, repo, 6310).unwrap().unwrap(),
+            exported[1]
+        );
+
+        // Exported patches are not fetched again.
+        let requests = mock.requests().len();
+        export_patches(mock.client(), &db, org, repo, prs, &options)
+            .await
+            .unwrap();
+        assert_eq!(mock.requests().len(), requests);
+
+        // A diff has no commit message, so it's committed with the title.
+        let script = apply_script(
+            org,
+            repo,
+            &[ExportedPatch {
+                format: PatchFormat::Diff,
+                ..exported[0].clone()
+            }],
+        );
+        assert!(script.ends_with(
+            "git apply --3way --index \"$PATCHES/6300-fix-maxnominators-to-match-the-staking-limit.patch\"\n\
+            git commit -m 'Fix `MaxNominators` to match the staking limit (paritytech/polkadot#6300)'\n"
+        ));
+        let diff = get_patch(mock.client(), org, repo, 6300, PatchFormat::Diff)
+            .await
+            .unwrap();
+        assert!(diff.starts_with("diff --git a/runtime/polkadot/src/lib.rs"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
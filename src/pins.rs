@@ -0,0 +1,303 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! How far commits we're pinned to are behind heads of the branches they track.
+//!
+//! Every check is kept in sled by time, so the trend can be charted.
+
+use crate::discover::GitReference;
+use crate::divergence::MissingPr;
+use anyhow::Result;
+use chrono::prelude::*;
+use octocrab::Octocrab;
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use std::{collections::HashSet, fmt, sync::Arc};
+use toml::Value;
+
+// A branch of upstream and the commit we're pinned to on it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Pin {
+    pub organization: String,
+    pub repository: String,
+    pub branch: String,
+    pub pinned: String,
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}:{}",
+            self.organization, self.repository, self.branch
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PinStatus {
+    pub pin: Pin,
+    // Head of the branch, the pinned commit if nothing is newer.
+    pub head: String,
+    pub commits_behind: usize,
+    // Days since the oldest commit we miss landed, 0 if we're up to date.
+    pub days_behind: i64,
+    // Merged PRs and commits pushed without a PR between the pinned commit and the head, oldest first.
+    pub missing: Vec<MissingPr>,
+    pub checked_at: DateTime<Utc>,
+}
+
+fn history_prefix(pin: &Pin) -> String {
+    format!(
+        "pins#{}/{}#{}#",
+        pin.organization, pin.repository, pin.branch
+    )
+}
+
+// Branches pinned in `lock-file` of section `relevance`, then `pins` of subscribed repositories
+// written by `discover`, if the lock file doesn't pin the branch.
+pub fn get_pins(config: &Value) -> Result<Vec<Pin>> {
+    let mut pins: Vec<Pin> = vec![];
+    for source in crate::relevance::lock_sources(config)? {
+        if let (GitReference::Branch(branch), Some(pinned)) = (source.reference, source.pinned) {
+            pins.push(Pin {
+                organization: source.organization,
+                repository: source.repository,
+                branch,
+                pinned,
+            });
+        }
+    }
+    for repo in crate::utils::get_repositories(config)? {
+        for (branch, pinned) in repo.pins {
+            let pinned_already = pins.iter().any(|pin| {
+                pin.organization.eq_ignore_ascii_case(&repo.organization)
+                    && pin.repository.eq_ignore_ascii_case(&repo.repository)
+                    && pin.branch == branch
+            });
+            // Tags are pinned too, but nothing lands on them.
+            if !pinned_already && repo.branches.contains(&branch) {
+                pins.push(Pin {
+                    organization: repo.organization.clone(),
+                    repository: repo.repository.clone(),
+                    branch,
+                    pinned,
+                });
+            }
+        }
+    }
+
+    Ok(pins)
+}
+
+pub async fn compute_pin_status(
+    octocrab: Arc<Octocrab>,
+    pin: &Pin,
+    now: DateTime<Utc>,
+) -> Result<PinStatus> {
+    let (org, repo) = (&pin.organization, &pin.repository);
+    let comparison = crate::subcribe_commits::compare_refs(
+        octocrab.clone(),
+        org,
+        repo,
+        &pin.pinned,
+        &pin.branch,
+    )
+    .await?;
+    let missing =
+        crate::divergence::missing_prs(octocrab, org, repo, &comparison.commits, &HashSet::new())
+            .await?;
+    let days_behind = comparison
+        .commits
+        .iter()
+        .filter_map(|commit| commit.date)
+        .min()
+        .map_or(0, |oldest| (now - oldest).num_days().max(0));
+
+    Ok(PinStatus {
+        pin: pin.clone(),
+        head: comparison
+            .commits
+            .last()
+            .map_or_else(|| pin.pinned.clone(), |commit| commit.sha.clone()),
+        commits_behind: comparison.ahead_by,
+        days_behind,
+        missing,
+        checked_at: now,
+    })
+}
+
+pub fn store_pin_status(db: &Db, status: &PinStatus) -> Result<()> {
+    let key = format!(
+        "{}{}",
+        history_prefix(&status.pin),
+        status
+            .checked_at
+            .to_rfc3339_opts(SecondsFormat::Nanos, true)
+    );
+    db.insert(key, serde_json::to_vec(status)?)?;
+    Ok(())
+}
+
+// Every check of the pin, oldest first.
+pub fn get_pin_history(db: &Db, pin: &Pin) -> Result<Vec<PinStatus>> {
+    let mut history = vec![];
+    for status in db.scan_prefix(history_prefix(pin)).values() {
+        history.push(serde_json::from_slice(&status?)?);
+    }
+
+    Ok(history)
+}
+
+// Check and store status of pins not checked in `summary-frequence`, or all of them with `force`.
+pub async fn update_pins(
+    config: &Value,
+    db: Arc<Db>,
+    force: bool,
+) -> Result<Vec<(Pin, Result<PinStatus>)>> {
+    let frequence = crate::utils::get_summary_frequence(config, "pins");
+    let mut results = vec![];
+    for pin in get_pins(config)? {
+        let now = Utc::now();
+        if !force {
+            // Every pinned commit is checked on its own, a new pin is checked at once.
+            let last = get_pin_history(&db, &pin)?
+                .into_iter()
+                .rev()
+                .find(|status| status.pin.pinned == pin.pinned);
+            if last.map_or(false, |last| now - last.checked_at < frequence) {
+                continue;
+            }
+        }
+
+        let octocrab = crate::utils::repository_client(config, &pin.organization, &pin.repository)?;
+        let status = compute_pin_status(octocrab, &pin, now).await;
+        if let Ok(status) = status.as_ref() {
+            store_pin_status(&db, status)?;
+        }
+        results.push((pin, status));
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    #[test]
+    fn pins_should_come_from_lock_file_and_config() {
+        let config = format!(
+            r#"
+            [relevance]
+            lock-file = "{}/fixtures/cargo/Cargo.lock"
+
+            [paritytech.substrate]
+            query-release = false
+            branches = ["polkadot-v0.9.33", "polkadot-v0.9.34"]
+            pins = {{ "polkadot-v0.9.33" = "{}", "polkadot-v0.9.34" = "{}" }}
+            "#,
+            env!("CARGO_MANIFEST_DIR"),
+            "1".repeat(40),
+            "4".repeat(40),
+        )
+        .parse::<Value>()
+        .unwrap();
+        let pins = get_pins(&config)
+            .unwrap()
+            .into_iter()
+            .map(|pin| (pin.to_string(), pin.pinned[..1].to_owned()))
+            .collect::<Vec<_>>();
+        // The lock file wins over config, and the tag of polkadot is not a branch.
+        assert_eq!(
+            pins,
+            vec![
+                (
+                    "paritytech/cumulus:polkadot-v0.9.33".to_owned(),
+                    "1".to_owned()
+                ),
+                (
+                    "paritytech/substrate:polkadot-v0.9.33".to_owned(),
+                    "2".to_owned()
+                ),
+                (
+                    "paritytech/polkadot:release-v0.9.33".to_owned(),
+                    "3".to_owned()
+                ),
+                (
+                    "paritytech/substrate:polkadot-v0.9.34".to_owned(),
+                    "4".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pin_status_should_be_stored_by_time() {
+        let mock = MockGithub::start(&["github"]).await;
+        let config = format!(
+            r#"
+            [github]
+            base-url = "{}"
+
+            [paritytech.substrate]
+            query-release = false
+            branches = ["polkadot-v0.9.33"]
+            pins = {{ "polkadot-v0.9.33" = "{}" }}
+            "#,
+            mock.base_url,
+            "2".repeat(40),
+        )
+        .parse::<Value>()
+        .unwrap();
+        let db = temporary_db();
+        let pin = get_pins(&config).unwrap().remove(0);
+
+        let now = "2022-12-05T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let status = compute_pin_status(mock.client(), &pin, now).await.unwrap();
+        assert_eq!(status.commits_behind, 2);
+        assert_eq!(status.head, "4".repeat(40));
+        // The backport landed on 2022-11-25.
+        assert_eq!(status.days_behind, 10);
+        assert_eq!(
+            status
+                .missing
+                .iter()
+                .map(|pr| pr.number)
+                .collect::<Vec<_>>(),
+            vec![None, Some(12900)]
+        );
+
+        let results = update_pins(&config, db.clone(), false).await.unwrap();
+        assert_eq!(results.len(), 1);
+        // It's checked just now.
+        assert!(update_pins(&config, db.clone(), false)
+            .await
+            .unwrap()
+            .is_empty());
+
+        store_pin_status(&db, &status).unwrap();
+        // Checks in the same second are kept apart.
+        let mut later = status.clone();
+        later.checked_at = now + chrono::Duration::milliseconds(1);
+        store_pin_status(&db, &later).unwrap();
+        let history = get_pin_history(&db, &pin).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0], status);
+        assert_eq!(history[1], later);
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parse release notes following the convention of Polkadot.
+//!
+//! They tell how urgent the upgrade is, versions and hashes of runtimes built by srtool,
+//! and changes of the client and runtimes, in `## Client` and `## Runtime` sections or in
+//! `### ` sections of `## Changes` as the release template renders them.
+//! Bodies not following it give empty notes.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpgradePriority {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for UpgradePriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let priority = match self {
+            Self::Low => "Low",
+            Self::Medium => "Medium",
+            Self::High => "High",
+        };
+        write!(f, "{priority}")
+    }
+}
+
+// A runtime built by srtool.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RuntimeInfo {
+    // Title of the runtime, like `Polkadot`.
+    pub name: String,
+    pub spec_name: Option<String>,
+    pub spec_version: Option<u32>,
+    // Blake2-256 hash of the wasm.
+    pub wasm_hash: Option<String>,
+    // Hash of the `system.setCode` proposal.
+    pub proposal_hash: Option<String>,
+    pub authorize_upgrade_hash: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReleaseNotes {
+    pub priority: Option<UpgradePriority>,
+    pub runtimes: Vec<RuntimeInfo>,
+    pub client_changes: Vec<String>,
+    pub runtime_changes: Vec<String>,
+}
+
+impl ReleaseNotes {
+    // True if the body doesn't follow the convention.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Like `polkadot-9330, kusama-9330`.
+    pub fn spec_versions(&self) -> Vec<String> {
+        self.runtimes
+            .iter()
+            .filter_map(|runtime| match (&runtime.spec_name, runtime.spec_version) {
+                (Some(name), Some(version)) => Some(format!("{name}-{version}")),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// Changes rendered by the release template, like
+// ``✅ `trivial` [`#6277`](https://github.com/paritytech/polkadot/pull/6277) - Companion for …``,
+// are told like `#6277 Companion for …`.
+fn bullet(line: &str, linked_change: &Regex) -> Option<String> {
+    let change = line
+        .strip_prefix("* ")
+        .or_else(|| line.strip_prefix("- "))
+        .map(str::trim)
+        .filter(|change| !change.is_empty())?;
+    Some(match linked_change.captures(change) {
+        Some(captures) => format!("#{} {}", &captures[1], captures[2].trim()),
+        None => change.to_owned(),
+    })
+}
+
+pub fn parse(body: &str) -> ReleaseNotes {
+    let priority = Regex::new(r"(?i)\b(low|medium|high)\b").expect("Valid regex.");
+    // Like `polkadot-9330 (parity-polkadot-0.tx18.au0)`.
+    let core_version = Regex::new(r"([A-Za-z][\w-]*?)-(\d+)\s*\(").expect("Valid regex.");
+    let hash_regex = Regex::new(r"0x[0-9a-fA-F]+").expect("Valid regex.");
+    let hash = |line: &str| hash_regex.find(line).map(|hash| hash.as_str().to_owned());
+    let linked_change = Regex::new(r"\[`?#(\d+)`?\]\([^)]*\)\s*-?\s*(.+)$").expect("Valid regex.");
+
+    let mut notes = ReleaseNotes::default();
+    let mut section = String::new();
+    for line in body.lines().map(str::trim) {
+        if let Some(title) = line.strip_prefix("## ") {
+            section = title.trim().to_lowercase();
+            continue;
+        }
+        if let Some(title) = line.strip_prefix("### ") {
+            match section.as_str() {
+                "runtimes" => notes.runtimes.push(RuntimeInfo {
+                    name: title.trim().to_owned(),
+                    ..Default::default()
+                }),
+                // `## Changes` is split into `### Client`, `### Runtime` and so on.
+                "changes" | "changes/client" | "changes/runtime" | "changes/other" => {
+                    section = match title.trim().to_lowercase().as_str() {
+                        "client" => "changes/client",
+                        "runtime" => "changes/runtime",
+                        _ => "changes/other",
+                    }
+                    .to_owned();
+                }
+                _ => (),
+            }
+            continue;
+        }
+        if line.is_empty() || line.starts_with("```") {
+            continue;
+        }
+
+        match section.as_str() {
+            // Only the first level in the section counts.
+            title if title.contains("priority") && notes.priority.is_none() => {
+                notes.priority = priority.captures(line).and_then(|captures| {
+                    match captures[1].to_lowercase().as_str() {
+                        "low" => Some(UpgradePriority::Low),
+                        "medium" => Some(UpgradePriority::Medium),
+                        "high" => Some(UpgradePriority::High),
+                        _ => None,
+                    }
+                });
+            }
+            "runtimes" => {
+                let Some(runtime) = notes.runtimes.last_mut() else {
+                    continue;
+                };
+                if line.contains("Core Version:") {
+                    if let Some(captures) = core_version.captures(line) {
+                        runtime.spec_name = Some(captures[1].to_owned());
+                        runtime.spec_version = captures[2].parse().ok();
+                    }
+                } else if line.contains("system.setCode hash:") {
+                    runtime.proposal_hash = hash(line);
+                } else if line.contains("authorizeUpgrade hash:") {
+                    runtime.authorize_upgrade_hash = hash(line);
+                } else if line.contains("Blake2-256 hash:") {
+                    runtime.wasm_hash = hash(line);
+                }
+            }
+            "client" | "changes/client" => {
+                notes.client_changes.extend(bullet(line, &linked_change))
+            }
+            "runtime" | "changes/runtime" => {
+                notes.runtime_changes.extend(bullet(line, &linked_change))
+            }
+            _ => (),
+        }
+    }
+
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_release_notes_should_work() {
+        let notes = parse(include_str!(
+            "../fixtures/release-notes/polkadot-v0.9.33.md"
+        ));
+        assert_eq!(notes.priority, Some(UpgradePriority::Low));
+        assert_eq!(
+            notes.spec_versions(),
+            vec!["westend-9330", "kusama-9330", "polkadot-9330"]
+        );
+        let polkadot = &notes.runtimes[2];
+        assert_eq!(polkadot.name, "Polkadot");
+        assert_eq!(
+            polkadot.proposal_hash.as_deref(),
+            Some("0x394ebfa10a25e34ac95420015cc6eb6f339320febf17ad616c114aa17304a3c8")
+        );
+        assert_eq!(
+            polkadot.wasm_hash.as_deref(),
+            Some("0xa0013ba6212b2005183717231fd23d454e446329d3da1b8eca2dbca51e24d51d")
+        );
+        assert!(polkadot.authorize_upgrade_hash.is_some());
+        // Changes of `## Changes` are told by their PRs, `Node-dev` and rustc versions are not changes.
+        assert_eq!(
+            notes.client_changes,
+            vec![
+                "#6277 Companion for substrate#12602: Add `OnRuntimeUpgrade` hooks for state version",
+                "#6232 Bump `clap` to 4.0.17",
+            ]
+        );
+        assert_eq!(
+            notes.runtime_changes,
+            vec![
+                "#6304 Kusama: Enable nomination pools commission",
+                "#6257 Polkadot: Remove `Gilt` leftovers and unused storage",
+                "#6281 Westend: Add `FastUnstake` pallet",
+            ]
+        );
+
+        // The newer release, with a `Global Priority` heading and `-` bullets.
+        let notes = parse(include_str!(
+            "../fixtures/release-notes/polkadot-v0.9.34.md"
+        ));
+        assert_eq!(notes.priority, Some(UpgradePriority::High));
+        assert_eq!(notes.spec_versions(), vec!["polkadot-9340"]);
+        assert_eq!(
+            notes.client_changes,
+            vec!["#6371 Fix a race condition in the approval voting subsystem"]
+        );
+
+        assert!(parse("This release contains the changes from `v0.9.32` to `v0.9.33`.").is_empty());
+    }
+}
@@ -0,0 +1,375 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tell PRs touching crates we depend on.
+//!
+//! Crates we depend on come from `lock-file` in section `relevance`, like Manta's Cargo.lock.
+//! Changed files of a PR are mapped to crates by the nearest Cargo.toml on its base branch,
+//! or on its head commit if the PR changes that Cargo.toml, like adding a crate.
+//! The crate of every directory is cached in sled per branch or commit.
+
+use crate::discover::GitSource;
+use crate::filter::Filter;
+use crate::retry::{is_not_found, retry};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use octocrab::{models::pulls::PullRequest, models::repos::Content, Octocrab};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use std::{collections::BTreeSet, sync::Arc};
+use toml::Value;
+
+// What a PR touches of paths and crates we care about.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Touched {
+    pub paths: Vec<String>,
+    // Crates we depend on.
+    pub crates: Vec<String>,
+}
+
+pub fn get_lock_file(config: &Value) -> Option<&str> {
+    config
+        .get("relevance")
+        .and_then(|relevance| relevance.get("lock-file"))
+        .and_then(|lock_file| lock_file.as_str())
+        .filter(|lock_file| !lock_file.is_empty())
+}
+
+// Git sources in the lock file, nothing if it's not configured.
+pub fn lock_sources(config: &Value) -> Result<Vec<GitSource>> {
+    match get_lock_file(config) {
+        Some(lock_file) => crate::discover::parse_lock(&std::fs::read_to_string(lock_file)?),
+        None => Ok(vec![]),
+    }
+}
+
+// Crates coming from the repository, whatever branch they're from.
+pub fn used_crates(sources: &[GitSource], org: &str, repo: &str) -> BTreeSet<String> {
+    sources
+        .iter()
+        .filter(|source| {
+            source.organization.eq_ignore_ascii_case(org)
+                && source.repository.eq_ignore_ascii_case(repo)
+        })
+        .flat_map(|source| source.crates.iter().cloned())
+        .collect()
+}
+
+// Where Cargo.toml files are read from for a PR.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestSource {
+    // The base branch of the PR, like `master` or `polkadot-v0.9.33`.
+    pub base: String,
+    // Head commit of the PR, manifests the PR changes are read from it, like a crate it adds.
+    pub head: String,
+    // The base branch may get the crate later, e.g. by this PR, so misses aren't cached.
+    pub open: bool,
+}
+
+impl ManifestSource {
+    pub fn of_pr(pr: &PullRequest) -> Self {
+        Self {
+            base: pr.base.ref_field.clone(),
+            head: pr.head.sha.clone(),
+            open: pr.closed_at.is_none(),
+        }
+    }
+}
+
+// Manifests of a branch change, so they're looked up again once in a while.
+// Ones of a commit never change.
+const BRANCH_MANIFEST_TTL_HOURS: i64 = 24;
+
+#[derive(Deserialize, Serialize)]
+struct CachedManifest {
+    found: Option<Option<String>>,
+    cached_at: DateTime<Utc>,
+}
+
+// None if `dir` has no Cargo.toml at `git_ref`, so parent directories should be looked at.
+// Some(None) if its Cargo.toml has no package, like a workspace, and Some(name) of the crate otherwise.
+async fn crate_in_dir(
+    octocrab: Arc<Octocrab>,
+    db: &Db,
+    org: &str,
+    repo: &str,
+    git_ref: &str,
+    cache_misses: bool,
+    dir: &str,
+) -> Result<Option<Option<String>>> {
+    let key = format!("{org}#{repo}#manifests#{git_ref}#{dir}");
+    let is_commit = git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit());
+    if let Some(cached) = db.get(&key)? {
+        let cached = serde_json::from_slice::<CachedManifest>(&cached)?;
+        if is_commit || Utc::now() - cached.cached_at < Duration::hours(BRANCH_MANIFEST_TTL_HOURS) {
+            return Ok(cached.found);
+        }
+    }
+
+    let route = match dir.is_empty() {
+        true => format!("repos/{org}/{repo}/contents/Cargo.toml"),
+        false => format!("repos/{org}/{repo}/contents/{dir}/Cargo.toml"),
+    };
+    let parameters = [("ref", git_ref)];
+    let found = match retry(
+        format!("{org}/{repo} {dir}/Cargo.toml at {git_ref}"),
+        || octocrab.get::<Content, _, _>(&route, Some(&parameters)),
+    )
+    .await
+    {
+        Ok(content) => {
+            let manifest = content
+                .decoded_content()
+                .unwrap_or_default()
+                .parse::<Value>()?;
+            Some(
+                manifest
+                    .get("package")
+                    .and_then(|package| package.get("name"))
+                    .and_then(|name| name.as_str())
+                    .map(ToOwned::to_owned),
+            )
+        }
+        Err(e) if is_not_found(&e) => None,
+        Err(e) => return Err(e.into()),
+    };
+    if found.is_some() || cache_misses {
+        let cached = CachedManifest {
+            found: found.clone(),
+            cached_at: Utc::now(),
+        };
+        db.insert(key, serde_json::to_vec(&cached)?)?;
+    }
+
+    Ok(found)
+}
+
+// The crate a file belongs to, by the nearest Cargo.toml.
+// `files` are all files the PR changes, a Cargo.toml among them is read from the head commit.
+pub async fn crate_of_file(
+    octocrab: Arc<Octocrab>,
+    db: &Db,
+    org: &str,
+    repo: &str,
+    source: &ManifestSource,
+    files: &[String],
+    file: &str,
+) -> Result<Option<String>> {
+    let mut dir = file;
+    loop {
+        dir = match dir.rsplit_once('/') {
+            Some((parent, _)) => parent,
+            None => "",
+        };
+        let manifest = match dir.is_empty() {
+            true => "Cargo.toml".to_owned(),
+            false => format!("{dir}/Cargo.toml"),
+        };
+        let (git_ref, cache_misses) = match files.contains(&manifest) {
+            true => (source.head.as_str(), true),
+            false => (source.base.as_str(), !source.open),
+        };
+        let found = crate_in_dir(octocrab.clone(), db, org, repo, git_ref, cache_misses, dir);
+        match found.await? {
+            Some(name) => return Ok(name),
+            None if dir.is_empty() => return Ok(None),
+            None => (),
+        }
+    }
+}
+
+pub async fn touched_crates(
+    octocrab: Arc<Octocrab>,
+    db: &Db,
+    org: &str,
+    repo: &str,
+    source: &ManifestSource,
+    files: &[String],
+) -> Result<BTreeSet<String>> {
+    let mut crates = BTreeSet::new();
+    for file in files {
+        let name = crate_of_file(octocrab.clone(), db, org, repo, source, files, file).await?;
+        if let Some(name) = name {
+            crates.insert(name);
+        }
+    }
+
+    Ok(crates)
+}
+
+// Paths and crates the PR touches, None if it's filtered out by them.
+// Changed files are only fetched if the filter has paths or tracks relevance.
+pub async fn touched_by_pr(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    org: &str,
+    repo: &str,
+    pr: &PullRequest,
+    filter: &Filter,
+) -> Result<Option<Touched>> {
+    if !filter.has_paths() && !filter.tracks_relevance() {
+        return Ok(Some(Touched::default()));
+    }
+
+    let files =
+        crate::subcribe_prs::get_cached_changed_files(octocrab.clone(), db.clone(), org, repo, pr)
+            .await?;
+    let paths = filter.touched_paths(&files);
+    if filter.has_paths() && paths.is_empty() {
+        return Ok(None);
+    }
+    let mut crates = vec![];
+    if filter.tracks_relevance() {
+        let source = ManifestSource::of_pr(pr);
+        let touched = touched_crates(octocrab, &db, org, repo, &source, &files).await?;
+        crates = filter.relevant_crates(&touched);
+        if filter.relevant_only() && crates.is_empty() {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(Touched { paths, crates }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    fn source(open: bool) -> ManifestSource {
+        ManifestSource {
+            base: "manta".to_owned(),
+            head: "0123456789abcdef0123456789abcdef01234567".to_owned(),
+            open,
+        }
+    }
+
+    #[tokio::test]
+    async fn files_should_be_mapped_to_crates() {
+        let mock = MockGithub::start(&["github"]).await;
+        let db = temporary_db();
+        let (org, repo) = ("Manta-Network", "Manta");
+        let files = [
+            "runtime/calamari/src/fee.rs",
+            "runtime/calamari/src/xcm_config.rs",
+            "pallets/parachain-staking/Cargo.toml",
+            ".github/workflows/ci.yml",
+            "Cargo.lock",
+        ]
+        .map(ToOwned::to_owned);
+        let crates = touched_crates(mock.client(), &db, org, repo, &source(false), &files)
+            .await
+            .unwrap();
+        assert_eq!(
+            crates.into_iter().collect::<Vec<_>>(),
+            vec!["calamari-runtime", "pallet-parachain-staking"]
+        );
+
+        // Every directory is looked up once.
+        let requests = mock.requests().len();
+        let crates = touched_crates(mock.client(), &db, org, repo, &source(false), &files)
+            .await
+            .unwrap();
+        assert_eq!(crates.len(), 2);
+        assert_eq!(mock.requests().len(), requests);
+    }
+
+    #[tokio::test]
+    async fn manifests_should_be_read_from_the_ref_of_pr() {
+        let mock = MockGithub::start(&["github"]).await;
+        let db = temporary_db();
+        let (org, repo) = ("Manta-Network", "Manta");
+        // The pr changes the manifest of parachain-staking, `runtime` has no Cargo.toml.
+        let files = [
+            "pallets/parachain-staking/Cargo.toml",
+            "pallets/parachain-staking/src/lib.rs",
+            "runtime/README.md",
+        ]
+        .map(ToOwned::to_owned);
+        let crates = touched_crates(mock.client(), &db, org, repo, &source(true), &files)
+            .await
+            .unwrap();
+        assert_eq!(
+            crates.into_iter().collect::<Vec<_>>(),
+            vec!["pallet-parachain-staking"]
+        );
+        let requests = mock.requests();
+        assert!(requests.contains(
+            &"GET /repos/Manta-Network/Manta/contents/pallets/parachain-staking/Cargo.toml?ref=0123456789abcdef0123456789abcdef01234567".to_owned()
+        ));
+        assert!(requests.contains(
+            &"GET /repos/Manta-Network/Manta/contents/runtime/Cargo.toml?ref=manta".to_owned()
+        ));
+
+        // Misses on the base of an open pr are looked up again, hits are cached.
+        touched_crates(mock.client(), &db, org, repo, &source(true), &files)
+            .await
+            .unwrap();
+        let again = mock.requests()[requests.len()..].to_vec();
+        assert_eq!(
+            again,
+            vec![
+                "GET /repos/Manta-Network/Manta/contents/pallets/parachain-staking/src/Cargo.toml?ref=manta",
+                "GET /repos/Manta-Network/Manta/contents/runtime/Cargo.toml?ref=manta",
+            ]
+        );
+
+        // Misses on the base of a merged pr are cached, but only for that branch.
+        touched_crates(mock.client(), &db, org, repo, &source(false), &files)
+            .await
+            .unwrap();
+        let requests = mock.requests().len();
+        touched_crates(mock.client(), &db, org, repo, &source(false), &files)
+            .await
+            .unwrap();
+        assert_eq!(mock.requests().len(), requests);
+        let release = ManifestSource {
+            base: "polkadot-v0.9.33".to_owned(),
+            ..source(false)
+        };
+        touched_crates(mock.client(), &db, org, repo, &release, &files)
+            .await
+            .unwrap();
+        assert!(mock.requests().contains(
+            &"GET /repos/Manta-Network/Manta/contents/runtime/Cargo.toml?ref=polkadot-v0.9.33"
+                .to_owned()
+        ));
+    }
+
+    #[test]
+    fn used_crates_should_come_from_lock_file() {
+        let config = format!(
+            r#"
+            [relevance]
+            lock-file = "{}/fixtures/cargo/downstream/Cargo.lock"
+            "#,
+            env!("CARGO_MANIFEST_DIR")
+        )
+        .parse::<Value>()
+        .unwrap();
+        let sources = lock_sources(&config).unwrap();
+        assert_eq!(
+            used_crates(&sources, "manta-network", "manta")
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec!["calamari-runtime", "pallet-parachain-staking"]
+        );
+        assert!(used_crates(&sources, "paritytech", "substrate").is_empty());
+        assert!(lock_sources(&Value::Table(Default::default()))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retry transient GitHub failures with exponential backoff and jitter.
+
+use octocrab::{Octocrab, Page};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::hash_map::RandomState,
+    fmt::{Debug, Display},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::RwLock,
+};
+use tokio::time::{sleep, Duration};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct RetryPolicy {
+    // How many times a request is sent at most, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    // Wait a random time in [backoff / 2, backoff], so parallel requests don't retry together.
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    pub const fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            jitter: true,
+        }
+    }
+
+    // The backoff before the next attempt, attempt starts from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff_ms);
+        let backoff = match self.jitter && backoff > 1 {
+            true => backoff / 2 + random() % (backoff / 2 + 1),
+            false => backoff,
+        };
+        Duration::from_millis(backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static POLICY: RwLock<RetryPolicy> = RwLock::new(RetryPolicy::new());
+
+// Set the policy used by all requests to GitHub, like `octocrab::initialise`.
+pub fn initialise(policy: RetryPolicy) {
+    *POLICY.write().unwrap() = policy;
+}
+
+pub fn policy() -> RetryPolicy {
+    POLICY.read().unwrap().clone()
+}
+
+// Every RandomState is seeded randomly, that's enough for jitter.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+// 5xx, rate limits, timeouts and broken connections may succeed next time,
+// but 401, 404, 422, a malformed request or a body we can't decode won't.
+pub fn is_transient(error: &octocrab::Error) -> bool {
+    match error {
+        // octocrab drops the headers, so a 403 of the rate limit can't be told from
+        // other 403s, they're retried alike and give up after `max-attempts`.
+        octocrab::Error::Http { source, .. } => match source.status() {
+            Some(status) => status.is_server_error() || matches!(status.as_u16(), 403 | 429),
+            None => source.is_timeout() || source.is_connect(),
+        },
+        _ => false,
+    }
+}
+
+// It's not an error if what's asked for may not exist.
+pub fn is_not_found(error: &octocrab::Error) -> bool {
+    match error {
+        octocrab::Error::Http { source, .. } => {
+            source.status().map(|status| status.as_u16()) == Some(404)
+        }
+        octocrab::Error::GitHub { source, .. } => source.message == "Not Found",
+        _ => false,
+    }
+}
+
+// Send the request by `f` until it succeeds, fails permanently or runs out of attempts.
+// `what` tells which request is retried in logs, like `paritytech/substrate prs page 3`.
+pub async fn retry<T, F, Fut>(what: impl Display, mut f: F) -> octocrab::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = octocrab::Result<T>>,
+{
+    let policy = policy();
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(e) if attempt < policy.max_attempts && is_transient(&e) => {
+                let backoff = policy.backoff(attempt);
+                println!(
+                    "Attempt {attempt}/{} of {what} failed: {e}, retry in {backoff:?}.",
+                    policy.max_attempts
+                );
+                sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// The page after `page` with retries, `None` at the end.
+pub async fn next_page<T: DeserializeOwned>(
+    what: impl Display,
+    octocrab: &Octocrab,
+    page: &Page<T>,
+) -> octocrab::Result<Option<Page<T>>> {
+    retry(what, || octocrab.get_page::<T>(&page.next)).await
+}
+
+// A scan failing after its first page, with items of the pages fetched before.
+// Reports may keep them with a warning, but they must not be taken as complete.
+#[derive(Debug)]
+pub struct Incomplete<T> {
+    pub what: String,
+    pub source: octocrab::Error,
+    pub items: Vec<T>,
+}
+
+impl<T> Incomplete<T> {
+    pub fn new(what: impl Display, source: octocrab::Error, items: Vec<T>) -> Self {
+        Self {
+            what: what.to_string(),
+            source,
+            items,
+        }
+    }
+}
+
+impl<T> Display for Incomplete<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} failed: {}, only {} items of pages before it are fetched.",
+            self.what,
+            self.source,
+            self.items.len()
+        )
+    }
+}
+
+impl<T: Debug> std::error::Error for Incomplete<T> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// Items of a scan, and why they're incomplete if it fails halfway.
+// Other failures, like the first page, are still errors.
+pub fn partial<T: Debug + Send + Sync + 'static>(
+    result: anyhow::Result<Vec<T>>,
+) -> anyhow::Result<(Vec<T>, Option<String>)> {
+    match result {
+        Ok(items) => Ok((items, None)),
+        Err(e) => match e.downcast::<Incomplete<T>>() {
+            Ok(incomplete) => {
+                let warning = incomplete.to_string();
+                Ok((incomplete.items, Some(warning)))
+            }
+            Err(e) => Err(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_should_grow_exponentially() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 300,
+            jitter: false,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(10), Duration::from_millis(300));
+
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        for attempt in 1..5 {
+            let backoff = policy.backoff(attempt);
+            let expected = 100u64 * 2u64.pow(attempt - 1);
+            let expected = Duration::from_millis(expected.min(300));
+            assert!(backoff >= expected / 2 && backoff <= expected);
+        }
+    }
+}
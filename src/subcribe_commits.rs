@@ -0,0 +1,538 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Track commits landing on release branches, like `polkadot-v0.9.33` of substrate.
+//!
+//! The last seen commit of every branch is kept in sled, so every sync only tells newer ones.
+
+use crate::retry::retry;
+use crate::utils::Repository;
+use anyhow::Result;
+use chrono::prelude::*;
+use octocrab::{Octocrab, Page};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use std::sync::Arc;
+
+// At most so many commits are told in one sync, in case the branch is force-pushed
+// and the last seen commit is gone.
+pub const MAX_COMMITS_PER_SYNC: usize = 300;
+
+// At most so many commits without a PR number in their titles are asked for their PRs at once,
+// every one costs a request.
+pub const MAX_PR_LOOKUPS: usize = 100;
+
+// A commit on a branch, with the pr it comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BranchCommit {
+    pub branch: String,
+    pub sha: String,
+    // GitHub login of the author, or the name in git if it's not linked to a GitHub user.
+    pub author: String,
+    pub message: String,
+    pub date: Option<DateTime<Utc>>,
+    pub html_url: String,
+    pub pr: Option<String>,
+}
+
+impl BranchCommit {
+    // First line of the message.
+    pub fn title(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..self.sha.len().min(7)]
+    }
+
+    // Squash merges and cherry-picks of them end with the pr number, like `Fix a typo (#12345)`.
+    pub fn squashed_pr_number(&self) -> Option<u64> {
+        let squashed = Regex::new(r"\(#(\d+)\)\s*$").expect("Valid regex.");
+        squashed
+            .captures(self.title())
+            .and_then(|captures| captures[1].parse().ok())
+    }
+}
+
+#[derive(Deserialize)]
+struct CommitNode {
+    sha: String,
+    html_url: String,
+    commit: CommitDetail,
+    author: Option<GithubUser>,
+}
+
+#[derive(Deserialize)]
+struct CommitDetail {
+    message: String,
+    author: Option<Signature>,
+    committer: Option<Signature>,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    name: Option<String>,
+    date: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct GithubUser {
+    login: String,
+}
+
+// How `head` diverges from `base`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comparison {
+    // Commits in `head` but not in `base`, they're listed in `commits`.
+    pub ahead_by: usize,
+    // Commits in `base` but not in `head`.
+    pub behind_by: usize,
+    // Oldest first.
+    pub commits: Vec<BranchCommit>,
+}
+
+#[derive(Deserialize)]
+struct ComparisonPage {
+    ahead_by: usize,
+    behind_by: usize,
+    total_commits: usize,
+    commits: Vec<CommitNode>,
+}
+
+#[derive(Deserialize)]
+struct CommitPull {
+    number: u64,
+    merged_at: Option<DateTime<Utc>>,
+}
+
+impl CommitNode {
+    fn into_commit(self, branch: &str) -> BranchCommit {
+        let author = match (self.author, &self.commit.author) {
+            (Some(user), _) => user.login,
+            (
+                None,
+                Some(Signature {
+                    name: Some(name), ..
+                }),
+            ) => name.clone(),
+            _ => "someone".to_owned(),
+        };
+        BranchCommit {
+            branch: branch.to_owned(),
+            date: self.commit.committer.and_then(|committer| committer.date),
+            sha: self.sha,
+            author,
+            message: self.commit.message,
+            html_url: self.html_url,
+            pr: None,
+        }
+    }
+}
+
+// Take the pr number of squash merges, or ask GitHub otherwise.
+pub async fn get_commit_pr_number(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    commit: &BranchCommit,
+) -> Result<Option<u64>> {
+    match commit.squashed_pr_number() {
+        Some(number) => Ok(Some(number)),
+        None => get_merged_pr_of_sha(&octocrab, org, repo, &commit.sha).await,
+    }
+}
+
+// The merged pr containing the commit, asked for by its sha.
+pub async fn get_merged_pr_of_sha(
+    octocrab: &Octocrab,
+    org: &str,
+    repo: &str,
+    sha: &str,
+) -> Result<Option<u64>> {
+    let route = format!("repos/{org}/{repo}/commits/{sha}/pulls");
+    let pulls = retry(format!("{org}/{repo} prs of commit {sha}"), || {
+        octocrab.get::<Vec<CommitPull>, _, ()>(&route, None)
+    })
+    .await?;
+
+    Ok(pulls
+        .into_iter()
+        .find(|pr| pr.merged_at.is_some())
+        .map(|pr| pr.number))
+}
+
+// PR numbers of commits in their order, squashed commits tell theirs, and at most `limit`
+// others are asked for, the rest are taken as commits without a PR.
+pub async fn get_commit_pr_numbers(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    commits: &[BranchCommit],
+    limit: usize,
+) -> Result<Vec<Option<u64>>> {
+    let mut numbers = vec![];
+    let mut lookups = 0;
+    for commit in commits {
+        let number = match commit.squashed_pr_number() {
+            Some(number) => Some(number),
+            None if lookups < limit => {
+                lookups += 1;
+                get_commit_pr_number(octocrab.clone(), org, repo, commit).await?
+            }
+            None => None,
+        };
+        numbers.push(number);
+    }
+    let skipped = commits
+        .iter()
+        .filter(|commit| commit.squashed_pr_number().is_none())
+        .count()
+        .saturating_sub(lookups);
+    if skipped > 0 {
+        println!("{org}/{repo}: PRs of {skipped} commits are not asked for, at most {limit} are.");
+    }
+
+    Ok(numbers)
+}
+
+// Link of the pr on the same host as the commit.
+fn pr_url(org: &str, repo: &str, commit: &BranchCommit, number: u64) -> String {
+    let repo_url = commit
+        .html_url
+        .rsplit_once("/commit/")
+        .map(|(repo_url, _)| repo_url.to_owned())
+        .unwrap_or_else(|| format!("https://github.com/{org}/{repo}"));
+
+    format!("{repo_url}/pull/{number}")
+}
+
+// Commits of the branch newest first, until `keep_going` is false or `limit` commits are got.
+async fn scan_commits(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    branch: &str,
+    parameters: &[(&str, String)],
+    limit: usize,
+    keep_going: impl Fn(&BranchCommit) -> bool,
+) -> Result<Vec<BranchCommit>> {
+    let route = format!("repos/{org}/{repo}/commits");
+    let mut parameters = parameters.to_vec();
+    parameters.push(("sha", branch.to_owned()));
+    parameters.push(("per_page", "100".to_owned()));
+    let mut page = retry(format!("{org}/{repo} {branch} commits page 0"), || {
+        octocrab.get::<Page<CommitNode>, _, _>(&route, Some(&parameters))
+    })
+    .await?;
+
+    let mut commits = vec![];
+    let mut pull_times = 0u32;
+    'query_commit: loop {
+        for node in page.items.drain(..) {
+            let commit = node.into_commit(branch);
+            if !keep_going(&commit) || commits.len() >= limit {
+                break 'query_commit;
+            }
+            commits.push(commit);
+        }
+
+        pull_times += 1;
+        let next = retry(
+            format!("{org}/{repo} {branch} commits page {pull_times}"),
+            || octocrab.get_page::<CommitNode>(&page.next),
+        )
+        .await?;
+        page = match next {
+            Some(next_page) => next_page,
+            None => break,
+        };
+    }
+
+    Ok(commits)
+}
+
+async fn attach_prs(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    commits: &mut [BranchCommit],
+) -> Result<()> {
+    let numbers = get_commit_pr_numbers(octocrab, org, repo, commits, MAX_PR_LOOKUPS).await?;
+    for (commit, number) in commits.iter_mut().zip(numbers) {
+        commit.pr = number.map(|number| pr_url(org, repo, commit, number));
+    }
+
+    Ok(())
+}
+
+// Commits of the branch committed in (from, to), newest first.
+pub async fn get_commits_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    branch: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<BranchCommit>> {
+    let parameters = [("since", from.to_rfc3339()), ("until", to.to_rfc3339())];
+    let mut commits = scan_commits(
+        octocrab.clone(),
+        org,
+        repo,
+        branch,
+        &parameters,
+        usize::MAX,
+        |commit| commit.date >= Some(from),
+    )
+    .await?;
+    commits.retain(|commit| commit.date <= Some(to));
+    attach_prs(octocrab, org, repo, &mut commits).await?;
+
+    Ok(commits)
+}
+
+// Compare `base...head`, both can be a tag, a branch or a commit,
+// and `head` can be a branch of a fork in the same network, like `Manta-Network:polkadot-v0.9.33`.
+// The compare API puts commits in the body, so pages are walked by number until all are got.
+pub async fn compare_refs(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    base: &str,
+    head: &str,
+) -> Result<Comparison> {
+    let route = format!("repos/{org}/{repo}/compare/{base}...{head}");
+    let mut commits = vec![];
+    let mut page = 1u32;
+    loop {
+        let parameters = [("per_page", "100".to_owned()), ("page", page.to_string())];
+        let comparison = retry(format!("{org}/{repo} {base}...{head} page {page}"), || {
+            octocrab.get::<ComparisonPage, _, _>(&route, Some(&parameters))
+        })
+        .await?;
+        let got = comparison.commits.len();
+        commits.extend(
+            comparison
+                .commits
+                .into_iter()
+                .map(|node| node.into_commit(head)),
+        );
+        if got == 0 || commits.len() >= comparison.total_commits {
+            return Ok(Comparison {
+                ahead_by: comparison.ahead_by,
+                behind_by: comparison.behind_by,
+                commits,
+            });
+        }
+        page += 1;
+    }
+}
+
+// Commits reachable from `to_ref` but not from `from_ref`, oldest first, like `v0.9.32...v0.9.33`.
+pub async fn get_commits_between(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from_ref: &str,
+    to_ref: &str,
+) -> Result<Vec<BranchCommit>> {
+    Ok(compare_refs(octocrab, org, repo, from_ref, to_ref)
+        .await?
+        .commits)
+}
+
+// New commits of every branch since the last seen one, oldest first.
+// Nothing is new at the first time, only the head is remembered.
+// Heads are remembered once every branch is told, or new commits would be lost on failures.
+pub async fn update_commit_status(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    repository: &Repository,
+) -> Result<Vec<BranchCommit>> {
+    let (org, repo) = (&repository.organization, &repository.repository);
+    let mut all_commits = vec![];
+    let mut heads = vec![];
+    for branch in repository.branches.iter() {
+        let key = format!("{org}#{repo}#branches#{branch}");
+        let last_seen = db
+            .get(&key)?
+            .map(|sha| String::from_utf8_lossy(&sha).into_owned());
+        let limit = match last_seen {
+            Some(_) => MAX_COMMITS_PER_SYNC,
+            None => 1,
+        };
+        let mut commits = scan_commits(octocrab.clone(), org, repo, branch, &[], limit, |commit| {
+            Some(&commit.sha) != last_seen.as_ref()
+        })
+        .await?;
+        let Some(head) = commits.first() else {
+            continue;
+        };
+        heads.push((key, head.sha.clone()));
+        if last_seen.is_none() {
+            continue;
+        }
+
+        commits.reverse();
+        attach_prs(octocrab.clone(), org, repo, &mut commits).await?;
+        all_commits.append(&mut commits);
+    }
+    for (key, head) in heads {
+        db.insert(key, head.as_bytes())?;
+    }
+
+    Ok(all_commits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    #[tokio::test]
+    async fn update_commit_status_should_tell_new_commits_with_prs() {
+        let db = temporary_db();
+        let substrate = Repository {
+            organization: "paritytech".to_owned(),
+            repository: "substrate".to_owned(),
+            branches: vec!["polkadot-v0.9.33".to_owned()],
+            ..Default::default()
+        };
+
+        // Only the head is remembered at the first time.
+        let mock = MockGithub::start(&["github"]).await;
+        let commits = update_commit_status(mock.client(), db.clone(), &substrate)
+            .await
+            .unwrap();
+        assert!(commits.is_empty());
+        assert_eq!(
+            db.get("paritytech#substrate#branches#polkadot-v0.9.33")
+                .unwrap()
+                .unwrap(),
+            "3".repeat(40).as_bytes()
+        );
+
+        // New commits are told again if their prs failed to be asked for.
+        let mock = MockGithub::start(&["github-new-commits", "github"]).await;
+        mock.fail(
+            &format!(
+                "repos/paritytech/substrate/commits/{}/pulls",
+                "5".repeat(40)
+            ),
+            1,
+            422,
+            1,
+        );
+        assert!(update_commit_status(mock.client(), db.clone(), &substrate)
+            .await
+            .is_err());
+        assert_eq!(
+            db.get("paritytech#substrate#branches#polkadot-v0.9.33")
+                .unwrap()
+                .unwrap(),
+            "3".repeat(40).as_bytes()
+        );
+        let commits = update_commit_status(mock.client(), db.clone(), &substrate)
+            .await
+            .unwrap();
+        let told = commits
+            .iter()
+            .map(|commit| {
+                (
+                    commit.short_sha(),
+                    commit.author.as_str(),
+                    commit.pr.as_deref(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            told,
+            vec![
+                (
+                    "4444444",
+                    "bkchr",
+                    Some("https://github.com/paritytech/substrate/pull/12900")
+                ),
+                (
+                    "5555555",
+                    "Sergej Sakac",
+                    Some("https://github.com/paritytech/substrate/pull/12950")
+                ),
+            ]
+        );
+        assert_eq!(commits[0].branch, "polkadot-v0.9.33");
+        // The pr of a commit without its number in the title is asked for.
+        assert!(mock
+            .requests()
+            .iter()
+            .any(|request| request.contains(&format!("/commits/{}/pulls", "5".repeat(40)))));
+
+        // Nothing is new then.
+        let commits = update_commit_status(mock.client(), db.clone(), &substrate)
+            .await
+            .unwrap();
+        assert!(commits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pr_lookups_of_commits_should_be_capped() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (org, repo) = ("paritytech", "polkadot");
+        let commits = get_commits_between(mock.client(), org, repo, "v0.9.32", "v0.9.33")
+            .await
+            .unwrap();
+        let numbers = get_commit_pr_numbers(mock.client(), org, repo, &commits, 1)
+            .await
+            .unwrap();
+        // Squashed commits tell their PRs, and only the first other one is asked for.
+        assert_eq!(numbers.len(), 5);
+        assert_eq!(numbers[0], Some(6300));
+        assert!(numbers[1].is_some());
+        assert_eq!(&numbers[2..], [None, Some(6320), None]);
+        let lookups = mock
+            .requests()
+            .iter()
+            .filter(|request| request.ends_with("/pulls?"))
+            .count();
+        assert_eq!(lookups, 1);
+    }
+
+    #[tokio::test]
+    async fn get_commits_by_date_should_work() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-15", "2022-11-30").unwrap();
+        let commits = get_commits_by_date(
+            mock.client(),
+            "paritytech",
+            "substrate",
+            "polkadot-v0.9.33",
+            from,
+            to,
+        )
+        .await
+        .unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].author, "Keith Yeung");
+        assert_eq!(commits[0].pr, None);
+        assert_eq!(commits[1].title(), "Fix the weight of `set_code` (#12850)");
+        assert_eq!(
+            commits[1].pr.as_deref(),
+            Some("https://github.com/paritytech/substrate/pull/12850")
+        );
+    }
+}
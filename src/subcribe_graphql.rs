@@ -0,0 +1,744 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Query recent issues and PRs of many repositories in a few aliased GraphQL queries.
+//!
+//! Snapshots are diffed against sled like the REST subscription does, so both modes tell the same events.
+//! Releases are still synced by REST, a few latest ones in a snapshot can't tell what's new.
+
+use crate::links::LinkParser;
+use crate::subcribe_issues::{ClosedIssue, IssueEvent};
+use crate::subcribe_prs::{PrEvent, ReviewDecision, ReviewStatus};
+use crate::utils::Repository;
+use anyhow::{anyhow, Result};
+use chrono::prelude::*;
+use octocrab::{
+    models::{issues, pulls},
+    Octocrab,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sled::Db;
+use std::{collections::HashMap, sync::Arc};
+
+// How many repositories are queried by one GraphQL request.
+pub const REPOSITORIES_PER_QUERY: usize = 3;
+// How many recently updated issues/PRs are fetched for each repository.
+pub const ITEMS_PER_REPOSITORY: usize = 50;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    pub cost: u32,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RepositorySnapshot {
+    pub organization: String,
+    pub repository: String,
+    pub issues: Vec<issues::Issue>,
+    pub prs: Vec<pulls::PullRequest>,
+    // pr number => APPROVED, CHANGES_REQUESTED or REVIEW_REQUIRED
+    pub review_decisions: HashMap<u64, String>,
+    // issue number => completed, not_planned or reopened
+    pub state_reasons: HashMap<u64, String>,
+    // issue number => html url of the merged pr closing it
+    pub closing_prs: HashMap<u64, String>,
+}
+
+const ACTOR_FIELDS: &str = "login ... on User { databaseId }";
+
+const LABEL_FIELDS: &str = "labels(first: 20) { nodes { id name color description isDefault } }";
+
+const MILESTONE_FIELDS: &str =
+    "milestone { id number title description url state createdAt closedAt dueOn }";
+
+// What closed the issue, a pr or a commit of a pr.
+const CLOSER_FIELDS: &str = "timelineItems(last: 1, itemTypes: [CLOSED_EVENT]) { nodes { \
+                             ... on ClosedEvent { closer { __typename \
+                             ... on PullRequest { url merged } \
+                             ... on Commit { associatedPullRequests(first: 5) { nodes { url merged } } } \
+                             } } } }";
+
+pub fn build_query(repos: &[Repository]) -> String {
+    let common_fields = format!(
+        "id databaseId number title body url state createdAt updatedAt closedAt locked \
+         authorAssociation author {{ {ACTOR_FIELDS} }} {LABEL_FIELDS} {MILESTONE_FIELDS} \
+         assignees(first: 10) {{ nodes {{ login databaseId }} }} comments {{ totalCount }}"
+    );
+    let issue_fields = format!("{common_fields} stateReason {CLOSER_FIELDS}");
+    let pr_fields = format!(
+        "{common_fields} isDraft mergedAt reviewDecision headRefName headRefOid baseRefName \
+         baseRefOid mergeCommit {{ oid }}"
+    );
+
+    let mut query = String::from("query { rateLimit { cost limit remaining resetAt } ");
+    for (index, repo) in repos.iter().enumerate() {
+        query.push_str(&format!(
+            "r{index}: repository(owner: {:?}, name: {:?}) {{ \
+             issues(first: {ITEMS_PER_REPOSITORY}, orderBy: {{ field: UPDATED_AT, direction: DESC }}) {{ nodes {{ {issue_fields} }} }} \
+             pullRequests(first: {ITEMS_PER_REPOSITORY}, orderBy: {{ field: UPDATED_AT, direction: DESC }}) {{ nodes {{ {pr_fields} }} }} }} ",
+            repo.organization, repo.repository
+        ));
+    }
+    query.push('}');
+
+    query
+}
+
+// The REST models need a full user object, all urls can be derived from the login.
+fn user_value(api: &str, html: &str, actor: &Value) -> Value {
+    let login = actor["login"].as_str().unwrap_or("ghost");
+    let id = actor["databaseId"].as_u64().unwrap_or_default();
+    json!({
+        "login": login,
+        "id": id,
+        "node_id": "",
+        "avatar_url": format!("{html}{login}.png"),
+        "gravatar_id": "",
+        "url": format!("{api}users/{login}"),
+        "html_url": format!("{html}{login}"),
+        "followers_url": format!("{api}users/{login}/followers"),
+        "following_url": format!("{api}users/{login}/following"),
+        "gists_url": format!("{api}users/{login}/gists"),
+        "starred_url": format!("{api}users/{login}/starred"),
+        "subscriptions_url": format!("{api}users/{login}/subscriptions"),
+        "organizations_url": format!("{api}users/{login}/orgs"),
+        "repos_url": format!("{api}users/{login}/repos"),
+        "events_url": format!("{api}users/{login}/events"),
+        "received_events_url": format!("{api}users/{login}/received_events"),
+        "type": "User",
+        "site_admin": false,
+    })
+}
+
+fn labels_value(repo_api: &str, node: &Value) -> Value {
+    let labels = node["labels"]["nodes"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    labels
+        .iter()
+        .map(|label| {
+            let name = label["name"].as_str().unwrap_or_default();
+            json!({
+                // GraphQL doesn't expose the numeric id of labels.
+                "id": 0,
+                "node_id": label["id"],
+                "url": format!("{repo_api}/labels/{name}"),
+                "name": name,
+                "description": label["description"],
+                "color": label["color"],
+                "default": label["isDefault"].as_bool().unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+// GraphQL doesn't expose the numeric id of milestones either.
+fn milestone_value(repo_api: &str, node: &Value) -> Value {
+    let milestone = &node["milestone"];
+    if milestone.is_null() {
+        return Value::Null;
+    }
+    let number = milestone["number"].as_u64().unwrap_or_default();
+    json!({
+        "url": format!("{repo_api}/milestones/{number}"),
+        "html_url": milestone["url"],
+        "id": 0,
+        "node_id": milestone["id"],
+        "number": number,
+        "state": milestone["state"].as_str().map(str::to_lowercase),
+        "title": milestone["title"],
+        "description": milestone["description"],
+        "created_at": milestone["createdAt"],
+        "closed_at": milestone["closedAt"],
+        "due_on": milestone["dueOn"],
+    })
+}
+
+// The merged pr closing the issue, or the merged pr of the commit closing it.
+fn closing_pr_from_node(node: &Value) -> Option<String> {
+    let closer = &node["timelineItems"]["nodes"][0]["closer"];
+    let merged_url = |pr: &Value| match pr["merged"].as_bool() {
+        Some(true) => pr["url"].as_str().map(ToOwned::to_owned),
+        _ => None,
+    };
+    match closer["__typename"].as_str()? {
+        "PullRequest" => merged_url(closer),
+        "Commit" => closer["associatedPullRequests"]["nodes"]
+            .as_array()?
+            .iter()
+            .find_map(merged_url),
+        _ => None,
+    }
+}
+
+fn users_value(api: &str, html: &str, node: &Value) -> Value {
+    let users = node["nodes"].as_array().cloned().unwrap_or_default();
+    users
+        .iter()
+        .map(|user| user_value(api, html, user))
+        .collect()
+}
+
+fn issue_from_node(api: &str, html: &str, repo_api: &str, node: &Value) -> Result<issues::Issue> {
+    let number = node["number"].as_u64().unwrap_or_default();
+    let issue_api = format!("{repo_api}/issues/{number}");
+    let value = json!({
+        "id": node["databaseId"],
+        "node_id": node["id"],
+        "url": issue_api,
+        "repository_url": repo_api,
+        "labels_url": format!("{issue_api}/labels{{/name}}"),
+        "comments_url": format!("{issue_api}/comments"),
+        "events_url": format!("{issue_api}/events"),
+        "html_url": node["url"],
+        "number": number,
+        "state": node["state"].as_str().unwrap_or("OPEN").to_lowercase(),
+        "title": node["title"],
+        "body": node["body"],
+        "user": user_value(api, html, &node["author"]),
+        "labels": labels_value(repo_api, node),
+        "milestone": milestone_value(repo_api, node),
+        "assignees": users_value(api, html, &node["assignees"]),
+        "author_association": node["authorAssociation"],
+        "locked": node["locked"].as_bool().unwrap_or_default(),
+        "comments": node["comments"]["totalCount"].as_u64().unwrap_or_default(),
+        "closed_at": node["closedAt"],
+        "created_at": node["createdAt"],
+        "updated_at": node["updatedAt"],
+    });
+
+    Ok(serde_json::from_value(value)?)
+}
+
+fn pr_from_node(api: &str, html: &str, repo_api: &str, node: &Value) -> Result<pulls::PullRequest> {
+    let number = node["number"].as_u64().unwrap_or_default();
+    let html_url = node["url"].as_str().unwrap_or_default();
+    let state = match node["state"].as_str() {
+        Some("OPEN") => "open",
+        _ => "closed",
+    };
+    let value = json!({
+        "url": format!("{repo_api}/pulls/{number}"),
+        "id": node["databaseId"],
+        "node_id": node["id"],
+        "html_url": html_url,
+        "diff_url": format!("{html_url}.diff"),
+        "patch_url": format!("{html_url}.patch"),
+        "issue_url": format!("{repo_api}/issues/{number}"),
+        "number": number,
+        "state": state,
+        "locked": node["locked"].as_bool().unwrap_or_default(),
+        "title": node["title"],
+        "user": user_value(api, html, &node["author"]),
+        "body": node["body"],
+        "labels": labels_value(repo_api, node),
+        "milestone": milestone_value(repo_api, node),
+        "created_at": node["createdAt"],
+        "updated_at": node["updatedAt"],
+        "closed_at": node["closedAt"],
+        "merged_at": node["mergedAt"],
+        "merge_commit_sha": node["mergeCommit"]["oid"],
+        "assignees": users_value(api, html, &node["assignees"]),
+        "head": { "ref": node["headRefName"], "sha": node["headRefOid"] },
+        "base": { "ref": node["baseRefName"], "sha": node["baseRefOid"] },
+        "author_association": node["authorAssociation"],
+        "draft": node["isDraft"],
+    });
+
+    Ok(serde_json::from_value(value)?)
+}
+
+// Map the response of `build_query` onto the same models as the REST API returns.
+pub fn parse_response(
+    api: &str,
+    repos: &[Repository],
+    response: &Value,
+) -> Result<(Vec<RepositorySnapshot>, RateLimit)> {
+    if let Some(errors) = response.get("errors") {
+        return Err(anyhow!("GraphQL query failed: {errors}"));
+    }
+    let data = &response["data"];
+    let rate_limit: RateLimit = serde_json::from_value(data["rateLimit"].clone())?;
+
+    let mut snapshots = Vec::with_capacity(repos.len());
+    for (index, repo) in repos.iter().enumerate() {
+        let node = &data[format!("r{index}")];
+        if node.is_null() {
+            return Err(anyhow!(
+                "Repository {}/{} is not found.",
+                repo.organization,
+                repo.repository
+            ));
+        }
+        let repo_api = format!("{api}repos/{}/{}", repo.organization, repo.repository);
+        // html links are like https://github.com/org/repo/issues/1, so the host is the same for users.
+        let html = node["issues"]["nodes"][0]["url"]
+            .as_str()
+            .or_else(|| node["pullRequests"]["nodes"][0]["url"].as_str())
+            .and_then(|url| {
+                url.find(&format!("/{}/", repo.organization))
+                    .map(|end| url[..=end].to_owned())
+            })
+            .unwrap_or_else(|| "https://github.com/".to_owned());
+
+        let mut snapshot = RepositorySnapshot {
+            organization: repo.organization.clone(),
+            repository: repo.repository.clone(),
+            ..Default::default()
+        };
+        for issue in node["issues"]["nodes"].as_array().into_iter().flatten() {
+            let issue_model = issue_from_node(api, &html, &repo_api, issue)?;
+            let number = issue_model.number as u64;
+            if let Some(reason) = issue["stateReason"].as_str() {
+                snapshot.state_reasons.insert(number, reason.to_lowercase());
+            }
+            if let Some(closing_pr) = closing_pr_from_node(issue) {
+                snapshot.closing_prs.insert(number, closing_pr);
+            }
+            snapshot.issues.push(issue_model);
+        }
+        for pr in node["pullRequests"]["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            let pr_model = pr_from_node(api, &html, &repo_api, pr)?;
+            if let Some(decision) = pr["reviewDecision"].as_str() {
+                snapshot
+                    .review_decisions
+                    .insert(pr_model.number, decision.to_owned());
+            }
+            snapshot.prs.push(pr_model);
+        }
+        snapshots.push(snapshot);
+    }
+
+    Ok((snapshots, rate_limit))
+}
+
+// Query all repositories, return snapshots and the total cost of rate limit.
+pub async fn get_repositories_by_graphql(
+    octocrab: Arc<Octocrab>,
+    repos: &[Repository],
+) -> Result<(Vec<RepositorySnapshot>, u32)> {
+    let api = octocrab.base_url.to_string();
+    let graphql_url = crate::utils::graphql_url(&api);
+
+    let mut all_snapshots = Vec::with_capacity(repos.len());
+    let mut total_cost = 0u32;
+    let mut last_rate_limit: Option<RateLimit> = None;
+    for batch in repos.chunks(REPOSITORIES_PER_QUERY) {
+        // Stop querying if the remaining points cannot afford another batch,
+        // the rest of repositories will be synced next time.
+        if let Some(rate_limit) = &last_rate_limit {
+            if rate_limit.remaining < rate_limit.cost {
+                println!(
+                    "GraphQL rate limit is exhausted, remaining: {}, reset at: {:?}.",
+                    rate_limit.remaining, rate_limit.reset_at
+                );
+                break;
+            }
+        }
+
+        let query = build_query(batch);
+        let response: Value = octocrab
+            .post(&graphql_url, Some(&json!({ "query": query })))
+            .await?;
+        let (mut snapshots, rate_limit) = parse_response(&api, batch, &response)?;
+        println!(
+            "GraphQL query cost: {}, remaining: {}/{}.",
+            rate_limit.cost, rate_limit.remaining, rate_limit.limit
+        );
+        total_cost += rate_limit.cost;
+        last_rate_limit = Some(rate_limit);
+        all_snapshots.append(&mut snapshots);
+    }
+
+    Ok((all_snapshots, total_cost))
+}
+
+// Tell what happened to issues of a snapshot since the last sync, like `update_issue_status`.
+// Only recently updated issues are in a snapshot, so an archived open issue missing from it is
+// still open, and new issues are archived without being told.
+async fn diff_issues(db: Arc<Db>, snapshot: &RepositorySnapshot) -> Result<Vec<IssueEvent>> {
+    let (org, repo) = (&snapshot.organization, &snapshot.repository);
+    let mut events = vec![];
+    let mut open_issues = vec![];
+    for issue in snapshot.issues.iter() {
+        let open_key = format!("{org}#{repo}#issues#open#{0}", issue.number);
+        let old_issue = match db.get(open_key.as_bytes())? {
+            Some(old) => Some(serde_json::from_slice::<issues::Issue>(&old)?),
+            None => None,
+        };
+        match (issue.state == "open", old_issue) {
+            (true, Some(old_issue)) => {
+                for change in crate::diff::issue_changes(&old_issue, issue) {
+                    events.push(IssueEvent::Changed(issue.clone(), change));
+                }
+                open_issues.push(issue.clone());
+            }
+            // if a closed issue is open again, that means this issue has been reopened.
+            (true, None) => {
+                let closed_key = format!("{org}#{repo}#issues#closed#{0}", issue.number);
+                if db.remove(closed_key.as_bytes())?.is_some() {
+                    let new_key_prefix = format!("{org}#{repo}#issues#reopened");
+                    crate::db::insert_one_issue(db.clone(), &new_key_prefix, issue).await?;
+                    events.push(IssueEvent::Reopened(issue.clone()));
+                }
+                open_issues.push(issue.clone());
+            }
+            (false, Some(_)) => {
+                let number = issue.number as u64;
+                let state_reason = snapshot.state_reasons.get(&number).cloned();
+                // only completed issues can be closed by a pr.
+                let closing_pr = match state_reason.as_deref() {
+                    Some("completed") => snapshot.closing_prs.get(&number).cloned(),
+                    _ => None,
+                };
+                let closed_issue = ClosedIssue {
+                    issue: issue.clone(),
+                    state_reason,
+                    closing_pr,
+                };
+                let _ = db.remove(open_key.as_bytes())?;
+                let closed_key = format!("{org}#{repo}#issues#closed#{0}", issue.number);
+                db.insert(closed_key.as_bytes(), serde_json::to_vec(&closed_issue)?)?;
+                events.push(IssueEvent::Closed(closed_issue));
+            }
+            // closed before it's archived, nothing happened since the last sync.
+            (false, None) => (),
+        }
+    }
+    crate::db::insert_batch_issues(db, &format!("{org}#{repo}#issues#open"), &open_issues).await?;
+
+    Ok(events)
+}
+
+// Tell what happened to prs of a snapshot since the last sync, like `update_pr_status`
+// and `update_review_status` if the repository tracks reviews.
+async fn diff_prs(
+    db: Arc<Db>,
+    repository: &Repository,
+    snapshot: &RepositorySnapshot,
+) -> Result<Vec<PrEvent>> {
+    let (org, repo) = (&snapshot.organization, &snapshot.repository);
+    let mut events = vec![];
+    let mut open_prs = vec![];
+    for pr in snapshot.prs.iter() {
+        let open_key = format!("{org}#{repo}#prs#open#{0}", pr.number);
+        let old_pr = match db.get(open_key.as_bytes())? {
+            Some(old) => Some(serde_json::from_slice::<pulls::PullRequest>(&old)?),
+            None => None,
+        };
+        match (pr.merged_at, pr.closed_at, old_pr) {
+            (None, None, Some(old_pr)) => {
+                match (
+                    crate::subcribe_prs::is_draft(&old_pr),
+                    crate::subcribe_prs::is_draft(pr),
+                ) {
+                    (false, true) => events.push(PrEvent::ConvertedToDraft(pr.clone())),
+                    (true, false) => events.push(PrEvent::ReadyForReview(pr.clone())),
+                    _ => (),
+                }
+                for change in crate::diff::pr_changes(&old_pr, pr) {
+                    events.push(PrEvent::Changed(pr.clone(), change));
+                }
+                open_prs.push(pr.clone());
+            }
+            (None, None, None) => {
+                events.push(PrEvent::New(pr.clone()));
+                open_prs.push(pr.clone());
+            }
+            (merged_at, Some(_), Some(_)) => {
+                let _ = db.remove(open_key.as_bytes())?;
+                let _ = db.remove(format!("{org}#{repo}#reviews#{0}", pr.number))?;
+                let state = match merged_at {
+                    Some(_) => "merged",
+                    None => "closed",
+                };
+                crate::db::insert_one_pr(db.clone(), &format!("{org}#{repo}#prs#{state}"), pr)
+                    .await?;
+                events.push(match merged_at {
+                    Some(_) => PrEvent::Merged(pr.clone()),
+                    None => PrEvent::Closed(pr.clone()),
+                });
+            }
+            // merged or closed before it's archived, nothing happened since the last sync.
+            _ => (),
+        }
+    }
+    crate::db::insert_batch_prs(db.clone(), &format!("{org}#{repo}#prs#open"), &open_prs).await?;
+
+    if !repository.track_reviews {
+        return Ok(events);
+    }
+    // GraphQL knows the review decision only, who approved it is left to the next REST sync.
+    for pr in open_prs {
+        let decision = snapshot
+            .review_decisions
+            .get(&pr.number)
+            .and_then(|decision| ReviewDecision::from_graphql(decision));
+        let Some(decision) = decision else {
+            continue;
+        };
+        let key = format!("{org}#{repo}#reviews#{0}", pr.number);
+        let old_decision = match db.get(key.as_bytes())? {
+            Some(old) => Some(serde_json::from_slice::<ReviewStatus>(&old)?.decision),
+            None => None,
+        };
+        // Dismissals are unknown to GraphQL, a dismissed pr requires reviews again.
+        if old_decision == Some(decision)
+            || (old_decision == Some(ReviewDecision::Dismissed)
+                && decision == ReviewDecision::ReviewRequired)
+        {
+            continue;
+        }
+        let status = ReviewStatus {
+            decision,
+            approved_by: vec![],
+            pr_updated_at: None,
+        };
+        db.insert(key.as_bytes(), serde_json::to_vec(&status)?)?;
+        match decision {
+            ReviewDecision::Approved => events.push(PrEvent::Approved(pr, vec![])),
+            ReviewDecision::ChangesRequested => events.push(PrEvent::ChangesRequested(pr)),
+            ReviewDecision::Dismissed | ReviewDecision::ReviewRequired => (),
+        }
+    }
+
+    Ok(events)
+}
+
+// Archive a snapshot with the same keys as the REST subscription does,
+// and tell the same events, so the next sync of either mode goes on from it.
+pub async fn archive_snapshot(
+    db: Arc<Db>,
+    repository: &Repository,
+    snapshot: &RepositorySnapshot,
+    parser: &LinkParser,
+) -> Result<(Vec<IssueEvent>, Vec<PrEvent>)> {
+    let issue_events = diff_issues(db.clone(), snapshot).await?;
+    let pr_events = diff_prs(db.clone(), repository, snapshot).await?;
+    for pr in snapshot.prs.iter() {
+        crate::links::archive_links(
+            &db,
+            parser,
+            &snapshot.organization,
+            &snapshot.repository,
+            pr,
+        )?;
+    }
+
+    Ok((issue_events, pr_events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repositories() -> Vec<Repository> {
+        vec![Repository {
+            organization: "paritytech".to_owned(),
+            repository: "substrate".to_owned(),
+            ..Default::default()
+        }]
+    }
+
+    #[test]
+    fn build_query_should_alias_every_repository() {
+        let mut repos = repositories();
+        repos.push(Repository {
+            organization: "paritytech".to_owned(),
+            repository: "polkadot".to_owned(),
+            query_release: true,
+            ..Default::default()
+        });
+        let query = build_query(&repos);
+        assert!(query.contains("rateLimit { cost limit remaining resetAt }"));
+        assert!(query.contains(r#"r0: repository(owner: "paritytech", name: "substrate")"#));
+        assert!(query.contains(r#"r1: repository(owner: "paritytech", name: "polkadot")"#));
+        assert!(query.contains("reviewDecision"));
+    }
+
+    #[test]
+    fn parse_response_should_work() {
+        let response = json!({
+            "data": {
+                "rateLimit": { "cost": 1, "limit": 5000, "remaining": 4999, "resetAt": "2022-11-25T15:00:00Z" },
+                "r0": {
+                    "issues": { "nodes": [{
+                        "id": "I_1", "databaseId": 100, "number": 12900,
+                        "title": "client/mmr: make it resilient", "body": null,
+                        "url": "https://github.com/paritytech/substrate/issues/12900",
+                        "state": "CLOSED", "createdAt": "2022-11-25T14:45:34Z",
+                        "updatedAt": "2022-11-25T15:45:34Z", "closedAt": "2022-11-25T15:45:34Z",
+                        "locked": false, "authorAssociation": "MEMBER",
+                        "author": { "login": "acatangiu", "databaseId": 1 },
+                        "labels": { "nodes": [{ "id": "L_1", "name": "I3-bug", "color": "d73a4a", "description": null, "isDefault": false }] },
+                        "assignees": { "nodes": [] },
+                        "comments": { "totalCount": 2 }
+                    }]},
+                    "pullRequests": { "nodes": [{
+                        "id": "PR_1", "databaseId": 200, "number": 12901,
+                        "title": "add EnsureWithSuccess", "body": "",
+                        "url": "https://github.com/paritytech/substrate/pull/12901",
+                        "state": "MERGED", "createdAt": "2022-11-23T14:45:34Z",
+                        "updatedAt": "2022-11-24T05:05:54Z", "closedAt": "2022-11-24T05:05:54Z",
+                        "locked": false, "authorAssociation": "CONTRIBUTOR",
+                        "author": { "login": "xlc", "databaseId": 2 },
+                        "labels": { "nodes": [] },
+                        "assignees": { "nodes": [] },
+                        "comments": { "totalCount": 0 },
+                        "isDraft": false, "mergedAt": "2022-11-24T05:05:54Z",
+                        "reviewDecision": "APPROVED",
+                        "headRefName": "ensure", "headRefOid": "abc",
+                        "baseRefName": "master", "baseRefOid": "def",
+                        "mergeCommit": { "oid": "123" }
+                    }]},
+                    "releases": { "nodes": [] }
+                }
+            }
+        });
+        let (snapshots, rate_limit) =
+            parse_response("https://api.github.com/", &repositories(), &response).unwrap();
+        assert_eq!(rate_limit.remaining, 4999);
+        assert_eq!(snapshots.len(), 1);
+
+        let issue = &snapshots[0].issues[0];
+        assert_eq!(issue.number, 12900);
+        assert_eq!(issue.state, "closed");
+        assert_eq!(issue.labels[0].name, "I3-bug");
+        assert_eq!(issue.user.login, "acatangiu");
+
+        let pr = &snapshots[0].prs[0];
+        assert_eq!(pr.number, 12901);
+        assert!(pr.merged_at.is_some());
+        assert_eq!(pr.draft, Some(false));
+        assert_eq!(
+            pr.url,
+            "https://api.github.com/repos/paritytech/substrate/pulls/12901"
+        );
+        assert_eq!(
+            snapshots[0]
+                .review_decisions
+                .get(&12901)
+                .map(String::as_str),
+            Some("APPROVED")
+        );
+    }
+
+    #[tokio::test]
+    async fn get_repositories_by_graphql_should_archive_snapshots() {
+        let mock = crate::mock_github::MockGithub::start(&["github"]).await;
+        let db = crate::mock_github::temporary_db();
+        let mut repos = repositories();
+        repos[0].track_reviews = true;
+        repos.push(Repository {
+            organization: "paritytech".to_owned(),
+            repository: "polkadot".to_owned(),
+            query_release: true,
+            ..Default::default()
+        });
+
+        let (snapshots, total_cost) = get_repositories_by_graphql(mock.client(), &repos)
+            .await
+            .unwrap();
+        assert_eq!(total_cost, 1);
+        assert_eq!(mock.requests(), vec!["POST /graphql?".to_owned()]);
+        let parser = LinkParser::new("https://github.com/", ["substrate", "polkadot"]);
+        let (issue_events, pr_events) =
+            archive_snapshot(db.clone(), &repos[0], &snapshots[0], &parser)
+                .await
+                .unwrap();
+        // nothing is archived yet, closed issues and prs are not told again.
+        assert!(issue_events.is_empty());
+        let pr_events = pr_events.iter().map(PrEvent::summary).collect::<Vec<_>>();
+        assert_eq!(pr_events, vec!["new"]);
+
+        let keys = db
+            .scan_prefix("paritytech#substrate#")
+            .keys()
+            .map(|key| String::from_utf8(key.unwrap().to_vec()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "paritytech#substrate#issues#open#12900",
+                "paritytech#substrate#prs#open#12945",
+                "paritytech#substrate#reviews#12945",
+            ]
+        );
+
+        // the next round diffs against the archived snapshot.
+        let mock = crate::mock_github::MockGithub::start(&["github-graphql-next", "github"]).await;
+        let (snapshots, _) = get_repositories_by_graphql(mock.client(), &repos)
+            .await
+            .unwrap();
+        let (issue_events, pr_events) =
+            archive_snapshot(db.clone(), &repos[0], &snapshots[0], &parser)
+                .await
+                .unwrap();
+        assert_eq!(issue_events.len(), 1);
+        let IssueEvent::Closed(closed) = &issue_events[0] else {
+            panic!("issue 12900 should be closed");
+        };
+        assert_eq!(closed.issue.number, 12900);
+        assert_eq!(closed.state_reason.as_deref(), Some("completed"));
+        assert_eq!(
+            closed.closing_pr.as_deref(),
+            Some("https://github.com/paritytech/substrate/pull/12945")
+        );
+        let pr_events = pr_events
+            .iter()
+            .map(|event| (event.pr().number, event.summary()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pr_events,
+            vec![
+                (12950, "new".to_owned()),
+                (12945, "merged".to_owned()),
+                (12950, "approved".to_owned()),
+            ]
+        );
+        let keys = db
+            .scan_prefix("paritytech#substrate#")
+            .keys()
+            .map(|key| String::from_utf8(key.unwrap().to_vec()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "paritytech#substrate#issues#closed#12900",
+                "paritytech#substrate#prs#merged#12945",
+                "paritytech#substrate#prs#open#12950",
+                "paritytech#substrate#reviews#12950",
+            ]
+        );
+        assert_eq!(db.scan_prefix("paritytech#polkadot#releases#").count(), 0);
+    }
+
+    #[test]
+    fn parse_response_should_fail_on_errors() {
+        let response = json!({ "errors": [{ "message": "Could not resolve to a Repository" }] });
+        assert!(parse_response("https://api.github.com/", &repositories(), &response).is_err());
+    }
+}
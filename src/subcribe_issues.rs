@@ -14,13 +14,17 @@
 // You should have received a copy of the GNU General Public License
 // along with Manta.  If not, see <http://www.gnu.org/licenses/>.
 
-//! Only query open issues
+//! Query open, closed and reopened issues
 
+use crate::diff::FieldChange;
+use crate::retry::{retry, Incomplete};
 use anyhow::Result;
 use chrono::prelude::*;
-use octocrab::{models::issues, params, Octocrab};
+use octocrab::{models::issues, params, Octocrab, Page};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use sled::Db;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 pub async fn get_open_issues_by_date(
     octocrab: Arc<Octocrab>,
@@ -29,17 +33,21 @@ pub async fn get_open_issues_by_date(
     from: DateTime<Utc>,
     to: DateTime<Utc>,
 ) -> Result<Vec<issues::Issue>> {
-    let mut page = octocrab
-        .issues(org, repo)
-        .list()
-        .state(params::State::Open)
-        .sort(params::issues::Sort::Created)
-        .direction(params::Direction::Descending)
-        .per_page(100)
-        .send()
-        .await?;
+    let mut page = retry(format!("{org}/{repo} issues page 0"), || async {
+        octocrab
+            .issues(org, repo)
+            .list()
+            .state(params::State::Open)
+            .sort(params::issues::Sort::Created)
+            .direction(params::Direction::Descending)
+            .per_page(100)
+            .send()
+            .await
+    })
+    .await?;
 
     let mut all_issues = vec![];
+    let mut pull_times = 0u32;
     'query_issue: loop {
         for issue in &page {
             // seems there's a bug, some PRs will be returned.
@@ -60,9 +68,12 @@ pub async fn get_open_issues_by_date(
         }
 
         // go to next page of issues.
-        page = match octocrab.get_page::<issues::Issue>(&page.next).await? {
-            Some(next_page) => next_page,
-            None => break,
+        pull_times += 1;
+        let what = format!("{org}/{repo} issues page {pull_times}");
+        page = match crate::retry::next_page(&what, &octocrab, &page).await {
+            Ok(Some(next_page)) => next_page,
+            Ok(None) => break,
+            Err(e) => return Err(Incomplete::new(what, e, all_issues).into()),
         }
     }
 
@@ -74,15 +85,19 @@ pub async fn get_all_open_issues(
     org: &str,
     repo: &str,
 ) -> Result<Vec<issues::Issue>> {
-    let mut page = octocrab
-        .issues(org, repo)
-        .list()
-        .state(params::State::Open)
-        .per_page(50)
-        .send()
-        .await?;
+    let mut page = retry(format!("{org}/{repo} open issues page 0"), || async {
+        octocrab
+            .issues(org, repo)
+            .list()
+            .state(params::State::Open)
+            .per_page(50)
+            .send()
+            .await
+    })
+    .await?;
 
     let mut all_issues = vec![];
+    let mut pull_times = 0u32;
     loop {
         for issue in &page {
             // seems there's a bug, some PRs will be returned.
@@ -94,7 +109,13 @@ pub async fn get_all_open_issues(
         }
 
         // go to next page of issues.
-        page = match octocrab.get_page::<issues::Issue>(&page.next).await? {
+        pull_times += 1;
+        let next = retry(
+            format!("{org}/{repo} open issues page {pull_times}"),
+            || octocrab.get_page::<issues::Issue>(&page.next),
+        )
+        .await?;
+        page = match next {
             Some(next_page) => next_page,
             None => break,
         }
@@ -103,33 +124,365 @@ pub async fn get_all_open_issues(
     Ok(all_issues)
 }
 
-// return closed issues
-pub async fn update_issue_status(db: Arc<Db>, org: &str, repo: &str) -> Result<Vec<issues::Issue>> {
-    let octocrab = octocrab::instance();
+// `issues::Issue` of octocrab has no `state_reason`, and GitHub doesn't tell which pr closed the issue.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ClosedIssue {
+    #[serde(flatten)]
+    pub issue: issues::Issue,
+    // completed, not_planned or reopened.
+    pub state_reason: Option<String>,
+    // html url of the merged pr which closed the issue.
+    #[serde(default)]
+    pub closing_pr: Option<String>,
+}
 
-    // pr has 2 status: open, closed
-    let open_issues = get_all_open_issues(octocrab, org, repo).await?;
-    // insert open issues
-    let key_prefix = format!("{org}#{repo}#issues#open");
-    crate::db::insert_batch_issues(db.clone(), &key_prefix, &open_issues).await?;
+// An event of the issue events API or the timeline API, only fields in use are kept.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IssueActivity {
+    // Cross-referenced events in timeline have no id.
+    pub id: Option<u64>,
+    pub event: String,
+    pub created_at: Option<DateTime<Utc>>,
+    // Only in the issue events API.
+    pub issue: Option<issues::Issue>,
+    // In cross-referenced events, where the issue is mentioned,
+    // and in closed events, the pr closing it if GitHub tells it.
+    pub source: Option<Value>,
+    // The commit closing the issue in closed events, like the merge commit of a pr.
+    pub commit_id: Option<String>,
+    pub commit_url: Option<String>,
+}
+
+// What happened to an issue since the last sync.
+#[derive(Clone, Debug)]
+pub enum IssueEvent {
+    Closed(ClosedIssue),
+    Reopened(issues::Issue),
+    Changed(issues::Issue, FieldChange),
+}
+
+impl IssueEvent {
+    pub fn issue(&self) -> &issues::Issue {
+        match self {
+            Self::Closed(closed) => &closed.issue,
+            Self::Reopened(issue) | Self::Changed(issue, _) => issue,
+        }
+    }
 
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Closed(_) => "closed",
+            Self::Reopened(_) => "reopened",
+            Self::Changed(..) => "changed",
+        }
+    }
+
+    // Like `closed as completed by https://github.com/org/repo/pull/1`.
+    pub fn summary(&self) -> String {
+        match self {
+            Self::Closed(ClosedIssue {
+                state_reason,
+                closing_pr,
+                ..
+            }) => {
+                let mut summary = format!(
+                    "closed as {}",
+                    state_reason
+                        .as_deref()
+                        .unwrap_or("completed")
+                        .replace('_', " ")
+                );
+                if let Some(pr) = closing_pr {
+                    summary.push_str(&format!(" by {pr}"));
+                }
+                summary
+            }
+            Self::Reopened(_) => self.kind().to_owned(),
+            Self::Changed(_, change) => change.to_string(),
+        }
+    }
+}
+
+// return closed and reopened issues
+pub async fn update_issue_status(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    org: &str,
+    repo: &str,
+) -> Result<Vec<IssueEvent>> {
+    // issue has 2 status: open, closed
+    let open_issues = get_all_open_issues(octocrab.clone(), org, repo).await?;
+    let key_prefix = format!("{org}#{repo}#issues#open");
+    // read archived issues before they're overwritten by current ones.
     let existing_issues =
         crate::db::get_all_archived_issues(db.clone(), key_prefix.as_bytes()).await?;
 
-    let mut closed_issues = vec![];
-    for old_issue in existing_issues {
+    // issues are identified by number, other fields may change anytime.
+    let open_by_number = open_issues
+        .iter()
+        .map(|issue| (issue.number, issue))
+        .collect::<HashMap<_, _>>();
+    let existing_by_number = existing_issues
+        .iter()
+        .map(|issue| (issue.number, issue))
+        .collect::<HashMap<_, _>>();
+
+    let mut events = vec![];
+    for old_issue in existing_issues.iter() {
+        // the issue is still open, but its title, labels and the like may be changed.
+        if let Some(issue) = open_by_number.get(&old_issue.number) {
+            for change in crate::diff::issue_changes(old_issue, issue) {
+                events.push(IssueEvent::Changed((*issue).clone(), change));
+            }
+            continue;
+        }
+
         // if old issue is not in current open issues, that means this issue has been closed.
-        if !open_issues.contains(&old_issue) {
-            // delete the issue if it has been closed.
-            let old_key_prefix = format!("{org}#{repo}#issues#open#{0}", old_issue.number);
-            let _ = db.remove(old_key_prefix.as_bytes())?;
-            let new_key_prefix = format!("{org}#{repo}#issues#closed");
-            crate::db::insert_one_issue(db.clone(), &new_key_prefix, &old_issue).await?;
-            closed_issues.push(old_issue);
+        // find out why it's closed, it may be open still if it's reopened during the query.
+        let closed_issue =
+            get_closed_issue_by_id(&octocrab, old_issue.number as u64, org, repo).await?;
+        if closed_issue.issue.closed_at.is_none() {
+            continue;
+        }
+        // delete the issue if it has been closed.
+        let old_key_prefix = format!("{org}#{repo}#issues#open#{0}", old_issue.number);
+        let _ = db.remove(old_key_prefix.as_bytes())?;
+        let new_key = format!("{org}#{repo}#issues#closed#{0}", old_issue.number);
+        db.insert(new_key.as_bytes(), serde_json::to_vec(&closed_issue)?)?;
+        events.push(IssueEvent::Closed(closed_issue));
+    }
+
+    // if a closed issue is open again, that means this issue has been reopened.
+    for issue in open_issues.iter() {
+        if existing_by_number.contains_key(&issue.number) {
+            continue;
+        }
+        let closed_key = format!("{org}#{repo}#issues#closed#{0}", issue.number);
+        if db.remove(closed_key.as_bytes())?.is_some() {
+            let new_key_prefix = format!("{org}#{repo}#issues#reopened");
+            crate::db::insert_one_issue(db.clone(), &new_key_prefix, issue).await?;
+            events.push(IssueEvent::Reopened(issue.clone()));
         }
     }
 
-    Ok(closed_issues)
+    // insert open issues
+    crate::db::insert_batch_issues(db.clone(), &key_prefix, &open_issues).await?;
+
+    Ok(events)
+}
+
+// Issues closed in (from, to), with the reason and the closing pr.
+pub async fn get_closed_issues_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<ClosedIssue>> {
+    let route = format!("repos/{org}/{repo}/issues");
+    let parameters = [
+        ("state", "closed"),
+        ("sort", "updated"),
+        ("direction", "desc"),
+        ("per_page", "100"),
+    ];
+    let mut page = retry(format!("{org}/{repo} closed issues page 0"), || {
+        octocrab.get::<Page<ClosedIssue>, _, _>(&route, Some(&parameters))
+    })
+    .await?;
+
+    let mut all_issues = vec![];
+    let mut pull_times = 0u32;
+    let mut failure = None;
+    'query_issue: loop {
+        for closed in &page {
+            let issue = &closed.issue;
+            // seems there's a bug, some PRs will be returned.
+            // so need to filter PRs.
+            if issue.html_url.as_str().contains("pull") {
+                continue;
+            }
+
+            // updated_at >= closed_at always, and issues are sorted by updated date.
+            if issue.updated_at < from {
+                break 'query_issue;
+            }
+            if issue.closed_at >= Some(from) && issue.closed_at <= Some(to) {
+                all_issues.push(closed.clone());
+            }
+        }
+
+        // go to next page of issues.
+        pull_times += 1;
+        let what = format!("{org}/{repo} closed issues page {pull_times}");
+        page = match crate::retry::next_page(&what, &octocrab, &page).await {
+            Ok(Some(next_page)) => next_page,
+            Ok(None) => break,
+            Err(e) => {
+                failure = Some((what, e));
+                break;
+            }
+        }
+    }
+
+    // only completed issues can be closed by a pr.
+    for closed in all_issues.iter_mut() {
+        if closed.state_reason.as_deref() == Some("completed") {
+            closed.closing_pr =
+                get_closing_pr(&octocrab, closed.issue.number as u64, org, repo).await?;
+        }
+    }
+    // closing prs of issues fetched are still looked up.
+    if let Some((what, e)) = failure {
+        return Err(Incomplete::new(what, e, all_issues).into());
+    }
+
+    Ok(all_issues)
+}
+
+// Issues reopened in (from, to) and when they're reopened, the latest reopening goes first.
+pub async fn get_reopened_issues_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<(DateTime<Utc>, issues::Issue)>> {
+    let route = format!("repos/{org}/{repo}/issues/events");
+    let parameters = [("per_page", "100")];
+    let mut page = retry(format!("{org}/{repo} issue events page 0"), || {
+        octocrab.get::<Page<IssueActivity>, _, _>(&route, Some(&parameters))
+    })
+    .await?;
+
+    let mut all_issues: Vec<(DateTime<Utc>, issues::Issue)> = vec![];
+    let mut pull_times = 0u32;
+    'query_event: loop {
+        // events are sorted by created date already.
+        for activity in &page {
+            let (Some(created_at), Some(issue)) = (activity.created_at, activity.issue.as_ref())
+            else {
+                continue;
+            };
+            if created_at < from {
+                break 'query_event;
+            }
+            if created_at > to
+                || activity.event != "reopened"
+                || issue.html_url.as_str().contains("pull")
+                || all_issues.iter().any(|(_, i)| i.number == issue.number)
+            {
+                continue;
+            }
+            all_issues.push((created_at, issue.clone()));
+        }
+
+        // go to next page of events.
+        pull_times += 1;
+        let what = format!("{org}/{repo} issue events page {pull_times}");
+        page = match crate::retry::next_page(&what, &octocrab, &page).await {
+            Ok(Some(next_page)) => next_page,
+            Ok(None) => break,
+            Err(e) => return Err(Incomplete::new(what, e, all_issues).into()),
+        }
+    }
+
+    Ok(all_issues)
+}
+
+pub async fn get_closed_issue_by_id(
+    octo: &Octocrab,
+    id: u64,
+    org: &str,
+    repo: &str,
+) -> Result<ClosedIssue> {
+    let route = format!("repos/{org}/{repo}/issues/{id}");
+    let mut closed = retry(format!("{org}/{repo} issue {id}"), || {
+        octo.get::<ClosedIssue, _, ()>(&route, None)
+    })
+    .await?;
+    if closed.issue.closed_at.is_some() && closed.state_reason.as_deref() == Some("completed") {
+        closed.closing_pr = get_closing_pr(octo, id, org, repo).await?;
+    }
+    Ok(closed)
+}
+
+// Link of the merged pr in the source of a timeline event.
+fn merged_pr_url(source: &Value) -> Option<&str> {
+    let issue = &source["issue"];
+    (!issue["pull_request"]["merged_at"].is_null())
+        .then(|| issue["html_url"].as_str())
+        .flatten()
+}
+
+// The merged pr closing the issue, told by the last closed event of its timeline.
+// Closing keywords like `fixes #1` make a cross-referenced event, and the closed event
+// has the merge commit of the pr, so a pr only mentioning the issue doesn't count.
+pub async fn get_closing_pr(
+    octo: &Octocrab,
+    id: u64,
+    org: &str,
+    repo: &str,
+) -> Result<Option<String>> {
+    let route = format!("repos/{org}/{repo}/issues/{id}/timeline");
+    let parameters = [("per_page", "100")];
+    let mut page = retry(format!("{org}/{repo} issue {id} timeline page 0"), || {
+        octo.get::<Page<IssueActivity>, _, _>(&route, Some(&parameters))
+    })
+    .await?;
+    let mut timeline = vec![];
+    let mut pull_times = 0u32;
+    loop {
+        timeline.append(&mut page.items);
+        pull_times += 1;
+        let next = retry(
+            format!("{org}/{repo} issue {id} timeline page {pull_times}"),
+            || octo.get_page::<IssueActivity>(&page.next),
+        )
+        .await?;
+        page = match next {
+            Some(next_page) => next_page,
+            None => break,
+        };
+    }
+
+    let Some(closed) = timeline
+        .iter()
+        .rev()
+        .find(|activity| activity.event == "closed")
+    else {
+        return Ok(None);
+    };
+    if let Some(url) = closed.source.as_ref().and_then(merged_pr_url) {
+        return Ok(Some(url.to_owned()));
+    }
+    let Some(sha) = closed.commit_id.as_deref() else {
+        return Ok(None);
+    };
+    // The commit may be in another repository, like `repos/org/repo/commits/sha`.
+    let (commit_org, commit_repo) = closed
+        .commit_url
+        .as_deref()
+        .and_then(|url| url.split_once("/repos/"))
+        .and_then(|(_, path)| {
+            let mut parts = path.split('/');
+            Some((parts.next()?, parts.next()?))
+        })
+        .unwrap_or((org, repo));
+    let Some(number) =
+        crate::subcribe_commits::get_merged_pr_of_sha(octo, commit_org, commit_repo, sha).await?
+    else {
+        return Ok(None);
+    };
+    let suffix = format!("/{commit_org}/{commit_repo}/pull/{number}").to_lowercase();
+    let closing_pr = timeline
+        .iter()
+        .filter(|activity| activity.event == "cross-referenced")
+        .filter_map(|activity| activity.source.as_ref().and_then(merged_pr_url))
+        .find(|url| url.to_lowercase().ends_with(&suffix))
+        .map(ToOwned::to_owned);
+
+    Ok(closing_pr)
 }
 
 pub async fn get_issue_by_id(
@@ -138,17 +491,191 @@ pub async fn get_issue_by_id(
     org: &str,
     repo: &str,
 ) -> Result<issues::Issue> {
-    let issue = octo.issues(org, repo).get(id).await?;
+    let issue = retry(format!("{org}/{repo} issue {id}"), || async {
+        octo.issues(org, repo).get(id).await
+    })
+    .await?;
     Ok(issue)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
 
+    // It only opens the db, the unused `db` is allowed so that `-D warnings` passes.
     #[tokio::test]
     #[ignore]
+    #[allow(unused_variables)]
     async fn get_issues_should_work() {
         let db = crate::utils::db_config().unwrap();
     }
+
+    #[tokio::test]
+    async fn get_open_issues_by_date_should_work() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-20", "2022-11-21").unwrap();
+        let issues = get_open_issues_by_date(mock.client(), "Manta-Network", "docs", from, to)
+            .await
+            .unwrap();
+        // #117 is a pr, #120 is newer, #110 is older.
+        assert_eq!(
+            issues.iter().map(|i| i.number).collect::<Vec<_>>(),
+            vec![118]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_issue_status_should_record_closed_and_reopened_issues() {
+        let db = temporary_db();
+        let (org, repo) = ("Manta-Network", "docs");
+
+        let mock = MockGithub::start(&["github"]).await;
+        let events = update_issue_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert!(events.is_empty());
+
+        // #118 has been closed by #119 in the next round.
+        let mock = MockGithub::start(&["github-closed-issue", "github"]).await;
+        let events = update_issue_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].issue().number, 118);
+        assert_eq!(
+            events[0].summary(),
+            "closed as completed by https://github.com/Manta-Network/docs/pull/119"
+        );
+        assert!(db
+            .get(format!("{org}#{repo}#issues#open#118"))
+            .unwrap()
+            .is_none());
+        let closed: ClosedIssue = serde_json::from_slice(
+            &db.get(format!("{org}#{repo}#issues#closed#118"))
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(closed.state_reason.as_deref(), Some("completed"));
+
+        // #118 is reopened at last.
+        let mock = MockGithub::start(&["github"]).await;
+        let events = update_issue_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.kind(), e.issue().number))
+                .collect::<Vec<_>>(),
+            vec![("reopened", 118)]
+        );
+        assert!(db
+            .get(format!("{org}#{repo}#issues#closed#118"))
+            .unwrap()
+            .is_none());
+        assert!(db
+            .get(format!("{org}#{repo}#issues#reopened#118"))
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn update_issue_status_should_report_changed_fields_only() {
+        let db = temporary_db();
+        let (org, repo) = ("Manta-Network", "docs");
+
+        let mock = MockGithub::start(&["github"]).await;
+        update_issue_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+
+        // Every issue is updated, but only the title, labels, assignees and milestone are told.
+        let mock = MockGithub::start(&["github-issue-edited", "github"]).await;
+        let events = update_issue_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.kind(), e.issue().number, e.summary()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "changed",
+                    110,
+                    "assignees changed: +Dengjianping".to_owned()
+                ),
+                ("changed", 110, "milestone set to Q4".to_owned()),
+                ("changed", 118, "labels added: good first issue".to_owned()),
+                (
+                    "changed",
+                    120,
+                    "title edited from \"Document how to run a collator\"".to_owned()
+                ),
+            ]
+        );
+        // No issue is closed by mistake.
+        assert!(db
+            .scan_prefix(format!("{org}#{repo}#issues#closed"))
+            .next()
+            .is_none());
+
+        let events = update_issue_status(mock.client(), db, org, repo)
+            .await
+            .unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_closed_issues_by_date_should_work() {
+        let mock = MockGithub::start(&["github-issue-report", "github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-26").unwrap();
+        let closed_issues =
+            get_closed_issues_by_date(mock.client(), "Manta-Network", "docs", from, to)
+                .await
+                .unwrap();
+        // #125 is closed after the window, #121 is a pr, and #100 is closed before the window.
+        assert_eq!(
+            closed_issues
+                .iter()
+                .map(|c| (
+                    c.issue.number,
+                    c.state_reason.as_deref(),
+                    c.closing_pr.as_deref()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    122,
+                    Some("completed"),
+                    Some("https://github.com/Manta-Network/docs/pull/123")
+                ),
+                (116, Some("not_planned"), None)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_reopened_issues_by_date_should_work() {
+        let mock = MockGithub::start(&["github-issue-report", "github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-20", "2022-11-26").unwrap();
+        let reopened_issues =
+            get_reopened_issues_by_date(mock.client(), "Manta-Network", "docs", from, to)
+                .await
+                .unwrap();
+        // #117 is a pr, and #90 is reopened before the window, so the next page is not requested.
+        assert_eq!(
+            reopened_issues
+                .iter()
+                .map(|(_, issue)| issue.number)
+                .collect::<Vec<_>>(),
+            vec![118, 110]
+        );
+        assert_eq!(mock.requests().len(), 1);
+    }
 }
@@ -14,15 +14,124 @@
 // You should have received a copy of the GNU General Public License
 // along with Manta.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::diff::FieldChange;
+use crate::retry::{retry, Incomplete};
+use crate::utils::IntenalError;
 use anyhow::Result;
-use chrono::prelude::*;
-use octocrab::{models::pulls, params, Octocrab};
+use chrono::{prelude::*, Duration};
+use octocrab::{models::pulls, params, Octocrab, Page};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use sled::Db;
-use std::sync::Arc;
+use std::{cmp::Reverse, collections::HashMap, sync::Arc};
 
 pub type OpenPRs = Vec<pulls::PullRequest>;
 pub type MergedPRs = Vec<pulls::PullRequest>;
 pub type ClosedPRs = Vec<pulls::PullRequest>;
+pub type DraftPRs = Vec<pulls::PullRequest>;
+pub type ReadyPRs = Vec<pulls::PullRequest>;
+pub type DateWindow = (DateTime<Utc>, DateTime<Utc>);
+
+pub const SEARCH_RESULT_LIMIT: u64 = 1000;
+
+// What happened to a pr since the last sync.
+#[derive(Clone, Debug)]
+pub enum PrEvent {
+    New(pulls::PullRequest),
+    ConvertedToDraft(pulls::PullRequest),
+    ReadyForReview(pulls::PullRequest),
+    Merged(pulls::PullRequest),
+    Closed(pulls::PullRequest),
+    Changed(pulls::PullRequest, FieldChange),
+    // with who approved it.
+    Approved(pulls::PullRequest, Vec<String>),
+    ChangesRequested(pulls::PullRequest),
+    ReviewDismissed(pulls::PullRequest),
+}
+
+// The review decision of a pr, like `reviewDecision` of GraphQL, but dismissals are told.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewDecision {
+    ReviewRequired,
+    Approved,
+    ChangesRequested,
+    Dismissed,
+}
+
+impl ReviewDecision {
+    // `reviewDecision` of GraphQL, it knows nothing about dismissals.
+    pub fn from_graphql(decision: &str) -> Option<Self> {
+        match decision {
+            "APPROVED" => Some(Self::Approved),
+            "CHANGES_REQUESTED" => Some(Self::ChangesRequested),
+            "REVIEW_REQUIRED" => Some(Self::ReviewRequired),
+            _ => None,
+        }
+    }
+}
+
+// The latest review state of a pr, stored in sled.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReviewStatus {
+    pub decision: ReviewDecision,
+    pub approved_by: Vec<String>,
+    // `updated_at` of the pr when its reviews are fetched, a new review updates the pr too.
+    #[serde(default)]
+    pub pr_updated_at: Option<DateTime<Utc>>,
+}
+
+impl PrEvent {
+    pub fn pr(&self) -> &pulls::PullRequest {
+        match self {
+            Self::New(pr)
+            | Self::ConvertedToDraft(pr)
+            | Self::ReadyForReview(pr)
+            | Self::Merged(pr)
+            | Self::Closed(pr)
+            | Self::Changed(pr, _)
+            | Self::Approved(pr, _)
+            | Self::ChangesRequested(pr)
+            | Self::ReviewDismissed(pr) => pr,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::New(_) => "new",
+            Self::ConvertedToDraft(_) => "converted to draft",
+            Self::ReadyForReview(_) => "ready for review",
+            Self::Merged(_) => "merged",
+            Self::Closed(_) => "closed",
+            Self::Changed(..) => "changed",
+            Self::Approved(..) => "approved",
+            Self::ChangesRequested(_) => "changes requested",
+            Self::ReviewDismissed(_) => "review dismissed",
+        }
+    }
+
+    // Like `ready for review` or `labels added: A0-pleasereview`.
+    pub fn summary(&self) -> String {
+        match self {
+            Self::Changed(_, change) => change.to_string(),
+            // GraphQL doesn't tell who approved it.
+            Self::Approved(_, approved_by) if approved_by.is_empty() => self.kind().to_owned(),
+            Self::Approved(_, approved_by) => {
+                format!("{} by {}", self.kind(), approved_by.join(", "))
+            }
+            _ => self.kind().to_owned(),
+        }
+    }
+}
+
+pub fn is_draft(pr: &pulls::PullRequest) -> bool {
+    pr.draft == Some(true)
+}
+
+// Drafts go first, then prs ready for review.
+pub fn split_drafts(prs: OpenPRs) -> (DraftPRs, ReadyPRs) {
+    prs.into_iter().partition(is_draft)
+}
 
 pub async fn get_all_merged_prs_by_date(
     octocrab: Arc<Octocrab>,
@@ -31,93 +140,422 @@ pub async fn get_all_merged_prs_by_date(
     state: params::State,
     from: DateTime<Utc>,
     to: DateTime<Utc>,
+) -> Result<MergedPRs> {
+    scan_prs_by_date(octocrab, org, repo, state, from, to, merged_date).await
+}
+
+// PRs closed without merging in (from, to).
+pub async fn get_closed_prs_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<ClosedPRs> {
+    scan_prs_by_date(
+        octocrab,
+        org,
+        repo,
+        params::State::Closed,
+        from,
+        to,
+        rejected_date,
+    )
+    .await
+}
+
+// Open PRs created in (from, to).
+pub async fn get_open_prs_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
 ) -> Result<OpenPRs> {
-    let mut page = octocrab
-        .pulls(org, repo)
-        .list()
-        .state(state)
-        .sort(params::pulls::Sort::Updated)
-        .direction(params::Direction::Descending)
-        .per_page(100)
-        .send()
-        .await?;
+    let mut page = retry(format!("{org}/{repo} open prs page 0"), || async {
+        octocrab
+            .pulls(org, repo)
+            .list()
+            .state(params::State::Open)
+            .sort(params::pulls::Sort::Created)
+            .direction(params::Direction::Descending)
+            .per_page(100)
+            .send()
+            .await
+    })
+    .await?;
 
     let mut all_prs = vec![];
     let mut pull_times = 0u32;
     'query_pr: loop {
         for pr in &page {
-            // seems there's a bug, some PRs will be returned.
-            // so need to filter PRs.
-            // not merged pr will be filtered.
-            if pr
-                .html_url
-                .as_ref()
-                .map(|url| url.as_str().contains("issues"))
-                == Some(true)
-                || pr.merged_at.is_none()
-            {
+            // closed prs are not expected here, but filter them in case.
+            if pr.closed_at.is_some() {
                 continue;
             }
-            println!(
-                "page: {pull_times}, prs: {}, pr title: {:?}, pr number: {}, date: {:?}",
-                all_prs.len(),
-                pr.html_url.as_ref().map(|s| s.to_string()),
-                pr.number,
-                pr.merged_at
-            );
-
-            // There're several situation(currently, there's no way to sort PRs by merged date in octocrab),
-            // and updated_at >= merged_at always:
-            // 1. both merged_at and updated_at in (from, to), record the pr.
-            // 2. merged_at in (from, to), but updated_at in (t0, ...), record the pr.
-            // 3. merged_at in (..., from), but updated_at in (from, to), continue.
-            // 4. both merged_at and updated_at in (..., from), break the whole loop,
-            //    because the current page of result is sorted by updated date.
-            // 5. both merged_at and updated_at in (to, ...), continue, they are more newer pr.
-
-            // situation 4
-            if pr.merged_at < Some(from) && pr.updated_at < Some(from) {
-                break 'query_pr;
-            }
-            // situation 3
-            if pr.merged_at < Some(from) && pr.updated_at >= Some(from) && pr.updated_at <= Some(to)
-            {
-                continue;
-            }
-            // situation 5
-            if pr.merged_at > Some(to) && pr.updated_at > Some(to) {
+
+            if pr.created_at > Some(to) {
                 continue;
             }
-            // situation 1, 2
-            if pr.merged_at >= Some(from) && pr.merged_at <= Some(to) {
+            if pr.created_at >= Some(from) && pr.created_at <= Some(to) {
                 all_prs.push(pr.clone());
             }
+            if pr.created_at < Some(from) {
+                break 'query_pr;
+            }
+        }
+
+        // go to next page of prs.
+        pull_times += 1;
+        let what = format!("{org}/{repo} open prs page {pull_times}");
+        page = match crate::retry::next_page(&what, &octocrab, &page).await {
+            Ok(Some(next_page)) => next_page,
+            Ok(None) => break,
+            Err(e) => return Err(Incomplete::new(what, e, all_prs).into()),
+        }
+    }
+
+    Ok(all_prs)
+}
+
+// When the pr was merged.
+pub fn merged_date(pr: &pulls::PullRequest) -> Option<DateTime<Utc>> {
+    pr.merged_at
+}
+
+// When the pr was closed without merging, None for merged prs.
+pub fn rejected_date(pr: &pulls::PullRequest) -> Option<DateTime<Utc>> {
+    match pr.merged_at {
+        Some(_) => None,
+        None => pr.closed_at,
+    }
+}
+
+// Scan PRs sorted by updated date, and pick ones whose `date_of` is in (from, to).
+async fn scan_prs_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    state: params::State,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    date_of: fn(&pulls::PullRequest) -> Option<DateTime<Utc>>,
+) -> Result<Vec<pulls::PullRequest>> {
+    let mut page = retry(format!("{org}/{repo} prs page 0"), || async {
+        octocrab
+            .pulls(org, repo)
+            .list()
+            .state(state)
+            .sort(params::pulls::Sort::Updated)
+            .direction(params::Direction::Descending)
+            .per_page(100)
+            .send()
+            .await
+    })
+    .await?;
+
+    let mut all_prs = vec![];
+    let mut pull_times = 0u32;
+    loop {
+        let reach_end = select_prs_by_date(&page.items, from, to, date_of, &mut all_prs);
+        println!("page: {pull_times}, prs: {}", all_prs.len());
+        if reach_end {
+            break;
         }
-        page = match octocrab.get_page::<pulls::PullRequest>(&page.next).await? {
-            Some(next_page) => next_page,
-            None => break,
-        };
         pull_times += 1;
+        let what = format!("{org}/{repo} prs page {pull_times}");
+        page = match crate::retry::next_page(&what, &octocrab, &page).await {
+            Ok(Some(next_page)) => next_page,
+            Ok(None) => break,
+            Err(e) => return Err(Incomplete::new(what, e, all_prs).into()),
+        };
+    }
+
+    Ok(all_prs)
+}
+
+// Pick PRs merged in (from, to) from a page of PRs sorted by updated date.
+// Return true if the following pages are older than `from`.
+pub fn select_merged_prs_by_date(
+    page: &[pulls::PullRequest],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    all_prs: &mut MergedPRs,
+) -> bool {
+    select_prs_by_date(page, from, to, merged_date, all_prs)
+}
+
+// Pick PRs whose `date_of` is in (from, to) from a page of PRs sorted by updated date,
+// PRs without the date are filtered.
+// Return true if the following pages are older than `from`.
+pub fn select_prs_by_date(
+    page: &[pulls::PullRequest],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    date_of: fn(&pulls::PullRequest) -> Option<DateTime<Utc>>,
+    all_prs: &mut Vec<pulls::PullRequest>,
+) -> bool {
+    for pr in page {
+        let date = date_of(pr);
+        // seems there's a bug, some PRs will be returned.
+        // so need to filter PRs.
+        // not merged(or not closed) pr will be filtered.
+        if pr
+            .html_url
+            .as_ref()
+            .map(|url| url.as_str().contains("issues"))
+            == Some(true)
+            || date.is_none()
+        {
+            continue;
+        }
+        println!(
+            "prs: {}, pr title: {:?}, pr number: {}, date: {:?}",
+            all_prs.len(),
+            pr.html_url.as_ref().map(|s| s.to_string()),
+            pr.number,
+            date
+        );
+
+        // There're several situation(currently, there's no way to sort PRs by merged date in octocrab),
+        // and updated_at >= merged_at(or closed_at) always, `date` is merged_at or closed_at:
+        // 1. both date and updated_at in (from, to), record the pr.
+        // 2. date in (from, to), but updated_at in (t0, ...), record the pr.
+        // 3. date in (..., from), but updated_at in (from, to), continue.
+        // 4. both date and updated_at in (..., from), break the whole loop,
+        //    because the current page of result is sorted by updated date.
+        // 5. both date and updated_at in (to, ...), continue, they are more newer pr.
+
+        // situation 4
+        if date < Some(from) && pr.updated_at < Some(from) {
+            return true;
+        }
+        // situation 3
+        if date < Some(from) && pr.updated_at >= Some(from) && pr.updated_at <= Some(to) {
+            continue;
+        }
+        // situation 5
+        if date > Some(to) && pr.updated_at > Some(to) {
+            continue;
+        }
+        // situation 1, 2
+        if date >= Some(from) && date <= Some(to) {
+            all_prs.push(pr.clone());
+        }
+    }
+
+    false
+}
+
+// Search API can sort nothing by merged date either, but it can filter PRs by merged date.
+// One query returns 1000 results at most, so a window with more results will be split.
+pub async fn search_merged_prs_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<MergedPRs> {
+    search_prs_by_date(
+        octocrab,
+        from,
+        to,
+        |from, to| merged_prs_query(org, repo, from, to),
+        |pr| pr.merged_at,
+    )
+    .await
+}
+
+pub async fn search_closed_prs_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<ClosedPRs> {
+    search_prs_by_date(
+        octocrab,
+        from,
+        to,
+        |from, to| closed_prs_query(org, repo, from, to),
+        |pr| pr.closed_at,
+    )
+    .await
+}
+
+pub async fn search_open_prs_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<OpenPRs> {
+    search_prs_by_date(
+        octocrab,
+        from,
+        to,
+        |from, to| open_prs_query(org, repo, from, to),
+        |pr| pr.created_at,
+    )
+    .await
+}
+
+// Search PRs by the query of every window, the latest `date_of` goes first.
+async fn search_prs_by_date(
+    octocrab: Arc<Octocrab>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    query_of: impl Fn(DateTime<Utc>, DateTime<Utc>) -> String,
+    date_of: fn(&pulls::PullRequest) -> Option<DateTime<Utc>>,
+) -> Result<Vec<pulls::PullRequest>> {
+    let mut windows = vec![(from, to)];
+    let mut all_prs = vec![];
+    let mut failure = None;
+    'query_window: while let Some((from, to)) = windows.pop() {
+        let query = query_of(from, to);
+        let parameters = [("q", query.as_str()), ("per_page", "100")];
+        let what = format!("{query} page 0");
+        let mut page = match retry(&what, || {
+            octocrab.get::<Page<Value>, _, _>("search/issues", Some(&parameters))
+        })
+        .await
+        {
+            Ok(page) => page,
+            // prs of windows searched before are kept.
+            Err(e) if !all_prs.is_empty() => {
+                failure = Some((what, e));
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let total_count = page.total_count.unwrap_or_default();
+        if total_count > SEARCH_RESULT_LIMIT {
+            // A window of one second can't be split, and the rest of it would be lost.
+            if to - from <= Duration::seconds(1) {
+                return Err(IntenalError::SearchResultLimit(query, total_count).into());
+            }
+            println!("{total_count} prs are found in ({from}, {to}), split it.");
+            let (left, right) = split_window(from, to);
+            windows.push(left);
+            windows.push(right);
+            continue;
+        }
+
+        let mut pull_times = 0u32;
+        loop {
+            for item in page.items.iter() {
+                all_prs.push(pr_from_search_item(item)?);
+            }
+            println!("query: {query}, page: {pull_times}, prs: {}", all_prs.len());
+            pull_times += 1;
+            let what = format!("{query} page {pull_times}");
+            page = match crate::retry::next_page(&what, &octocrab, &page).await {
+                Ok(Some(next_page)) => next_page,
+                Ok(None) => break,
+                Err(e) => {
+                    failure = Some((what, e));
+                    break 'query_window;
+                }
+            };
+        }
+    }
+    all_prs.sort_by_key(|pr| Reverse(date_of(pr)));
+    if let Some((what, e)) = failure {
+        return Err(Incomplete::new(what, e, all_prs).into());
     }
 
     Ok(all_prs)
 }
 
+const SEARCH_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+// Both ends of the range are inclusive.
+pub fn merged_prs_query(org: &str, repo: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> String {
+    format!(
+        "repo:{org}/{repo} is:pr is:merged merged:{}..{}",
+        from.format(SEARCH_DATE_FORMAT),
+        to.format(SEARCH_DATE_FORMAT)
+    )
+}
+
+pub fn closed_prs_query(org: &str, repo: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> String {
+    format!(
+        "repo:{org}/{repo} is:pr is:closed is:unmerged closed:{}..{}",
+        from.format(SEARCH_DATE_FORMAT),
+        to.format(SEARCH_DATE_FORMAT)
+    )
+}
+
+pub fn open_prs_query(org: &str, repo: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> String {
+    format!(
+        "repo:{org}/{repo} is:pr is:open created:{}..{}",
+        from.format(SEARCH_DATE_FORMAT),
+        to.format(SEARCH_DATE_FORMAT)
+    )
+}
+
+// Split (from, to) into (from, mid) and (mid + 1s, to), so no pr is counted twice.
+pub fn split_window(from: DateTime<Utc>, to: DateTime<Utc>) -> (DateWindow, DateWindow) {
+    let mid = from + (to - from) / 2;
+    ((from, mid), (mid + Duration::seconds(1), to))
+}
+
+// Search results are issues, the pr fields are under `pull_request`.
+// Head and base are not returned by search, so they are left empty.
+pub fn pr_from_search_item(item: &Value) -> Result<pulls::PullRequest> {
+    let link = &item["pull_request"];
+    let value = json!({
+        "url": link["url"],
+        "id": item["id"],
+        "node_id": item["node_id"],
+        "html_url": link["html_url"],
+        "diff_url": link["diff_url"],
+        "patch_url": link["patch_url"],
+        "issue_url": item["url"],
+        "comments_url": item["comments_url"],
+        "number": item["number"],
+        "state": item["state"],
+        "locked": item["locked"],
+        "title": item["title"],
+        "user": item["user"],
+        "body": item["body"],
+        "labels": item["labels"],
+        "milestone": item["milestone"],
+        "created_at": item["created_at"],
+        "updated_at": item["updated_at"],
+        "closed_at": item["closed_at"],
+        "merged_at": link["merged_at"],
+        "assignee": item["assignee"],
+        "assignees": item["assignees"],
+        "head": { "ref": "", "sha": "" },
+        "base": { "ref": "", "sha": "" },
+        "author_association": item["author_association"],
+        "draft": item["draft"],
+    });
+
+    Ok(serde_json::from_value(value)?)
+}
+
 pub async fn get_all_open_prs(
     octocrab: Arc<Octocrab>,
     org: &str,
     repo: &str,
     state: params::State,
 ) -> Result<OpenPRs> {
-    let mut page = octocrab
-        .pulls(org, repo)
-        .list()
-        .state(state)
-        .per_page(50)
-        .send()
-        .await?;
+    let mut page = retry(format!("{org}/{repo} open prs page 0"), || async {
+        octocrab
+            .pulls(org, repo)
+            .list()
+            .state(state)
+            .per_page(50)
+            .send()
+            .await
+    })
+    .await?;
 
     let mut all_prs = vec![];
+    let mut pull_times = 0u32;
     loop {
         for pr in &page {
             // seems there's a bug, some PRs will be returned.
@@ -127,79 +565,325 @@ pub async fn get_all_open_prs(
                 .as_ref()
                 .map(|url| url.as_str().contains("issues"))
                 == Some(true)
-                || pr.merged_at.is_none()
             {
                 continue;
             }
             all_prs.push(pr.clone());
         }
-        page = match octocrab.get_page::<pulls::PullRequest>(&page.next).await? {
-            Some(next_page) => next_page,
-            None => break,
+        pull_times += 1;
+        let what = format!("{org}/{repo} open prs page {pull_times}");
+        page = match crate::retry::next_page(&what, &octocrab, &page).await {
+            Ok(Some(next_page)) => next_page,
+            Ok(None) => break,
+            Err(e) => return Err(Incomplete::new(what, e, all_prs).into()),
         };
     }
 
     Ok(all_prs)
 }
 
-// return closed issues
+// return what happened to prs since the last sync
 pub async fn update_pr_status(
+    octocrab: Arc<Octocrab>,
     db: Arc<Db>,
     org: &str,
     repo: &str,
-) -> Result<(OpenPRs, MergedPRs, ClosedPRs)> {
-    let octocrab = octocrab::instance();
-
-    let open_prs = get_all_open_prs(octocrab.clone(), org, repo, params::State::Open).await?;
-    // insert open prs
+) -> Result<Vec<PrEvent>> {
     let key_prefix = format!("{org}#{repo}#prs#open");
-    crate::db::insert_batch_prs(db.clone(), &key_prefix, &open_prs).await?;
-
+    // read archived prs before they're overwritten by current ones.
     let existing_prs = crate::db::get_all_archived_prs(db.clone(), key_prefix.as_bytes()).await?;
+    let open_prs = get_all_open_prs(octocrab.clone(), org, repo, params::State::Open).await?;
 
-    // pr has 3 status: open, merged, closed
-    let mut closed_prs = vec![];
-    let mut merged_prs = vec![];
+    // prs are identified by number, other fields may change anytime.
+    let open_by_number = open_prs
+        .iter()
+        .map(|pr| (pr.number, pr))
+        .collect::<HashMap<_, _>>();
+    let existing_by_number = existing_prs
+        .iter()
+        .map(|pr| (pr.number, pr))
+        .collect::<HashMap<_, _>>();
+
+    let mut events = vec![];
     for old_pr in existing_prs.iter() {
-        // if old pr is not in current open prs, that means this pr has been closed or merged.
-        if !open_prs.contains(old_pr) {
-            // find out this pr is merged or closed.
-            let pr = get_pr_by_id(octocrab.clone(), org, repo, old_pr.number).await?;
-            // if pr is merged, merged_at is the concrete time.
-            let old_key_prefix = format!("{org}#{repo}#prs#open#{0}", old_pr.number);
-            let _ = db.remove(old_key_prefix.as_bytes())?;
-
-            // 1. (Some(_), Some(_)) means the pr has been merged.
-            // 2. (None, Some(_)) means the pr has been closed.
-            // 3. (Some(_), None) seems impossible.
-            // 4. (None, None) means it's a open pr.
-            match (pr.merged_at, pr.closed_at) {
-                (Some(_), Some(_)) => {
-                    // delete the pr if it has been merged.
-                    let new_key_prefix = format!("{org}#{repo}#prs#merged");
-                    crate::db::insert_one_pr(db.clone(), &new_key_prefix, old_pr).await?;
-                    merged_prs.push(old_pr.clone());
+        match open_by_number.get(&old_pr.number) {
+            // the pr is still open, but it may be converted to draft or marked as ready,
+            // and its title, labels and the like may be changed.
+            Some(pr) => {
+                match (is_draft(old_pr), is_draft(pr)) {
+                    (false, true) => events.push(PrEvent::ConvertedToDraft((*pr).clone())),
+                    (true, false) => events.push(PrEvent::ReadyForReview((*pr).clone())),
+                    _ => (),
+                }
+                for change in crate::diff::pr_changes(old_pr, pr) {
+                    events.push(PrEvent::Changed((*pr).clone(), change));
                 }
-                (None, Some(_)) => {
-                    // delete the pr if it has been closed.
-                    let new_key_prefix = format!("{org}#{repo}#prs#closed");
-                    crate::db::insert_one_pr(db.clone(), &new_key_prefix, old_pr).await?;
-                    closed_prs.push(old_pr.clone());
+            }
+            // if old pr is not in current open prs, that means this pr has been closed or merged.
+            None => {
+                // find out this pr is merged or closed.
+                let pr = get_pr_by_id(octocrab.clone(), org, repo, old_pr.number).await?;
+                let old_key_prefix = format!("{org}#{repo}#prs#open#{0}", old_pr.number);
+
+                // 1. (Some(_), Some(_)) means the pr has been merged.
+                // 2. (None, Some(_)) means the pr has been closed.
+                // 3. (Some(_), None) seems impossible.
+                // 4. (None, None) means it's a open pr, keep it.
+                match (pr.merged_at, pr.closed_at) {
+                    (Some(_), Some(_)) => {
+                        // delete the pr if it has been merged.
+                        let _ = db.remove(old_key_prefix.as_bytes())?;
+                        let _ = db.remove(format!("{org}#{repo}#reviews#{0}", pr.number))?;
+                        let new_key_prefix = format!("{org}#{repo}#prs#merged");
+                        crate::db::insert_one_pr(db.clone(), &new_key_prefix, &pr).await?;
+                        events.push(PrEvent::Merged(pr));
+                    }
+                    (None, Some(_)) => {
+                        // delete the pr if it has been closed.
+                        let _ = db.remove(old_key_prefix.as_bytes())?;
+                        let _ = db.remove(format!("{org}#{repo}#reviews#{0}", pr.number))?;
+                        let new_key_prefix = format!("{org}#{repo}#prs#closed");
+                        crate::db::insert_one_pr(db.clone(), &new_key_prefix, &pr).await?;
+                        events.push(PrEvent::Closed(pr));
+                    }
+                    _ => (),
                 }
-                _ => (),
             }
         }
     }
 
     // find out new prs
-    let mut new_prs = vec![];
-    for open_pr in open_prs {
-        if !existing_prs.contains(&open_pr) {
-            new_prs.push(open_pr);
+    for open_pr in open_prs.iter() {
+        if !existing_by_number.contains_key(&open_pr.number) {
+            events.push(PrEvent::New(open_pr.clone()));
+        }
+    }
+
+    // insert open prs
+    crate::db::insert_batch_prs(db.clone(), &key_prefix, &open_prs).await?;
+
+    Ok(events)
+}
+
+// Only the latest approval, change request or dismissal of every reviewer counts,
+// comments and pending reviews change nothing.
+pub fn review_status(reviews: &[pulls::Review]) -> ReviewStatus {
+    let mut latest_states: Vec<(&str, pulls::ReviewState)> = vec![];
+    for review in reviews {
+        let state = match review.state {
+            Some(
+                state @ (pulls::ReviewState::Approved
+                | pulls::ReviewState::ChangesRequested
+                | pulls::ReviewState::Dismissed),
+            ) => state,
+            _ => continue,
+        };
+        match latest_states
+            .iter_mut()
+            .find(|(reviewer, _)| *reviewer == review.user.login)
+        {
+            Some((_, latest)) => *latest = state,
+            None => latest_states.push((&review.user.login, state)),
+        }
+    }
+
+    let has = |state| latest_states.iter().any(|(_, latest)| *latest == state);
+    let decision = if has(pulls::ReviewState::ChangesRequested) {
+        ReviewDecision::ChangesRequested
+    } else if has(pulls::ReviewState::Approved) {
+        ReviewDecision::Approved
+    } else if has(pulls::ReviewState::Dismissed) {
+        ReviewDecision::Dismissed
+    } else {
+        ReviewDecision::ReviewRequired
+    };
+    let approved_by = latest_states
+        .iter()
+        .filter(|(_, latest)| *latest == pulls::ReviewState::Approved)
+        .map(|(reviewer, _)| reviewer.to_string())
+        .collect();
+
+    ReviewStatus {
+        decision,
+        approved_by,
+        pr_updated_at: None,
+    }
+}
+
+pub async fn get_all_reviews(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    id: u64,
+) -> Result<Vec<pulls::Review>> {
+    let mut page = retry(format!("{org}/{repo} pr {id} reviews page 0"), || async {
+        octocrab.pulls(org, repo).list_reviews(id).await
+    })
+    .await?;
+
+    let mut all_reviews = vec![];
+    let mut pull_times = 0u32;
+    loop {
+        all_reviews.append(&mut page.items);
+        pull_times += 1;
+        let next = retry(
+            format!("{org}/{repo} pr {id} reviews page {pull_times}"),
+            || octocrab.get_page::<pulls::Review>(&page.next),
+        )
+        .await?;
+        page = match next {
+            Some(next_page) => next_page,
+            None => break,
+        };
+    }
+
+    Ok(all_reviews)
+}
+
+// Files a pr changes, cached in sled.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChangedFiles {
+    // Files of open prs change with new commits, so they're fetched again if the head moves.
+    pub head_sha: String,
+    pub files: Vec<String>,
+}
+
+// A file a pr changes, GitHub leaves `patch` out of binary and huge diffs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChangedFile {
+    pub filename: String,
+    pub previous_filename: Option<String>,
+    pub patch: Option<String>,
+}
+
+// GitHub lists at most 3000 files.
+pub async fn get_pr_files(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    id: u64,
+) -> Result<Vec<ChangedFile>> {
+    let route = format!("repos/{org}/{repo}/pulls/{id}/files");
+    let parameters = [("per_page", "100")];
+    let mut page = retry(format!("{org}/{repo} pr {id} files page 0"), || {
+        octocrab.get::<Page<ChangedFile>, _, _>(&route, Some(&parameters))
+    })
+    .await?;
+
+    let mut files = vec![];
+    let mut pull_times = 0u32;
+    loop {
+        files.append(&mut page.items);
+        pull_times += 1;
+        let next = retry(
+            format!("{org}/{repo} pr {id} files page {pull_times}"),
+            || octocrab.get_page::<ChangedFile>(&page.next),
+        )
+        .await?;
+        page = match next {
+            Some(next_page) => next_page,
+            None => break,
+        };
+    }
+
+    Ok(files)
+}
+
+// Renamed files count by both names.
+pub async fn get_changed_files(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    id: u64,
+) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for file in get_pr_files(octocrab, org, repo, id).await? {
+        files.extend(file.previous_filename);
+        files.push(file.filename);
+    }
+
+    Ok(files)
+}
+
+// Changed files are only fetched once per head of a pr.
+pub async fn get_cached_changed_files(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    org: &str,
+    repo: &str,
+    pr: &pulls::PullRequest,
+) -> Result<Vec<String>> {
+    let key = format!("{org}#{repo}#files#{}", pr.number);
+    if let Some(cached) = db.get(&key)? {
+        let cached: ChangedFiles = serde_json::from_slice(&cached)?;
+        if cached.head_sha == pr.head.sha {
+            return Ok(cached.files);
+        }
+    }
+
+    let files = get_changed_files(octocrab, org, repo, pr.number).await?;
+    let changed = ChangedFiles {
+        head_sha: pr.head.sha.clone(),
+        files,
+    };
+    db.insert(key, serde_json::to_vec(&changed)?)?;
+
+    Ok(changed.files)
+}
+
+// Review status of archived open prs is compared with the stored one,
+// so `update_pr_status` should go first.
+// Reviews are only fetched again for prs updated since, and a pr whose reviews can't be fetched
+// is skipped till the next round, the pr events of this round are archived already.
+pub async fn update_review_status(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    org: &str,
+    repo: &str,
+) -> Result<Vec<PrEvent>> {
+    let open_prs =
+        crate::db::get_all_archived_prs(db.clone(), format!("{org}#{repo}#prs#open").as_bytes())
+            .await?;
+
+    let mut events = vec![];
+    for pr in open_prs {
+        let key = format!("{org}#{repo}#reviews#{0}", pr.number);
+        let old_status = match db.get(key.as_bytes())? {
+            Some(old) => Some(serde_json::from_slice::<ReviewStatus>(&old)?),
+            None => None,
+        };
+        if let Some(old_status) = old_status.as_ref() {
+            if old_status.pr_updated_at.is_some() && old_status.pr_updated_at == pr.updated_at {
+                continue;
+            }
+        }
+        let reviews = match get_all_reviews(octocrab.clone(), org, repo, pr.number).await {
+            Ok(reviews) => reviews,
+            Err(e) => {
+                println!(
+                    "{org}/{repo}: failed to fetch reviews of pr #{}: {e}",
+                    pr.number
+                );
+                continue;
+            }
+        };
+        let status = ReviewStatus {
+            pr_updated_at: pr.updated_at,
+            ..review_status(&reviews)
+        };
+        db.insert(key.as_bytes(), serde_json::to_vec(&status)?)?;
+        if old_status.map(|old| old.decision) == Some(status.decision) {
+            continue;
+        }
+        match status.decision {
+            ReviewDecision::Approved => events.push(PrEvent::Approved(pr, status.approved_by)),
+            ReviewDecision::ChangesRequested => events.push(PrEvent::ChangesRequested(pr)),
+            ReviewDecision::Dismissed => events.push(PrEvent::ReviewDismissed(pr)),
+            ReviewDecision::ReviewRequired => (),
         }
     }
 
-    Ok((new_prs, merged_prs, closed_prs))
+    Ok(events)
 }
 
 pub async fn get_pr_by_id(
@@ -208,20 +892,109 @@ pub async fn get_pr_by_id(
     repo: &str,
     id: u64,
 ) -> Result<pulls::PullRequest> {
-    let pr = octo.pulls(org, repo).get(id).await?;
+    let pr = retry(format!("{org}/{repo} pr {id}"), || async {
+        octo.pulls(org, repo).get(id).await
+    })
+    .await?;
     Ok(pr)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_github::MockGithub;
     use octocrab::params;
     use polars::prelude::*;
 
+    #[tokio::test]
+    async fn scan_and_search_should_find_the_same_merged_prs() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (org, repo) = ("paritytech", "substrate");
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-26").unwrap();
+
+        let scanned_prs =
+            get_all_merged_prs_by_date(mock.client(), org, repo, params::State::Closed, from, to)
+                .await
+                .unwrap();
+        let searched_prs = search_merged_prs_by_date(mock.client(), org, repo, from, to)
+            .await
+            .unwrap();
+
+        let summary = |prs: &[pulls::PullRequest]| {
+            let mut summary = prs
+                .iter()
+                .map(|pr| (pr.number, pr.merged_at, pr.html_url.clone()))
+                .collect::<Vec<_>>();
+            summary.sort();
+            summary
+        };
+        assert_eq!(summary(&scanned_prs), summary(&searched_prs));
+        assert_eq!(
+            summary(&scanned_prs)
+                .iter()
+                .map(|(number, ..)| *number)
+                .collect::<Vec<_>>(),
+            vec![12905, 12930, 12940]
+        );
+    }
+
+    #[test]
+    fn split_window_should_not_overlap() {
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-01", "2022-11-30").unwrap();
+        let ((left_from, left_to), (right_from, right_to)) = split_window(from, to);
+        assert_eq!(left_from, from);
+        assert_eq!(right_to, to);
+        assert_eq!(right_from - left_to, chrono::Duration::seconds(1));
+        assert_eq!(
+            merged_prs_query("paritytech", "substrate", left_from, left_to),
+            "repo:paritytech/substrate is:pr is:merged merged:2022-11-01T00:00:00Z..2022-11-16T00:00:00Z"
+        );
+    }
+
+    #[tokio::test]
+    async fn search_should_split_windows_over_the_limit() {
+        let mock = MockGithub::start(&["github-search-split"]).await;
+        let (org, repo) = ("paritytech", "substrate");
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-01", "2022-11-30").unwrap();
+        let mut numbers = search_merged_prs_by_date(mock.client(), org, repo, from, to)
+            .await
+            .unwrap()
+            .iter()
+            .map(|pr| pr.number)
+            .collect::<Vec<_>>();
+        numbers.sort();
+        assert_eq!(numbers, vec![12800, 12810, 12940]);
+        // The whole month, then both halves.
+        let queries = mock
+            .requests()
+            .iter()
+            .filter(|request| request.contains("merged%3A2022-11-"))
+            .count();
+        assert_eq!(queries, 3);
+
+        // A window of one second can't be split.
+        let from = "2022-11-10T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let result = search_merged_prs_by_date(
+            mock.client(),
+            org,
+            repo,
+            from,
+            from + chrono::Duration::seconds(1),
+        )
+        .await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("1001 results of `repo:paritytech/substrate"));
+    }
+
     #[tokio::test]
     async fn get_prs_should_work() {
-        let db = crate::utils::db_config().unwrap();
-        let octocrab = octocrab::instance();
+        let mock = MockGithub::start(&["github"]).await;
+        let octocrab = mock.client();
         let (org, repo) = ("Manta-Network", "Manta");
         // 871 is a closed pr.
         let pr = get_pr_by_id(octocrab.clone(), org, repo, 871)
@@ -240,7 +1013,8 @@ mod tests {
 
     #[tokio::test]
     async fn get_merged_prs_by_date_should_work() {
-        let octocrab = octocrab::instance();
+        let mock = MockGithub::start(&["github"]).await;
+        let octocrab = mock.client();
         let (org, repo) = ("paritytech", "substrate");
         let (from, to) = ("2022-11-24", "2022-11-26");
         let (from, to) = crate::utils::parse_from_date_and_to_date(from, to).unwrap();
@@ -254,14 +1028,384 @@ mod tests {
         )
         .await
         .unwrap();
+        assert_eq!(merged_prs.len(), 3);
         for pr in merged_prs {
             assert!(pr.merged_at.is_some());
         }
+        // The second page is the last one, and the scan stops there.
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn get_merged_prs_by_date_should_retry_transient_failures() {
+        let mock = MockGithub::start(&["github"]).await;
+        mock.fail("repos/paritytech/substrate/pulls", 2, 502, 2);
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-26").unwrap();
+        let merged_prs = get_all_merged_prs_by_date(
+            mock.client(),
+            "paritytech",
+            "substrate",
+            params::State::Closed,
+            from,
+            to,
+        )
+        .await
+        .unwrap();
+        assert_eq!(merged_prs.len(), 3);
+        // The second page is sent 3 times.
+        assert_eq!(mock.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn get_merged_prs_by_date_should_retry_rate_limits() {
+        let mock = MockGithub::start(&["github"]).await;
+        mock.fail("repos/paritytech/substrate/pulls", 1, 403, 1);
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-26").unwrap();
+        let merged_prs = get_all_merged_prs_by_date(
+            mock.client(),
+            "paritytech",
+            "substrate",
+            params::State::Closed,
+            from,
+            to,
+        )
+        .await
+        .unwrap();
+        assert_eq!(merged_prs.len(), 3);
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn get_merged_prs_by_date_should_not_retry_not_found() {
+        let mock = MockGithub::start(&["github"]).await;
+        mock.fail("repos/paritytech/substrate/pulls", 2, 404, 1);
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-26").unwrap();
+        let merged_prs = get_all_merged_prs_by_date(
+            mock.client(),
+            "paritytech",
+            "substrate",
+            params::State::Closed,
+            from,
+            to,
+        )
+        .await;
+        // The first page is kept, but the result is not taken as complete.
+        let error = merged_prs.as_ref().unwrap_err().to_string();
+        assert!(error.contains("paritytech/substrate prs page 1 failed"));
+        let (merged_prs, incomplete) = crate::retry::partial(merged_prs).unwrap();
+        assert!(!merged_prs.is_empty() && merged_prs.len() < 3);
+        assert!(incomplete.is_some());
+        assert_eq!(mock.requests().len(), 2);
+
+        // Nothing is fetched yet if the first page fails.
+        let mock = MockGithub::start(&["github"]).await;
+        mock.fail("repos/paritytech/substrate/pulls", 1, 422, 1);
+        let result = get_all_merged_prs_by_date(
+            mock.client(),
+            "paritytech",
+            "substrate",
+            params::State::Closed,
+            from,
+            to,
+        )
+        .await;
+        assert!(crate::retry::partial(result).is_err());
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn get_open_prs_by_date_should_keep_pages_before_a_failure() {
+        let mock = MockGithub::start(&["github"]).await;
+        mock.fail("repos/paritytech/substrate/pulls", 2, 404, 1);
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2000-01-01", "2022-11-26").unwrap();
+        let result = get_open_prs_by_date(mock.client(), "paritytech", "substrate", from, to).await;
+        let (_, incomplete) = crate::retry::partial(result).unwrap();
+        assert!(incomplete
+            .unwrap()
+            .contains("paritytech/substrate open prs page 1 failed"));
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn get_all_open_prs_should_separate_drafts() {
+        let mock = MockGithub::start(&["github"]).await;
+        let open_prs =
+            get_all_open_prs(mock.client(), "Manta-Network", "Manta", params::State::Open)
+                .await
+                .unwrap();
+        assert_eq!(open_prs.len(), 4);
+        let (drafts, ready_prs) = split_drafts(open_prs);
+        let numbers =
+            |prs: &[pulls::PullRequest]| prs.iter().map(|pr| pr.number).collect::<Vec<_>>();
+        assert_eq!(numbers(&drafts), vec![881]);
+        assert_eq!(numbers(&ready_prs), vec![880, 871, 867]);
+    }
+
+    #[tokio::test]
+    async fn update_pr_status_should_emit_every_transition() {
+        let db = crate::mock_github::temporary_db();
+        let (org, repo) = ("Manta-Network", "Manta");
+        let summary = |events: Vec<PrEvent>| {
+            events
+                .iter()
+                .map(|event| (event.kind(), event.pr().number))
+                .collect::<Vec<_>>()
+        };
+
+        // Every pr is new at the first time.
+        let mock = MockGithub::start(&["github"]).await;
+        let events = update_pr_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary(events),
+            vec![("new", 881), ("new", 880), ("new", 871), ("new", 867)]
+        );
+
+        // #880 is converted to draft, #881 is ready for review, #867 is merged, #871 is closed and #883 is new.
+        let mock = MockGithub::start(&["github-pr-transitions", "github"]).await;
+        let events = update_pr_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary(events),
+            vec![
+                ("merged", 867),
+                ("closed", 871),
+                ("converted to draft", 880),
+                ("ready for review", 881),
+                ("new", 883)
+            ]
+        );
+        assert!(db
+            .get(format!("{org}#{repo}#prs#merged#867"))
+            .unwrap()
+            .is_some());
+        assert!(db
+            .get(format!("{org}#{repo}#prs#closed#871"))
+            .unwrap()
+            .is_some());
+        assert!(db
+            .get(format!("{org}#{repo}#prs#open#871"))
+            .unwrap()
+            .is_none());
+        let open_prs = crate::db::get_all_archived_prs(
+            db.clone(),
+            format!("{org}#{repo}#prs#open").as_bytes(),
+        )
+        .await
+        .unwrap();
+        let (drafts, _) = split_drafts(open_prs);
+        assert_eq!(
+            drafts.iter().map(|pr| pr.number).collect::<Vec<_>>(),
+            vec![880, 883]
+        );
+
+        // Nothing happens if nothing changes.
+        let events = update_pr_status(mock.client(), db, org, repo)
+            .await
+            .unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_closed_prs_by_date_should_skip_merged_prs() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-26").unwrap();
+        let closed_prs = get_closed_prs_by_date(mock.client(), "paritytech", "substrate", from, to)
+            .await
+            .unwrap();
+        // #12935 is closed without merging, others are merged.
+        assert_eq!(
+            closed_prs.iter().map(|pr| pr.number).collect::<Vec<_>>(),
+            vec![12935]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_open_prs_by_date_should_work() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-18", "2022-11-19").unwrap();
+        let open_prs = get_open_prs_by_date(mock.client(), "Manta-Network", "Manta", from, to)
+            .await
+            .unwrap();
+        // #871 and #867 are created before the window.
+        assert_eq!(
+            open_prs.iter().map(|pr| pr.number).collect::<Vec<_>>(),
+            vec![881, 880]
+        );
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[test]
+    fn prs_queries_should_work() {
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-24", "2022-11-25").unwrap();
+        assert_eq!(
+            closed_prs_query("paritytech", "substrate", from, to),
+            "repo:paritytech/substrate is:pr is:closed is:unmerged closed:2022-11-24T00:00:00Z..2022-11-26T00:00:00Z"
+        );
+        assert_eq!(
+            open_prs_query("paritytech", "substrate", from, to),
+            "repo:paritytech/substrate is:pr is:open created:2022-11-24T00:00:00Z..2022-11-26T00:00:00Z"
+        );
+    }
+
+    #[tokio::test]
+    async fn update_pr_status_should_report_changed_fields() {
+        let db = crate::mock_github::temporary_db();
+        let (org, repo) = ("Manta-Network", "Manta");
+
+        let mock = MockGithub::start(&["github"]).await;
+        update_pr_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+
+        let mock = MockGithub::start(&["github-pr-edited", "github"]).await;
+        let events = update_pr_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.pr().number, event.summary()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    867,
+                    "title edited from \"Bump polkadot to v0.9.28\"".to_owned()
+                ),
+                (880, "labels added: A-calamari".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_review_status_should_emit_review_events() {
+        let db = crate::mock_github::temporary_db();
+        let (org, repo) = ("Manta-Network", "Manta");
+        let summary = |events: Vec<PrEvent>| {
+            events
+                .iter()
+                .map(|event| (event.pr().number, event.summary()))
+                .collect::<Vec<_>>()
+        };
+
+        // #871 has changes requested, even if it's commented later, #880 is approved.
+        let mock = MockGithub::start(&["github"]).await;
+        update_pr_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        let events = update_review_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary(events),
+            vec![
+                (871, "changes requested".to_owned()),
+                (880, "approved by Dengjianping".to_owned())
+            ]
+        );
+
+        // Nothing is fetched again if no pr is updated.
+        let requests = mock.requests().len();
+        let events = update_review_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert!(events.is_empty());
+        assert_eq!(mock.requests().len(), requests);
+
+        // #871 is approved by both reviewers, the approval of #880 is dismissed,
+        // and #881 gets changes requested, but its reviews fail to be fetched this time.
+        let mock = MockGithub::start(&["github-reviews-changed", "github"]).await;
+        mock.fail("repos/Manta-Network/Manta/pulls/881/reviews", 1, 404, 1);
+        update_pr_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        let events = update_review_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary(events),
+            vec![
+                (871, "approved by ghzlatarev, stechu".to_owned()),
+                (880, "review dismissed".to_owned()),
+            ]
+        );
+        // #867 isn't updated.
+        assert!(!mock
+            .requests()
+            .iter()
+            .any(|request| request.contains("pulls/867/reviews")));
+
+        // #881 is fetched again in the next round.
+        let events = update_review_status(mock.client(), db.clone(), org, repo)
+            .await
+            .unwrap();
+        assert_eq!(summary(events), vec![(881, "changes requested".to_owned())]);
+        let status: ReviewStatus = serde_json::from_slice(
+            &db.get(format!("{org}#{repo}#reviews#871"))
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(status.decision, ReviewDecision::Approved);
+
+        // Nothing happens if no review changes.
+        let events = update_review_status(mock.client(), db, org, repo)
+            .await
+            .unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn changed_files_should_be_cached_per_head() {
+        let db = crate::mock_github::temporary_db();
+        let (org, repo) = ("Manta-Network", "Manta");
+        let mock = MockGithub::start(&["github"]).await;
+        let mut pr = get_pr_by_id(mock.client(), org, repo, 867).await.unwrap();
+
+        let files = get_cached_changed_files(mock.client(), db.clone(), org, repo, &pr)
+            .await
+            .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                "Cargo.lock",
+                "pallets/parachain-staking/Cargo.toml",
+                "runtime/manta/Cargo.toml"
+            ]
+        );
+        let fetched = |mock: &MockGithub| {
+            mock.requests()
+                .iter()
+                .filter(|request| request.contains("/files"))
+                .count()
+        };
+        assert_eq!(fetched(&mock), 2);
+
+        // Cached until the head moves.
+        get_cached_changed_files(mock.client(), db.clone(), org, repo, &pr)
+            .await
+            .unwrap();
+        assert_eq!(fetched(&mock), 2);
+        pr.head.sha = "0".repeat(40);
+        get_cached_changed_files(mock.client(), db, org, repo, &pr)
+            .await
+            .unwrap();
+        assert_eq!(fetched(&mock), 4);
     }
 
     #[tokio::test]
     async fn format_prs_by_polars_should_work() {
-        let octocrab = octocrab::instance();
+        let mock = MockGithub::start(&["github"]).await;
+        let octocrab = mock.client();
         let (org, repo) = ("paritytech", "substrate");
         let (from, to) = ("2022-11-24", "2022-11-26");
         let (from, to) = crate::utils::parse_from_date_and_to_date(from, to).unwrap();
@@ -288,7 +1432,9 @@ mod tests {
         ]
         .unwrap();
         println!("{df}");
-        let mut file = std::fs::File::create("prs.csv").unwrap();
+        let path = std::env::temp_dir().join("prs.csv");
+        let mut file = std::fs::File::create(&path).unwrap();
         CsvWriter::new(&mut file).finish(&mut df).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap().lines().count(), 4);
     }
 }
@@ -14,28 +14,580 @@
 // You should have received a copy of the GNU General Public License
 // along with Manta.  If not, see <http://www.gnu.org/licenses/>.
 
-use octocrab::models::repos;
+use crate::release_notes::ReleaseNotes;
+use crate::retry::retry;
+use crate::utils::Repository;
+use anyhow::Result;
+use chrono::prelude::*;
+use octocrab::{models::repos, Octocrab, Page};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use std::{cmp::Reverse, collections::HashMap, sync::Arc};
+
+// A tag, for repositories tagging without publishing releases.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub sha: String,
+    pub html_url: String,
+    // When the tagged commit is committed, the tag API doesn't tell it, so it's fetched on demand.
+    #[serde(default)]
+    pub date: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct TagNode {
+    name: String,
+    commit: TagCommit,
+}
+
+#[derive(Deserialize)]
+struct TagCommit {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct CommitNode {
+    commit: CommitDetail,
+}
+
+#[derive(Deserialize)]
+struct CommitDetail {
+    committer: Option<Signature>,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    date: Option<DateTime<Utc>>,
+}
+
+// A release stored in sled, with notes parsed from its body.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReleaseWithNotes {
+    #[serde(flatten)]
+    pub release: repos::Release,
+    // Releases archived before notes are parsed have empty notes.
+    #[serde(default)]
+    pub notes: ReleaseNotes,
+}
+
+impl From<repos::Release> for ReleaseWithNotes {
+    fn from(release: repos::Release) -> Self {
+        let notes = crate::release_notes::parse(release.body.as_deref().unwrap_or_default());
+        Self { release, notes }
+    }
+}
+
+// What's released since the last sync.
+#[derive(Clone, Debug)]
+pub enum ReleaseEvent {
+    // A release or a pre-release.
+    Published(Box<ReleaseWithNotes>),
+    // A pre-release becomes a release.
+    Promoted(Box<ReleaseWithNotes>),
+    Tagged(Tag),
+}
+
+impl ReleaseEvent {
+    pub fn tag_name(&self) -> &str {
+        match self {
+            Self::Published(published) | Self::Promoted(published) => &published.release.tag_name,
+            Self::Tagged(tag) => &tag.name,
+        }
+    }
+
+    // Name of the release, or the tag if it has no name.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Published(published) | Self::Promoted(published) => published
+                .release
+                .name
+                .as_deref()
+                .unwrap_or(&published.release.tag_name),
+            Self::Tagged(tag) => &tag.name,
+        }
+    }
+
+    pub fn html_url(&self) -> &str {
+        match self {
+            Self::Published(published) | Self::Promoted(published) => {
+                published.release.html_url.as_str()
+            }
+            Self::Tagged(tag) => &tag.html_url,
+        }
+    }
+
+    // Tags have no notes, and neither do releases not following the convention of Polkadot.
+    pub fn notes(&self) -> Option<&ReleaseNotes> {
+        match self {
+            Self::Published(published) | Self::Promoted(published) => {
+                Some(&published.notes).filter(|notes| !notes.is_empty())
+            }
+            Self::Tagged(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Published(published) if published.release.prerelease => "pre-release",
+            Self::Published(_) => "release",
+            Self::Promoted(_) => "promoted to release",
+            Self::Tagged(_) => "tag",
+        }
+    }
+}
 
 // Get latest release.
-pub async fn get_latest_release(org: &str, repo: &str) -> Option<repos::Release> {
-    let latest_release = octocrab::instance()
-        .repos(org, repo)
-        .releases()
-        .get_latest()
-        .await
-        .ok()?;
+pub async fn get_latest_release(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+) -> Option<repos::Release> {
+    let latest_release = crate::retry::retry(format!("{org}/{repo} latest release"), || async {
+        octocrab.repos(org, repo).releases().get_latest().await
+    })
+    .await
+    .ok()?;
 
     // if it's prerelease, return nothing.
     (!latest_release.prerelease).then_some(latest_release)
 }
 
+// Published releases and pre-releases, newest first, drafts are never listed.
+pub async fn get_all_releases(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+) -> Result<Vec<repos::Release>> {
+    scan_releases(octocrab, org, repo, |_| true).await
+}
+
+// Releases published in (from, to), pre-releases are only included if `prereleases` is true.
+pub async fn get_releases_by_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    prereleases: bool,
+) -> Result<Vec<repos::Release>> {
+    let mut releases = scan_releases(octocrab, org, repo, |release| {
+        release.created_at >= Some(from)
+    })
+    .await?;
+    releases.retain(|release| {
+        (prereleases || !release.prerelease)
+            && release.published_at >= Some(from)
+            && release.published_at <= Some(to)
+    });
+
+    Ok(releases)
+}
+
+// Releases are sorted by created date, a release is published after it's created,
+// so pages are scanned until `keep_going` is false.
+async fn scan_releases(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    keep_going: impl Fn(&repos::Release) -> bool,
+) -> Result<Vec<repos::Release>> {
+    let mut page = retry(format!("{org}/{repo} releases page 0"), || async {
+        octocrab
+            .repos(org, repo)
+            .releases()
+            .list()
+            .per_page(100)
+            .send()
+            .await
+    })
+    .await?;
+
+    let mut all_releases = vec![];
+    let mut pull_times = 0u32;
+    'query_release: loop {
+        for release in page.items.drain(..) {
+            if !keep_going(&release) {
+                break 'query_release;
+            }
+            if !release.draft {
+                all_releases.push(release);
+            }
+        }
+
+        pull_times += 1;
+        let next = retry(format!("{org}/{repo} releases page {pull_times}"), || {
+            octocrab.get_page::<repos::Release>(&page.next)
+        })
+        .await?;
+        page = match next {
+            Some(next_page) => next_page,
+            None => break,
+        };
+    }
+
+    Ok(all_releases)
+}
+
+// Tags in the order of the tag API, without dates.
+pub async fn get_all_tags(octocrab: Arc<Octocrab>, org: &str, repo: &str) -> Result<Vec<Tag>> {
+    let route = format!("repos/{org}/{repo}/tags");
+    let parameters = [("per_page", "100")];
+    let mut page = retry(format!("{org}/{repo} tags page 0"), || {
+        octocrab.get::<Page<TagNode>, _, _>(&route, Some(&parameters))
+    })
+    .await?;
+
+    let html_root = crate::utils::html_root(octocrab.base_url.as_str());
+    let mut all_tags = vec![];
+    let mut pull_times = 0u32;
+    loop {
+        all_tags.extend(page.items.drain(..).map(|node| Tag {
+            html_url: format!("{html_root}{org}/{repo}/releases/tag/{}", node.name),
+            name: node.name,
+            sha: node.commit.sha,
+            date: None,
+        }));
+        pull_times += 1;
+        let next = retry(format!("{org}/{repo} tags page {pull_times}"), || {
+            octocrab.get_page::<TagNode>(&page.next)
+        })
+        .await?;
+        page = match next {
+            Some(next_page) => next_page,
+            None => break,
+        };
+    }
+
+    Ok(all_tags)
+}
+
+pub async fn get_commit_date(
+    octocrab: Arc<Octocrab>,
+    org: &str,
+    repo: &str,
+    sha: &str,
+) -> Result<Option<DateTime<Utc>>> {
+    let route = format!("repos/{org}/{repo}/commits/{sha}");
+    let commit = retry(format!("{org}/{repo} commit {sha}"), || {
+        octocrab.get::<CommitNode, _, ()>(&route, None)
+    })
+    .await?;
+
+    Ok(commit.commit.committer.and_then(|committer| committer.date))
+}
+
+// Dates of tags are cached in sled, only unknown ones are fetched.
+pub async fn get_tags_by_date(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    org: &str,
+    repo: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<Tag>> {
+    let archived = get_archived_tags(&db, org, repo)?;
+    let mut tags = vec![];
+    for mut tag in get_all_tags(octocrab.clone(), org, repo).await? {
+        tag.date = match archived.get(&tag.name) {
+            Some(archived) if archived.sha == tag.sha && archived.date.is_some() => archived.date,
+            _ => get_commit_date(octocrab.clone(), org, repo, &tag.sha).await?,
+        };
+        db.insert(
+            format!("{org}#{repo}#tags#{}", tag.name),
+            serde_json::to_vec(&tag)?,
+        )?;
+        if tag.date >= Some(from) && tag.date <= Some(to) {
+            tags.push(tag);
+        }
+    }
+    tags.sort_by_key(|tag| Reverse(tag.date));
+
+    Ok(tags)
+}
+
+fn get_archived_tags(db: &Db, org: &str, repo: &str) -> Result<HashMap<String, Tag>> {
+    let mut tags = HashMap::new();
+    for item in db.scan_prefix(format!("{org}#{repo}#tags#")) {
+        let (_, val) = item?;
+        let tag: Tag = serde_json::from_slice(&val)?;
+        tags.insert(tag.name.clone(), tag);
+    }
+
+    Ok(tags)
+}
+
+// Sync releases, or tags if `track-tags` is true, into sled and tell what's new.
+// Everything is new at the first time, so only the latest one is told then,
+// the latest release by created date, or the latest tag by commit date.
+pub async fn update_release_status(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    repository: &Repository,
+) -> Result<Vec<ReleaseEvent>> {
+    let (org, repo) = (&repository.organization, &repository.repository);
+    let mut events = vec![];
+    let first_sync;
+    if repository.track_tags {
+        let archived = get_archived_tags(&db, org, repo)?;
+        first_sync = archived.is_empty();
+        let mut batch = sled::Batch::default();
+        for mut tag in get_all_tags(octocrab.clone(), org, repo).await? {
+            if archived.contains_key(&tag.name) {
+                continue;
+            }
+            tag.date = get_commit_date(octocrab.clone(), org, repo, &tag.sha).await?;
+            batch.insert(
+                format!("{org}#{repo}#tags#{}", tag.name).as_bytes(),
+                serde_json::to_vec(&tag)?,
+            );
+            events.push(ReleaseEvent::Tagged(tag));
+        }
+        db.apply_batch(batch)?;
+        // The tag API sorts tags by name, not by date.
+        events.sort_by_key(|event| match event {
+            ReleaseEvent::Tagged(tag) => Reverse(tag.date),
+            _ => Reverse(None),
+        });
+    } else {
+        let mut archived = HashMap::new();
+        for item in db.scan_prefix(format!("{org}#{repo}#releases#")) {
+            let (_, val) = item?;
+            let archived_release: ReleaseWithNotes = serde_json::from_slice(&val)?;
+            archived.insert(
+                archived_release.release.tag_name.clone(),
+                archived_release.release,
+            );
+        }
+        first_sync = archived.is_empty();
+        // Releases older than an archived one are archived too, unless a pre-release
+        // as old as them is archived, which may be promoted.
+        let oldest_prerelease = archived
+            .values()
+            .filter(|release| release.prerelease)
+            .filter_map(|release| release.created_at)
+            .min();
+        let releases = scan_releases(octocrab, org, repo, |release| {
+            !archived.contains_key(&release.tag_name)
+                || (oldest_prerelease.is_some() && release.created_at >= oldest_prerelease)
+        })
+        .await?;
+        let mut batch = sled::Batch::default();
+        for release in releases {
+            let tag_name = release.tag_name.clone();
+            let release = ReleaseWithNotes::from(release);
+            let event = match archived.get(&tag_name) {
+                None if repository.prereleases || !release.release.prerelease => {
+                    Some(ReleaseEvent::Published(Box::new(release.clone())))
+                }
+                Some(old) if old.prerelease && !release.release.prerelease => {
+                    Some(ReleaseEvent::Promoted(Box::new(release.clone())))
+                }
+                _ => None,
+            };
+            batch.insert(
+                format!("{org}#{repo}#releases#{tag_name}").as_bytes(),
+                serde_json::to_vec(&release)?,
+            );
+            events.extend(event);
+        }
+        db.apply_batch(batch)?;
+    }
+    if first_sync {
+        events.truncate(1);
+    }
+
+    Ok(events)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
 
     #[tokio::test]
     async fn get_latest_release_should_work() {
+        let mock = MockGithub::start(&["github"]).await;
+        let (org, repo) = ("paritytech", "polkadot");
+        let release = get_latest_release(mock.client(), org, repo).await.unwrap();
+        assert_eq!(release.tag_name, "v0.9.33");
+
+        // substrate has no release.
+        assert!(get_latest_release(mock.client(), "paritytech", "substrate")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn update_release_status_should_tell_new_and_promoted_releases() {
+        let db = temporary_db();
+        let mut polkadot = Repository {
+            organization: "paritytech".to_owned(),
+            repository: "polkadot".to_owned(),
+            query_release: true,
+            ..Default::default()
+        };
+        let tags = |events: Vec<ReleaseEvent>| {
+            events
+                .iter()
+                .map(|event| (event.tag_name().to_owned(), event.kind()))
+                .collect::<Vec<_>>()
+        };
+
+        // Only the latest release is told at the first time, the draft and the pre-release are not.
+        let mock = MockGithub::start(&["github"]).await;
+        let events = update_release_status(mock.client(), db.clone(), &polkadot)
+            .await
+            .unwrap();
+        assert_eq!(tags(events), vec![("v0.9.33".to_owned(), "release")]);
+        let archived = db
+            .scan_prefix("paritytech#polkadot#releases#")
+            .keys()
+            .count();
+        assert_eq!(archived, 4);
+        // Notes are stored with the release.
+        let stored: ReleaseWithNotes = serde_json::from_slice(
+            &db.get("paritytech#polkadot#releases#v0.9.33")
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            stored.notes.priority,
+            Some(crate::release_notes::UpgradePriority::Low)
+        );
+
+        let mock = MockGithub::start(&["github-new-releases", "github"]).await;
+        let events = update_release_status(mock.client(), db.clone(), &polkadot)
+            .await
+            .unwrap();
+        assert_eq!(
+            tags(events),
+            vec![("v0.9.34".to_owned(), "promoted to release")]
+        );
+        // Pages stop at v0.9.33, older than the archived pre-release.
+        assert!(mock
+            .requests()
+            .iter()
+            .all(|request| !request.contains("page=2")));
+
+        // The new release candidate is archived already, it's told only if pre-releases are wanted.
+        let db = temporary_db();
+        polkadot.prereleases = true;
+        let mock = MockGithub::start(&["github"]).await;
+        update_release_status(mock.client(), db.clone(), &polkadot)
+            .await
+            .unwrap();
+        let mock = MockGithub::start(&["github-new-releases", "github"]).await;
+        let events = update_release_status(mock.client(), db, &polkadot)
+            .await
+            .unwrap();
+        assert_eq!(
+            tags(events),
+            vec![
+                ("v0.9.35-rc1".to_owned(), "pre-release"),
+                ("v0.9.34".to_owned(), "promoted to release")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_release_status_should_track_tags() {
+        let db = temporary_db();
+        let orml = Repository {
+            organization: "open-web3-stack".to_owned(),
+            repository: "open-runtime-module-library".to_owned(),
+            query_release: true,
+            track_tags: true,
+            ..Default::default()
+        };
+
+        // The latest tag by commit date is told at the first time, tags are sorted by name.
+        let mock = MockGithub::start(&["github-tags-by-name", "github"]).await;
+        let events = update_release_status(mock.client(), temporary_db(), &orml)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].tag_name(), "0.10.0");
+
+        let mock = MockGithub::start(&["github"]).await;
+        let events = update_release_status(mock.client(), db.clone(), &orml)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].tag_name(), "0.4.1");
+
+        let mock = MockGithub::start(&["github-new-releases", "github"]).await;
+        let events = update_release_status(mock.client(), db, &orml)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            ReleaseEvent::Tagged(tag) => {
+                assert_eq!(tag.name, "0.4.2");
+                assert_eq!(tag.date.unwrap().to_string(), "2022-11-30 10:00:00 UTC");
+                // Links are on the host of the client.
+                assert_eq!(
+                    tag.html_url,
+                    format!(
+                        "{}open-web3-stack/open-runtime-module-library/releases/tag/0.4.2",
+                        mock.base_url
+                    )
+                );
+            }
+            event => panic!("Unexpected event {event:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn get_releases_by_date_should_work() {
+        let mock = MockGithub::start(&["github"]).await;
         let (org, repo) = ("paritytech", "polkadot");
-        assert!(get_latest_release(org, repo).await.is_some());
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-01", "2022-11-30").unwrap();
+        let releases = get_releases_by_date(mock.client(), org, repo, from, to, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            releases
+                .iter()
+                .map(|r| r.tag_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["v0.9.33", "v0.9.32"]
+        );
+
+        let releases = get_releases_by_date(mock.client(), org, repo, from, to, true)
+            .await
+            .unwrap();
+        assert_eq!(releases[0].tag_name, "v0.9.34");
+        assert_eq!(releases.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn get_tags_by_date_should_cache_dates() {
+        let db = temporary_db();
+        let mock = MockGithub::start(&["github"]).await;
+        let (org, repo) = ("open-web3-stack", "open-runtime-module-library");
+        let (from, to) =
+            crate::utils::parse_from_date_and_to_date("2022-11-01", "2022-11-30").unwrap();
+        let tags = get_tags_by_date(mock.client(), db.clone(), org, repo, from, to)
+            .await
+            .unwrap();
+        assert_eq!(
+            tags.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+            vec!["0.4.1"]
+        );
+        let commits = |mock: &MockGithub| {
+            mock.requests()
+                .iter()
+                .filter(|request| request.contains("/commits/"))
+                .count()
+        };
+        assert_eq!(commits(&mock), 2);
+
+        get_tags_by_date(mock.client(), db, org, repo, from, to)
+            .await
+            .unwrap();
+        assert_eq!(commits(&mock), 2);
     }
 }
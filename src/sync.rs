@@ -0,0 +1,827 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sync subscribed repositories concurrently.
+//!
+//! Issues, PRs, releases and branch commits of every repository are synced by separate tasks,
+//! at most `concurrency` of them run at the same time, and results are gathered per repository.
+//! In GraphQL mode, issues and PRs of all repositories come from a few queries before tasks start,
+//! and the tasks only find out what their PRs touch and link.
+
+use crate::filter::Filter;
+use crate::links::{Link, LinkParser};
+use crate::migrations::Signal;
+use crate::subcribe_commits::BranchCommit;
+use crate::subcribe_issues::IssueEvent;
+use crate::subcribe_prs::PrEvent;
+use crate::subcribe_releases::ReleaseEvent;
+use crate::utils::{FetchMode, Repository};
+use anyhow::{anyhow, Result};
+use octocrab::Octocrab;
+use sled::Db;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+use tokio::{sync::Semaphore, task::JoinHandle};
+use toml::Value;
+
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+// Pr number to crates we depend on it touches.
+pub type Relevance = HashMap<u64, Vec<String>>;
+
+// Pr number to its links to other prs and issues, both ways.
+pub type Links = HashMap<u64, Vec<Link>>;
+
+// Merged pr number to why it's likely a runtime migration.
+pub type Migrations = HashMap<u64, Vec<Signal>>;
+
+// What a repository got in one round, every resource succeeds or fails on its own.
+#[derive(Debug)]
+pub struct RepositorySync {
+    pub organization: String,
+    pub repository: String,
+    pub issues: Result<Vec<IssueEvent>>,
+    pub prs: Result<Vec<PrEvent>>,
+    // Only prs of `prs` touching any crate we depend on are here.
+    pub relevance: Relevance,
+    // Only prs of `prs` linked to any pr or issue are here.
+    pub links: Links,
+    // Only merged prs of `prs` likely to be migrations are here, if `track-migrations` is true.
+    pub migrations: Migrations,
+    // New releases, or tags if `track-tags` is true, always empty if `query-release` is false.
+    pub releases: Result<Vec<ReleaseEvent>>,
+    // New commits on the subscribed branches, oldest first.
+    pub commits: Result<Vec<BranchCommit>>,
+    // Events are filtered by it already, it classifies them for announcements.
+    pub filter: Filter,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Resource {
+    Issues,
+    Prs,
+    Release,
+    Commits,
+}
+
+// Pr events with what's found out about their prs.
+struct SyncedPrs {
+    events: Vec<PrEvent>,
+    relevance: Relevance,
+    links: Links,
+    migrations: Migrations,
+}
+
+enum Synced {
+    Issues(Result<Vec<IssueEvent>>),
+    Prs(Result<SyncedPrs>),
+    Releases(Result<Vec<ReleaseEvent>>),
+    Commits(Result<Vec<BranchCommit>>),
+}
+
+// Remaining REST requests of the host and when it resets in unix time.
+// None if the host doesn't limit rates, like GitHub Enterprise Server by default.
+pub async fn remaining_requests(octocrab: &Octocrab) -> Option<(usize, usize)> {
+    let rate_limit = octocrab.ratelimit().get().await.ok()?;
+    Some((
+        rate_limit.resources.core.remaining,
+        rate_limit.resources.core.reset,
+    ))
+}
+
+// Reviews are fetched for archived open prs, so prs go first.
+async fn sync_prs(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    repository: &Repository,
+    filter: &Filter,
+    parser: &LinkParser,
+) -> Result<SyncedPrs> {
+    let (org, repo) = (&repository.organization, &repository.repository);
+    let mut events =
+        crate::subcribe_prs::update_pr_status(octocrab.clone(), db.clone(), org, repo).await?;
+    // Pr states are archived already, their events are kept even if reviews fail.
+    if repository.track_reviews {
+        match crate::subcribe_prs::update_review_status(octocrab.clone(), db.clone(), org, repo)
+            .await
+        {
+            Ok(mut review_events) => events.append(&mut review_events),
+            Err(e) => println!("{org}/{repo}: failed to update review status: {e}"),
+        }
+    }
+
+    inspect_prs(octocrab, db, repository, filter, parser, events).await
+}
+
+// Links, migrations and touched crates of prs with events.
+// If the repository has paths, only events of prs touching them are kept,
+// and so are prs touching crates we depend on if it's `relevant-only`.
+async fn inspect_prs(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    repository: &Repository,
+    filter: &Filter,
+    parser: &LinkParser,
+    events: Vec<PrEvent>,
+) -> Result<SyncedPrs> {
+    let (org, repo) = (&repository.organization, &repository.repository);
+    let mut relevance = HashMap::new();
+    let mut links: Links = HashMap::new();
+    let mut migrations = HashMap::new();
+    let mut touching = vec![];
+    for event in events {
+        // Links of every pr are kept, its companions may be told later.
+        let number = event.pr().number;
+        if let Entry::Vacant(entry) = links.entry(number) {
+            let pr_links = crate::links::update_links(&db, parser, org, repo, event.pr())
+                .unwrap_or_else(|e| {
+                    println!("{org}/{repo}: failed to update links of pr #{number}: {e}");
+                    vec![]
+                });
+            entry.insert(pr_links);
+        }
+        // So are migration checks of every merged pr.
+        if repository.track_migrations && matches!(event, PrEvent::Merged(_)) {
+            let check = crate::migrations::check_pr(
+                octocrab.clone(),
+                &db,
+                org,
+                repo,
+                event.pr(),
+                &repository.migration_labels,
+            )
+            .await;
+            match check {
+                Ok(check) if check.is_likely() => {
+                    migrations.insert(number, check.signals);
+                }
+                Ok(_) => {}
+                Err(e) => println!("{org}/{repo}: failed to check migrations of pr #{number}: {e}"),
+            }
+        }
+        // States are archived already, a pr whose files can't be fetched is told unfiltered.
+        let touched = crate::relevance::touched_by_pr(
+            octocrab.clone(),
+            db.clone(),
+            org,
+            repo,
+            event.pr(),
+            filter,
+        )
+        .await
+        .unwrap_or_else(|e| {
+            println!("{org}/{repo}: failed to fetch changed files of pr #{number}: {e}");
+            Some(Default::default())
+        });
+        if let Some(touched) = touched {
+            if !touched.crates.is_empty() {
+                relevance.insert(event.pr().number, touched.crates);
+            }
+            touching.push(event);
+        }
+    }
+
+    links.retain(|_, links| !links.is_empty());
+
+    Ok(SyncedPrs {
+        events: touching,
+        relevance,
+        links,
+        migrations,
+    })
+}
+
+// Issue and pr events of every repository from GraphQL snapshots, repositories on the same host
+// are queried together. A repository fails on its own if its host fails, or it's not queried
+// since the rate limit runs out.
+async fn graphql_events(
+    config: &Value,
+    db: Arc<Db>,
+    repositories: &[Repository],
+    names: &[String],
+) -> Result<Vec<(Result<Vec<IssueEvent>>, Result<Vec<PrEvent>>)>> {
+    if crate::utils::get_github_token(config).is_none() {
+        return Err(anyhow!(
+            "GraphQL mode requires a token, please set `token` in section `github`."
+        ));
+    }
+    let mut hosts: Vec<(Arc<Octocrab>, Vec<usize>)> = vec![];
+    for (index, repo) in repositories.iter().enumerate() {
+        let octocrab =
+            crate::utils::repository_client(config, &repo.organization, &repo.repository)?;
+        match hosts
+            .iter_mut()
+            .find(|(host, _)| host.base_url == octocrab.base_url)
+        {
+            Some((_, indexes)) => indexes.push(index),
+            None => hosts.push((octocrab, vec![index])),
+        }
+    }
+
+    let mut snapshot_events = repositories.iter().map(|_| None).collect::<Vec<_>>();
+    for (octocrab, indexes) in hosts {
+        // Links in pr bodies are of the host.
+        let parser = LinkParser::new(
+            &crate::utils::html_root(octocrab.base_url.as_str()),
+            names.iter().map(String::as_str),
+        );
+        let repos = indexes
+            .iter()
+            .map(|index| repositories[*index].clone())
+            .collect::<Vec<_>>();
+        match crate::subcribe_graphql::get_repositories_by_graphql(octocrab, &repos).await {
+            Ok((snapshots, cost)) => {
+                println!(
+                    "GraphQL total cost of {} repositories: {cost}.",
+                    repos.len()
+                );
+                for (index, snapshot) in indexes.iter().zip(snapshots.iter()) {
+                    let events = crate::subcribe_graphql::archive_snapshot(
+                        db.clone(),
+                        &repositories[*index],
+                        snapshot,
+                        &parser,
+                    )
+                    .await;
+                    snapshot_events[*index] = Some(events);
+                }
+            }
+            Err(e) => {
+                for index in indexes {
+                    snapshot_events[index] = Some(Err(anyhow!("{e}")));
+                }
+            }
+        }
+    }
+
+    Ok(snapshot_events
+        .into_iter()
+        .map(|events| match events {
+            Some(Ok((issues, prs))) => (Ok(issues), Ok(prs)),
+            Some(Err(e)) => (Err(anyhow!("{e}")), Err(e)),
+            None => (
+                Err(anyhow!("GraphQL rate limit is exhausted.")),
+                Err(anyhow!("GraphQL rate limit is exhausted.")),
+            ),
+        })
+        .collect())
+}
+
+pub async fn sync_repositories(
+    config: &Value,
+    db: Arc<Db>,
+    repositories: &[Repository],
+    concurrency: usize,
+    mode: FetchMode,
+) -> Result<Vec<RepositorySync>> {
+    // Invalid filters fail before any request.
+    let sources = crate::relevance::lock_sources(config)?;
+    let filters = repositories
+        .iter()
+        .map(|repo| {
+            Ok(
+                Filter::new(repo)?.with_crates(crate::relevance::used_crates(
+                    &sources,
+                    &repo.organization,
+                    &repo.repository,
+                )),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Short references in pr bodies, like `polkadot#123`, may name any subscribed repository.
+    let names = Arc::new(
+        repositories
+            .iter()
+            .map(|repo| repo.repository.clone())
+            .collect::<Vec<_>>(),
+    );
+    // Issues are done once queried, prs are inspected by tasks.
+    let (mut graphql_issues, mut graphql_prs): (Vec<_>, Vec<_>) = match mode {
+        FetchMode::Rest => (vec![], vec![]),
+        FetchMode::Graphql => graphql_events(config, db.clone(), repositories, &names)
+            .await?
+            .into_iter()
+            .map(|(issues, prs)| (Some(issues), Some(prs)))
+            .unzip(),
+    };
+    let mut tasks = vec![];
+    for (index, repo) in repositories.iter().enumerate() {
+        let octocrab =
+            crate::utils::repository_client(config, &repo.organization, &repo.repository)?;
+        if mode == FetchMode::Rest {
+            tasks.push((index, octocrab.clone(), Resource::Issues));
+        }
+        tasks.push((index, octocrab.clone(), Resource::Prs));
+        if repo.query_release {
+            tasks.push((index, octocrab.clone(), Resource::Release));
+        }
+        if !repo.branches.is_empty() {
+            tasks.push((index, octocrab, Resource::Commits));
+        }
+    }
+
+    // Repositories on the same host share its rate limit, every task costs one request at least.
+    // Don't start tasks of a host that can't afford them, they would fail halfway.
+    let mut hosts: HashMap<String, usize> = HashMap::new();
+    for (_, octocrab, _) in tasks.iter() {
+        *hosts.entry(octocrab.base_url.to_string()).or_default() += 1;
+    }
+    let mut exhausted_hosts = HashMap::new();
+    for (host, cost) in hosts {
+        let octocrab = tasks
+            .iter()
+            .find(|(_, octocrab, _)| octocrab.base_url.as_str() == host)
+            .map(|(_, octocrab, _)| octocrab.clone())
+            .expect("Every host comes from a task.");
+        if let Some((remaining, reset)) = remaining_requests(&octocrab).await {
+            if remaining < cost {
+                exhausted_hosts.insert(host, (remaining, reset));
+            }
+        }
+    }
+
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut handles: Vec<(usize, Resource, JoinHandle<Synced>)> = vec![];
+    for (index, octocrab, resource) in tasks {
+        let repo = &repositories[index];
+        let filter = filters[index].clone();
+        let repository = repo.clone();
+        let (org, repo) = (repo.organization.clone(), repo.repository.clone());
+        let exhausted =
+            exhausted_hosts
+                .get(octocrab.base_url.as_str())
+                .map(|(remaining, reset)| {
+                    anyhow!(
+                        "Only {remaining} requests left on {}, it resets at {reset}.",
+                        octocrab.base_url
+                    )
+                });
+        let pr_events = match resource {
+            Resource::Prs => graphql_prs.get_mut(index).and_then(Option::take),
+            _ => None,
+        };
+        let (semaphore, db, names) = (semaphore.clone(), db.clone(), names.clone());
+        let handle = tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            match (resource, exhausted) {
+                (Resource::Issues, Some(e)) => Synced::Issues(Err(e)),
+                (Resource::Prs, Some(e)) => Synced::Prs(Err(e)),
+                (Resource::Release, Some(e)) => Synced::Releases(Err(e)),
+                (Resource::Commits, Some(e)) => Synced::Commits(Err(e)),
+                (Resource::Issues, None) => Synced::Issues(
+                    crate::subcribe_issues::update_issue_status(octocrab, db, &org, &repo).await,
+                ),
+                (Resource::Prs, None) => {
+                    let parser = LinkParser::new(
+                        &crate::utils::html_root(octocrab.base_url.as_str()),
+                        names.iter().map(String::as_str),
+                    );
+                    Synced::Prs(match pr_events {
+                        Some(Ok(events)) => {
+                            inspect_prs(octocrab, db, &repository, &filter, &parser, events).await
+                        }
+                        Some(Err(e)) => Err(e),
+                        None => sync_prs(octocrab, db, &repository, &filter, &parser).await,
+                    })
+                }
+                (Resource::Release, None) => Synced::Releases(
+                    crate::subcribe_releases::update_release_status(octocrab, db, &repository)
+                        .await,
+                ),
+                (Resource::Commits, None) => Synced::Commits(
+                    crate::subcribe_commits::update_commit_status(octocrab, db, &repository).await,
+                ),
+            }
+        });
+        handles.push((index, resource, handle));
+    }
+
+    let mut results = repositories
+        .iter()
+        .zip(filters)
+        .enumerate()
+        .map(|(index, (repo, filter))| RepositorySync {
+            organization: repo.organization.clone(),
+            repository: repo.repository.clone(),
+            issues: match graphql_issues.get_mut(index).and_then(Option::take) {
+                Some(issues) => issues.map(|events| {
+                    events
+                        .into_iter()
+                        .filter(|event| filter.matches_issue(event.issue()))
+                        .collect()
+                }),
+                None => Ok(vec![]),
+            },
+            prs: Ok(vec![]),
+            relevance: HashMap::new(),
+            links: HashMap::new(),
+            migrations: HashMap::new(),
+            releases: Ok(vec![]),
+            commits: Ok(vec![]),
+            filter,
+        })
+        .collect::<Vec<_>>();
+    for (index, resource, handle) in handles {
+        let result = &mut results[index];
+        // Everything is archived, but only events of wanted issues and prs are told.
+        let filter = &result.filter;
+        match (resource, handle.await) {
+            (_, Ok(Synced::Issues(issues))) => {
+                result.issues = issues.map(|events| {
+                    events
+                        .into_iter()
+                        .filter(|event| filter.matches_issue(event.issue()))
+                        .collect()
+                })
+            }
+            (_, Ok(Synced::Prs(prs))) => {
+                result.prs = prs.map(|prs| {
+                    result.relevance = prs.relevance;
+                    result.links = prs.links;
+                    result.migrations = prs.migrations;
+                    prs.events
+                        .into_iter()
+                        .filter(|event| filter.matches_pr(event.pr()))
+                        .collect()
+                })
+            }
+            (_, Ok(Synced::Releases(releases))) => result.releases = releases,
+            (_, Ok(Synced::Commits(commits))) => result.commits = commits,
+            // A panicked task only fails its own resource.
+            (Resource::Issues, Err(e)) => result.issues = Err(e.into()),
+            (Resource::Prs, Err(e)) => result.prs = Err(e.into()),
+            (Resource::Release, Err(e)) => result.releases = Err(e.into()),
+            (Resource::Commits, Err(e)) => result.commits = Err(e.into()),
+        }
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    fn config_of(mock: &MockGithub) -> Value {
+        format!(
+            r#"
+            [github]
+            base-url = "{}"
+
+            [paritytech.polkadot]
+            query-release = true
+
+            [paritytech.substrate]
+            query-release = false
+
+            [Manta-Network.docs]
+            query-release = false
+            "#,
+            mock.base_url
+        )
+        .parse::<Value>()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn one_failed_repository_should_not_block_others() {
+        let mock = MockGithub::start(&["github"]).await;
+        let config = config_of(&mock);
+        let repos = crate::utils::get_repositories(&config).unwrap();
+        let results = sync_repositories(&config, temporary_db(), &repos, 2, FetchMode::Rest)
+            .await
+            .unwrap();
+        let result = |repo: &str| results.iter().find(|r| r.repository == repo).unwrap();
+
+        // There're no issues or PRs of polkadot in fixtures, but its release is still synced.
+        let polkadot = result("polkadot");
+        assert!(polkadot.issues.is_err());
+        assert!(polkadot.prs.is_err());
+        let releases = polkadot.releases.as_ref().unwrap();
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].tag_name(), "v0.9.33");
+
+        let substrate = result("substrate");
+        assert!(substrate.issues.is_ok());
+        assert!(substrate.prs.is_ok());
+        assert!(result("docs").issues.is_ok());
+        assert!(result("docs").prs.is_ok());
+    }
+
+    #[tokio::test]
+    async fn exhausted_rate_limit_should_skip_repositories() {
+        let mock = MockGithub::start(&["github-rate-limited", "github"]).await;
+        let config = config_of(&mock);
+        let repos = crate::utils::get_repositories(&config).unwrap();
+        let results = sync_repositories(&config, temporary_db(), &repos, 2, FetchMode::Rest)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        for result in results {
+            assert!(result.issues.is_err());
+            assert!(result.prs.is_err());
+            // Only polkadot queries releases.
+            assert_eq!(result.releases.is_err(), result.repository == "polkadot");
+        }
+        // Nothing is sent but the rate limit query.
+        assert_eq!(mock.requests(), vec!["GET /rate_limit?"]);
+    }
+
+    #[tokio::test]
+    async fn reviews_should_be_tracked_per_repository() {
+        let mock = MockGithub::start(&["github"]).await;
+        let config = format!(
+            r#"
+            [github]
+            base-url = "{}"
+
+            [Manta-Network.Manta]
+            query-release = false
+            track-reviews = true
+
+            [paritytech.substrate]
+            query-release = false
+            "#,
+            mock.base_url
+        )
+        .parse::<Value>()
+        .unwrap();
+        let repos = crate::utils::get_repositories(&config).unwrap();
+        let results = sync_repositories(&config, temporary_db(), &repos, 2, FetchMode::Rest)
+            .await
+            .unwrap();
+        let manta = results.iter().find(|r| r.repository == "Manta").unwrap();
+        assert!(manta
+            .prs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|event| event.kind() == "approved"));
+
+        let reviews = mock
+            .requests()
+            .into_iter()
+            .filter(|request| request.contains("/reviews"))
+            .collect::<Vec<_>>();
+        assert_eq!(reviews.len(), 4);
+        assert!(reviews
+            .iter()
+            .all(|request| request.contains("Manta-Network/Manta")));
+    }
+
+    #[tokio::test]
+    async fn graphql_mode_should_tell_events_and_sync_releases_by_rest() {
+        let mock = MockGithub::start(&["github"]).await;
+        let config = format!(
+            r#"
+            [github]
+            base-url = "{}"
+            token = "ghp_test"
+
+            [paritytech.substrate]
+            query-release = false
+
+            [paritytech.polkadot]
+            query-release = true
+            "#,
+            mock.base_url
+        )
+        .parse::<Value>()
+        .unwrap();
+        let mut repos = crate::utils::get_repositories(&config).unwrap();
+        // substrate is `r0` of the GraphQL fixture.
+        repos.sort_by_key(|repo| repo.repository != "substrate");
+        let results = sync_repositories(&config, temporary_db(), &repos, 2, FetchMode::Graphql)
+            .await
+            .unwrap();
+        let result = |repo: &str| results.iter().find(|r| r.repository == repo).unwrap();
+
+        let substrate = result("substrate");
+        assert!(substrate.issues.as_ref().unwrap().is_empty());
+        let prs = substrate.prs.as_ref().unwrap();
+        assert_eq!(prs.len(), 1);
+        assert_eq!(prs[0].kind(), "new");
+        assert_eq!(prs[0].pr().number, 12945);
+        let releases = result("polkadot").releases.as_ref().unwrap();
+        assert_eq!(releases[0].tag_name(), "v0.9.33");
+
+        // Both repositories are queried at once, nothing of issues or prs is fetched by REST.
+        let requests = mock.requests();
+        assert_eq!(
+            requests
+                .iter()
+                .filter(|request| request.starts_with("POST /graphql"))
+                .count(),
+            1
+        );
+        assert!(!requests
+            .iter()
+            .any(|request| request.contains("/issues?") || request.contains("/pulls?")));
+    }
+
+    #[tokio::test]
+    async fn events_should_be_filtered_per_repository() {
+        let mock = MockGithub::start(&["github"]).await;
+        let config = format!(
+            r#"
+            [github]
+            base-url = "{}"
+
+            [Manta-Network.Manta]
+            query-release = false
+            title-regex = "(?i)polkadot"
+            exclude-title-regex = "v0.9.28"
+            "#,
+            mock.base_url
+        )
+        .parse::<Value>()
+        .unwrap();
+        let repos = crate::utils::get_repositories(&config).unwrap();
+        let results = sync_repositories(&config, temporary_db(), &repos, 2, FetchMode::Rest)
+            .await
+            .unwrap();
+        let prs = results[0].prs.as_ref().unwrap();
+        assert_eq!(
+            prs.iter()
+                .map(|event| event.pr().number)
+                .collect::<Vec<_>>(),
+            vec![880]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_prs_touching_paths_should_be_told() {
+        let mock = MockGithub::start(&["github"]).await;
+        let config = format!(
+            r#"
+            [github]
+            base-url = "{}"
+
+            [Manta-Network.Manta]
+            query-release = false
+            paths = ["runtime/calamari/src/weights/**", "runtime/manta/*.toml"]
+            "#,
+            mock.base_url
+        )
+        .parse::<Value>()
+        .unwrap();
+        let repos = crate::utils::get_repositories(&config).unwrap();
+        let results = sync_repositories(&config, temporary_db(), &repos, 2, FetchMode::Rest)
+            .await
+            .unwrap();
+        let mut numbers = results[0]
+            .prs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|event| event.pr().number)
+            .collect::<Vec<_>>();
+        numbers.sort();
+        // #881 renames a file out of weights, #867 changes the manta runtime on page 2.
+        assert_eq!(numbers, vec![867, 881]);
+    }
+
+    #[tokio::test]
+    async fn only_relevant_prs_should_be_told() {
+        let mock = MockGithub::start(&["github"]).await;
+        let config = format!(
+            r#"
+            [github]
+            base-url = "{}"
+
+            [relevance]
+            lock-file = "{}/fixtures/cargo/downstream/Cargo.lock"
+
+            [Manta-Network.Manta]
+            query-release = false
+            relevant-only = true
+            "#,
+            mock.base_url,
+            env!("CARGO_MANIFEST_DIR")
+        )
+        .parse::<Value>()
+        .unwrap();
+        let repos = crate::utils::get_repositories(&config).unwrap();
+        let results = sync_repositories(&config, temporary_db(), &repos, 2, FetchMode::Rest)
+            .await
+            .unwrap();
+        let mut numbers = results[0]
+            .prs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|event| event.pr().number)
+            .collect::<Vec<_>>();
+        numbers.sort();
+        // #881 changes the calamari runtime, #867 the staking pallet, both are depended on.
+        assert_eq!(numbers, vec![867, 881]);
+        assert_eq!(results[0].relevance[&881], vec!["calamari-runtime"]);
+        assert_eq!(results[0].relevance[&867], vec!["pallet-parachain-staking"]);
+    }
+
+    #[tokio::test]
+    async fn prs_whose_files_fail_should_be_told_unfiltered() {
+        let mock = MockGithub::start(&["github"]).await;
+        mock.fail("repos/Manta-Network/Manta/pulls/881/files", 1, 404, 1);
+        let config = format!(
+            r#"
+            [github]
+            base-url = "{}"
+
+            [relevance]
+            lock-file = "{}/fixtures/cargo/downstream/Cargo.lock"
+
+            [Manta-Network.Manta]
+            query-release = false
+            relevant-only = true
+            "#,
+            mock.base_url,
+            env!("CARGO_MANIFEST_DIR")
+        )
+        .parse::<Value>()
+        .unwrap();
+        let repos = crate::utils::get_repositories(&config).unwrap();
+        let results = sync_repositories(&config, temporary_db(), &repos, 2, FetchMode::Rest)
+            .await
+            .unwrap();
+        let mut numbers = results[0]
+            .prs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|event| event.pr().number)
+            .collect::<Vec<_>>();
+        numbers.sort();
+        assert_eq!(numbers, vec![867, 881]);
+        assert!(!results[0].relevance.contains_key(&881));
+    }
+
+    #[tokio::test]
+    async fn merged_migrations_should_be_flagged() {
+        let config = |base_url: &str| {
+            format!(
+                r#"
+                [github]
+                base-url = "{base_url}"
+
+                [Manta-Network.Manta]
+                query-release = false
+                track-migrations = true
+                "#
+            )
+            .parse::<Value>()
+            .unwrap()
+        };
+        let db = temporary_db();
+        let mock = MockGithub::start(&["github"]).await;
+        let repos = crate::utils::get_repositories(&config(&mock.base_url)).unwrap();
+        sync_repositories(
+            &config(&mock.base_url),
+            db.clone(),
+            &repos,
+            2,
+            FetchMode::Rest,
+        )
+        .await
+        .unwrap();
+
+        // #867 is merged with label `A9-migration`.
+        let mock =
+            MockGithub::start(&["github-pr-migration", "github-pr-transitions", "github"]).await;
+        let results = sync_repositories(
+            &config(&mock.base_url),
+            db.clone(),
+            &repos,
+            2,
+            FetchMode::Rest,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            results[0].migrations,
+            HashMap::from([(867, vec![Signal::Label("A9-migration".to_owned())])])
+        );
+        // Its files are no migrations, only the label is.
+        assert_eq!(
+            crate::migrations::get_file_signals(&db, "Manta-Network", "Manta", 867).unwrap(),
+            Some(vec![])
+        );
+    }
+}
@@ -17,8 +17,11 @@
 use anyhow::Result;
 use chrono::naive::Days;
 use chrono::prelude::*;
+use clap::ValueEnum;
+use octocrab::Octocrab;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs::File,
     io::{prelude::*, BufReader},
     sync::Arc,
@@ -30,18 +33,69 @@ use toml::Value;
 pub enum IntenalError {
     #[error("Failed to parse toml file.")]
     TomlParseError,
+    #[error("Invalid item {0}, it should be like org/repo#number.")]
+    InvalidWatchedItem(String),
+    #[error("Invalid repository {0}, it should be like org/repo.")]
+    InvalidRepository(String),
+    #[error("{0} is relevant-only, but lock-file in section relevance is not set.")]
+    RelevanceWithoutLockFile(String),
+    #[error("{1} results of `{0}` are found in one second, search can't return more than 1000.")]
+    SearchResultLimit(String, u64),
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", default)]
 pub struct Repository {
     pub organization: String,
     pub repository: String,
     pub query_release: bool,
+    // REST API root of the host, falls back to `base-url` in section `github`.
+    pub base_url: Option<String>,
+    // Fetch reviews of open PRs, and tell approvals, change requests and dismissals.
+    pub track_reviews: bool,
+    // Only issues and PRs with at least one of these labels, all of them if it's empty.
+    pub include_labels: Vec<String>,
+    // Issues and PRs with any of these labels are ignored.
+    pub exclude_labels: Vec<String>,
+    // Only issues and PRs whose titles match it.
+    pub title_regex: Option<String>,
+    // Issues and PRs whose titles match it are ignored.
+    pub exclude_title_regex: Option<String>,
+    // Label to category, shown in reports and announcements.
+    pub categories: BTreeMap<String, String>,
+    // Tell pre-releases too, they're archived anyway.
+    pub prereleases: bool,
+    // Track tags instead of releases, for repositories tagging without publishing releases.
+    pub track_tags: bool,
+    // Only PRs changing files matched by these globs, like `frame/balances/**`, all of them if it's empty.
+    pub paths: Vec<String>,
+    // Release branches to follow, like `polkadot-v0.9.33`, new commits on them are told.
+    pub branches: Vec<String>,
+    // Branch or tag to the commit we're pinned to in Cargo.lock, written by `discover`.
+    pub pins: BTreeMap<String, String>,
+    // Only PRs touching crates in `lock-file` of section `relevance`.
+    pub relevant_only: bool,
+    // Check merged PRs for runtime migrations and storage changes, one more request per merged PR.
+    pub track_migrations: bool,
+    // Labels marking migrations, besides ones mentioning `migration`.
+    pub migration_labels: Vec<String>,
 }
 
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchMode {
+    // Query issues, PRs and releases repository by repository through REST pagination.
+    #[default]
+    Rest,
+    // Query all repositories in a few aliased GraphQL queries, it requires a token.
+    Graphql,
+}
+
+pub const CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml");
+
 // read project config file
 pub fn read_config() -> Result<Value> {
-    let config = File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml"))?;
+    let config = File::open(CONFIG_PATH)?;
     let mut buff = BufReader::new(config);
     let mut contents = String::new();
     buff.read_to_string(&mut contents)?;
@@ -59,13 +113,18 @@ pub fn get_repositories(config: &Value) -> Result<Vec<Repository>> {
     {
         for (m, n) in config[k].as_table().as_ref().unwrap().into_iter() {
             if let Some(query_release) = n.get("query-release") {
+                query_release
+                    .as_bool()
+                    .ok_or(IntenalError::TomlParseError)?;
                 let repo = Repository {
                     organization: k.to_owned(),
                     repository: m.to_owned(),
-                    query_release: query_release
-                        .as_bool()
-                        .ok_or(IntenalError::TomlParseError)?,
+                    ..n.clone().try_into()?
                 };
+                // Every PR would be dropped without crates we depend on.
+                if repo.relevant_only && crate::relevance::get_lock_file(config).is_none() {
+                    return Err(IntenalError::RelevanceWithoutLockFile(format!("{k}/{m}")).into());
+                }
                 repositories.push(repo);
             }
         }
@@ -74,6 +133,19 @@ pub fn get_repositories(config: &Value) -> Result<Vec<Repository>> {
     Ok(repositories)
 }
 
+// Summaries are posted once a day by default.
+pub const DEFAULT_SUMMARY_FREQUENCE: i64 = 24 * 60 * 60;
+
+// `summary-frequence` of the section, like `forks` or `pins`.
+pub fn get_summary_frequence(config: &Value, section: &str) -> chrono::Duration {
+    let secs = config
+        .get(section)
+        .and_then(|section| section.get("summary-frequence"))
+        .and_then(|frequence| frequence.as_integer())
+        .unwrap_or(DEFAULT_SUMMARY_FREQUENCE);
+    chrono::Duration::seconds(secs)
+}
+
 pub fn get_update_frequence(config: &Value) -> i64 {
     config["schedule"]["frequence"]
         .as_integer()
@@ -86,6 +158,114 @@ pub fn get_discord_token(config: &Value) -> &str {
         .expect("Please give discord token here.")
 }
 
+pub fn get_fetch_mode(config: &Value) -> Result<FetchMode> {
+    match config
+        .get("github")
+        .and_then(|github| github.get("fetch-mode"))
+    {
+        Some(mode) => Ok(mode.clone().try_into()?),
+        None => Ok(FetchMode::default()),
+    }
+}
+
+pub fn get_retry_policy(config: &Value) -> Result<crate::retry::RetryPolicy> {
+    match config.get("retry") {
+        Some(policy) => Ok(policy.clone().try_into()?),
+        None => Ok(Default::default()),
+    }
+}
+
+// How many sync tasks run at the same time, at least 1.
+pub fn get_concurrency(config: &Value) -> usize {
+    config
+        .get("github")
+        .and_then(|github| github.get("concurrency"))
+        .and_then(|concurrency| concurrency.as_integer())
+        .map(|concurrency| concurrency.max(1) as usize)
+        .unwrap_or(crate::sync::DEFAULT_CONCURRENCY)
+}
+
+// Token in config goes first, then the environment variable GITHUB_TOKEN.
+pub fn get_github_token(config: &Value) -> Option<String> {
+    config
+        .get("github")
+        .and_then(|github| github.get("token"))
+        .and_then(|token| token.as_str())
+        .filter(|token| !token.is_empty())
+        .map(ToOwned::to_owned)
+        .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+        .filter(|token| !token.is_empty())
+}
+
+// REST API root, e.g. https://github.example.com/api/v3/ for GitHub Enterprise Server.
+pub fn get_base_url(config: &Value) -> Option<&str> {
+    config
+        .get("github")
+        .and_then(|github| github.get("base-url"))
+        .and_then(|base_url| base_url.as_str())
+        .filter(|base_url| !base_url.is_empty())
+}
+
+pub fn build_github_client(base_url: Option<&str>, token: Option<String>) -> Result<Arc<Octocrab>> {
+    // Unauthenticated requests to api.github.com share the global instance.
+    if base_url.is_none() && token.is_none() {
+        return Ok(octocrab::instance());
+    }
+
+    let mut builder = Octocrab::builder();
+    if let Some(base_url) = base_url {
+        // Routes are joined to base url, so it must end with '/', or the last segment is dropped.
+        let base_url = match base_url.ends_with('/') {
+            true => base_url.to_owned(),
+            false => format!("{base_url}/"),
+        };
+        builder = builder.base_url(base_url)?;
+    }
+    if let Some(token) = token {
+        builder = builder.personal_token(token);
+    }
+
+    Ok(Arc::new(builder.build()?))
+}
+
+// Client of the host in section `github`.
+pub fn github_client(config: &Value) -> Result<Arc<Octocrab>> {
+    build_github_client(get_base_url(config), get_github_token(config))
+}
+
+// The subscribed repository in config, None if it's not subscribed.
+pub fn get_repository(config: &Value, org: &str, repo: &str) -> Result<Option<Repository>> {
+    Ok(get_repositories(config)?.into_iter().find(|r| {
+        r.organization.eq_ignore_ascii_case(org) && r.repository.eq_ignore_ascii_case(repo)
+    }))
+}
+
+// Use the host of the subscribed repository if it's configured.
+pub fn repository_client(config: &Value, org: &str, repo: &str) -> Result<Arc<Octocrab>> {
+    let base_url = get_repository(config, org, repo)?.and_then(|r| r.base_url);
+    let base_url = base_url.as_deref().or_else(|| get_base_url(config));
+
+    build_github_client(base_url, get_github_token(config))
+}
+
+// GitHub serves GraphQL at https://api.github.com/graphql,
+// but Enterprise Server serves it at https://host/api/graphql rather than under /api/v3/.
+pub fn graphql_url(base_url: &str) -> String {
+    match base_url.strip_suffix("/api/v3/") {
+        Some(host) => format!("{host}/api/graphql"),
+        None => format!("{base_url}graphql"),
+    }
+}
+
+// Web pages of https://api.github.com/ are under https://github.com/,
+// and Enterprise Server serves them at https://host/ rather than under /api/v3/.
+pub fn html_root(base_url: &str) -> String {
+    match base_url.strip_suffix("api/v3/") {
+        Some(host) => host.to_owned(),
+        None => base_url.replacen("://api.", "://", 1),
+    }
+}
+
 // configure sled db
 pub fn db_config() -> sled::Result<Arc<sled::Db>> {
     sled::Config::default()
@@ -128,6 +308,92 @@ mod tests {
         assert_eq!(repos.len(), 6);
         assert_eq!(get_discord_token(&config), "123456789");
         assert_eq!(get_update_frequence(&config), 7200);
+        assert_eq!(get_fetch_mode(&config).unwrap(), FetchMode::Rest);
+        assert_eq!(get_base_url(&config), Some("https://api.github.com/"));
+        assert_eq!(get_retry_policy(&config).unwrap().max_attempts, 3);
+        assert_eq!(get_concurrency(&config), 4);
+    }
+
+    #[test]
+    fn repository_base_url_should_override_global_one() {
+        let config = r#"
+            [github]
+            base-url = "https://api.github.com/"
+
+            [manta.manta]
+            organization = "manta"
+            repository = "manta"
+            query-release = false
+            base-url = "https://github.example.com/api/v3"
+        "#
+        .parse::<Value>()
+        .unwrap();
+        let repos = get_repositories(&config).unwrap();
+        assert_eq!(
+            repos[0].base_url.as_deref(),
+            Some("https://github.example.com/api/v3")
+        );
+
+        let octocrab = repository_client(&config, "manta", "manta").unwrap();
+        assert_eq!(
+            octocrab.base_url.as_str(),
+            "https://github.example.com/api/v3/"
+        );
+        assert_eq!(
+            octocrab.absolute_url("repos/manta/manta").unwrap().as_str(),
+            "https://github.example.com/api/v3/repos/manta/manta"
+        );
+        let octocrab = repository_client(&config, "paritytech", "substrate").unwrap();
+        assert_eq!(octocrab.base_url.as_str(), "https://api.github.com/");
+    }
+
+    #[test]
+    fn relevant_only_should_require_lock_file() {
+        let config = |relevance: &str| {
+            format!(
+                r#"
+                {relevance}
+
+                [paritytech.substrate]
+                query-release = false
+                relevant-only = true
+                "#
+            )
+            .parse::<Value>()
+            .unwrap()
+        };
+        assert!(get_repositories(&config("")).is_err());
+        assert!(get_repositories(&config("[relevance]\nlock-file = \"\"")).is_err());
+        assert!(get_repositories(&config("[relevance]\nlock-file = \"Cargo.lock\"")).is_ok());
+    }
+
+    #[test]
+    fn graphql_url_should_work() {
+        assert_eq!(
+            graphql_url("https://api.github.com/"),
+            "https://api.github.com/graphql"
+        );
+        assert_eq!(
+            graphql_url("https://github.example.com/api/v3/"),
+            "https://github.example.com/api/graphql"
+        );
+        assert_eq!(
+            graphql_url("http://127.0.0.1:8080/"),
+            "http://127.0.0.1:8080/graphql"
+        );
+    }
+
+    #[test]
+    fn html_root_should_work() {
+        assert_eq!(html_root("https://api.github.com/"), "https://github.com/");
+        assert_eq!(
+            html_root("https://github.example.com/api/v3/"),
+            "https://github.example.com/"
+        );
+        assert_eq!(
+            html_root("http://127.0.0.1:8080/"),
+            "http://127.0.0.1:8080/"
+        );
     }
 
     #[tokio::test]
@@ -135,7 +401,8 @@ mod tests {
         let config = read_config().unwrap();
         let all_repos = get_repositories(&config).unwrap();
 
-        let octocrab = octocrab::instance();
+        let mock = crate::mock_github::MockGithub::start(&["github"]).await;
+        let octocrab = mock.client();
         for repo in all_repos {
             assert!(octocrab
                 .repos(&repo.organization, &repo.repository)
@@ -0,0 +1,481 @@
+// Copyright 2020-2022 Manta Network.
+// This file is part of Manta.
+//
+// Manta is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Manta is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Manta.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Watch specific issues or PRs, like a tracking issue, and relay every update of their timeline.
+//!
+//! Watched items come from section `watchlist` in config, and ones added by CLI or slash commands
+//! are kept in sled. The last seen timeline event of every item is kept in sled too.
+
+use crate::retry::retry;
+use crate::utils::IntenalError;
+use anyhow::Result;
+use chrono::prelude::*;
+use octocrab::{Octocrab, Page};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sled::Db;
+use std::{fmt, str::FromStr, sync::Arc};
+use toml::Value as Config;
+
+// Timeline events nobody wants to be told.
+const NOISY_EVENTS: [&str; 4] = ["subscribed", "unsubscribed", "mentioned", "comment_deleted"];
+
+// An issue or a pr, written like `paritytech/substrate#12345`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WatchedItem {
+    pub organization: String,
+    pub repository: String,
+    pub number: u64,
+}
+
+impl FromStr for WatchedItem {
+    type Err = IntenalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || IntenalError::InvalidWatchedItem(s.to_owned());
+        let (repo, number) = s.trim().split_once('#').ok_or_else(invalid)?;
+        let (org, repo) = repo.split_once('/').ok_or_else(invalid)?;
+        if org.is_empty() || repo.is_empty() || repo.contains('/') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            organization: org.to_owned(),
+            repository: repo.to_owned(),
+            number: number.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for WatchedItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}#{}",
+            self.organization, self.repository, self.number
+        )
+    }
+}
+
+impl WatchedItem {
+    // GitHub redirects it to the pr if the number is a pr, `html_root` is like `https://github.com/`.
+    pub fn html_url(&self, html_root: &str) -> String {
+        format!(
+            "{html_root}{}/{}/issues/{}",
+            self.organization, self.repository, self.number
+        )
+    }
+}
+
+// An event of the timeline API, only fields in use are kept.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TimelineEvent {
+    // Cross-referenced events have no id.
+    pub id: Option<u64>,
+    pub event: String,
+    pub created_at: Option<DateTime<Utc>>,
+    // Who did it, commented events have `user` instead.
+    pub actor: Option<Value>,
+    pub user: Option<Value>,
+    pub body: Option<String>,
+    pub html_url: Option<String>,
+    pub label: Option<Value>,
+    pub assignee: Option<Value>,
+    pub rename: Option<Value>,
+    pub state: Option<String>,
+    pub state_reason: Option<String>,
+    // Where the item is mentioned, only in cross-referenced events.
+    pub source: Option<Value>,
+}
+
+// Where the timeline of an item has been read to.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WatchState {
+    pub last_event_id: Option<u64>,
+    // Events without id after the last seen one.
+    pub seen_after: usize,
+    // All seen events, in case the last seen one is deleted.
+    pub seen: usize,
+}
+
+// An update of a watched item.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchUpdate {
+    pub item: WatchedItem,
+    pub event: String,
+    pub description: String,
+    pub url: String,
+}
+
+impl fmt::Display for WatchUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.item, self.description)
+    }
+}
+
+fn login(user: &Option<Value>) -> &str {
+    user.as_ref()
+        .and_then(|user| user["login"].as_str())
+        .unwrap_or("someone")
+}
+
+// The first line of a comment, at most 100 chars.
+fn excerpt(body: &str) -> String {
+    let line = body.lines().next().unwrap_or_default();
+    match line.char_indices().nth(100) {
+        Some((index, _)) => format!("{}...", &line[..index]),
+        None => line.to_owned(),
+    }
+}
+
+// Like `bkchr added label A0-please_review`, None for noisy events.
+pub fn describe(event: &TimelineEvent) -> Option<String> {
+    if NOISY_EVENTS.contains(&event.event.as_str()) {
+        return None;
+    }
+    let actor = login(&event.actor);
+    let name = |value: &Option<Value>, field: &str| {
+        value
+            .as_ref()
+            .and_then(|value| value[field].as_str())
+            .unwrap_or_default()
+            .to_owned()
+    };
+    let description = match event.event.as_str() {
+        "commented" => format!(
+            "{} commented: {}",
+            login(&event.user),
+            excerpt(event.body.as_deref().unwrap_or_default())
+        ),
+        "labeled" => format!("{actor} added label {}", name(&event.label, "name")),
+        "unlabeled" => format!("{actor} removed label {}", name(&event.label, "name")),
+        "assigned" => format!("{actor} assigned {}", name(&event.assignee, "login")),
+        "unassigned" => format!("{actor} unassigned {}", name(&event.assignee, "login")),
+        "renamed" => format!(
+            "{actor} renamed it from \"{}\" to \"{}\"",
+            name(&event.rename, "from"),
+            name(&event.rename, "to")
+        ),
+        "closed" => match event.state_reason.as_deref() {
+            Some(reason) => format!("{actor} closed it as {}", reason.replace('_', " ")),
+            None => format!("{actor} closed it"),
+        },
+        "reopened" => format!("{actor} reopened it"),
+        "merged" => format!("{actor} merged it"),
+        "reviewed" => format!(
+            "{} reviewed it: {}",
+            login(&event.user),
+            event.state.as_deref().unwrap_or_default().replace('_', " ")
+        ),
+        "cross-referenced" => {
+            let issue = event
+                .source
+                .as_ref()
+                .map(|source| &source["issue"])
+                .unwrap_or(&Value::Null);
+            let kind = match issue["pull_request"].is_null() {
+                true => "issue",
+                false => "pr",
+            };
+            format!(
+                "is linked from {kind} {} {}",
+                issue["title"].as_str().unwrap_or_default(),
+                issue["html_url"].as_str().unwrap_or_default()
+            )
+        }
+        "connected" => format!("{actor} linked a pr"),
+        "disconnected" => format!("{actor} unlinked a pr"),
+        other => format!("{actor} {}", other.replace('_', " ")),
+    };
+
+    Some(description)
+}
+
+// Events after the last seen one, and where the timeline is read to now.
+// Nothing is new at the first time, or all events would be relayed.
+pub fn unseen_events(
+    timeline: &[TimelineEvent],
+    state: Option<&WatchState>,
+) -> (Vec<TimelineEvent>, WatchState) {
+    let start = match state {
+        None => timeline.len(),
+        Some(state) => state
+            .last_event_id
+            .and_then(|id| timeline.iter().position(|event| event.id == Some(id)))
+            .map(|position| position + 1 + state.seen_after)
+            .unwrap_or(state.seen),
+    };
+    let last_id_position = timeline.iter().rposition(|event| event.id.is_some());
+    let new_state = WatchState {
+        last_event_id: last_id_position.and_then(|position| timeline[position].id),
+        seen_after: match last_id_position {
+            Some(position) => timeline.len() - position - 1,
+            None => timeline.len(),
+        },
+        seen: timeline.len(),
+    };
+
+    (timeline.iter().skip(start).cloned().collect(), new_state)
+}
+
+pub async fn get_timeline(
+    octocrab: Arc<Octocrab>,
+    item: &WatchedItem,
+) -> Result<Vec<TimelineEvent>> {
+    let route = format!(
+        "repos/{}/{}/issues/{}/timeline",
+        item.organization, item.repository, item.number
+    );
+    let parameters = [("per_page", "100")];
+    let mut page = retry(format!("{item} timeline page 0"), || {
+        octocrab.get::<Page<TimelineEvent>, _, _>(&route, Some(&parameters))
+    })
+    .await?;
+
+    let mut timeline = vec![];
+    let mut pull_times = 0u32;
+    loop {
+        timeline.append(&mut page.items);
+        pull_times += 1;
+        let next = retry(format!("{item} timeline page {pull_times}"), || {
+            octocrab.get_page::<TimelineEvent>(&page.next)
+        })
+        .await?;
+        page = match next {
+            Some(next_page) => next_page,
+            None => break,
+        };
+    }
+
+    Ok(timeline)
+}
+
+fn watched_key(item: &WatchedItem) -> String {
+    format!("watchlist#items#{item}")
+}
+
+fn state_key(item: &WatchedItem) -> String {
+    format!("watchlist#last-seen#{item}")
+}
+
+// Items in config go first, then ones added by CLI or slash commands.
+pub fn get_watchlist(config: &Config, db: &Db) -> Result<Vec<WatchedItem>> {
+    let mut items = vec![];
+    let configured = config
+        .get("watchlist")
+        .and_then(|watchlist| watchlist.get("items"))
+        .and_then(|items| items.as_array())
+        .cloned()
+        .unwrap_or_default();
+    for item in configured {
+        let item = item.as_str().ok_or(IntenalError::TomlParseError)?;
+        items.push(item.parse::<WatchedItem>()?);
+    }
+    for key in db.scan_prefix("watchlist#items#").keys() {
+        let key = key?;
+        let item = String::from_utf8_lossy(&key["watchlist#items#".len()..]).parse()?;
+        if !items.contains(&item) {
+            items.push(item);
+        }
+    }
+
+    Ok(items)
+}
+
+// Return false if it's watched already.
+pub fn watch(db: &Db, item: &WatchedItem) -> Result<bool> {
+    Ok(db.insert(watched_key(item), vec![])?.is_none())
+}
+
+// Items in config can't be unwatched here, return false if it's not watched.
+// Where its timeline is read to is kept, so watching it again doesn't replay its history.
+pub fn unwatch(db: &Db, item: &WatchedItem) -> Result<bool> {
+    Ok(db.remove(watched_key(item))?.is_some())
+}
+
+pub async fn poll_item(
+    octocrab: Arc<Octocrab>,
+    db: Arc<Db>,
+    item: &WatchedItem,
+) -> Result<Vec<WatchUpdate>> {
+    let html_root = crate::utils::html_root(octocrab.base_url.as_str());
+    let timeline = get_timeline(octocrab, item).await?;
+    let state = match db.get(state_key(item))? {
+        Some(state) => Some(serde_json::from_slice::<WatchState>(&state)?),
+        None => None,
+    };
+    let (events, new_state) = unseen_events(&timeline, state.as_ref());
+    db.insert(state_key(item), serde_json::to_vec(&new_state)?)?;
+
+    let updates = events
+        .iter()
+        .filter_map(|event| {
+            describe(event).map(|description| WatchUpdate {
+                item: item.clone(),
+                event: event.event.clone(),
+                description,
+                url: event
+                    .html_url
+                    .clone()
+                    .unwrap_or_else(|| item.html_url(&html_root)),
+            })
+        })
+        .collect();
+
+    Ok(updates)
+}
+
+// Every item is polled on its own, so one failure doesn't stop the others.
+pub async fn poll_watchlist(
+    config: &Config,
+    db: Arc<Db>,
+) -> Result<Vec<(WatchedItem, Result<Vec<WatchUpdate>>)>> {
+    let mut results = vec![];
+    for item in get_watchlist(config, &db)? {
+        let octocrab =
+            crate::utils::repository_client(config, &item.organization, &item.repository)?;
+        let updates = poll_item(octocrab, db.clone(), &item).await;
+        results.push((item, updates));
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_github::{temporary_db, MockGithub};
+
+    #[test]
+    fn parse_watched_item_should_work() {
+        let item = "paritytech/substrate#12345".parse::<WatchedItem>().unwrap();
+        assert_eq!(item.organization, "paritytech");
+        assert_eq!(item.repository, "substrate");
+        assert_eq!(item.number, 12345);
+        assert_eq!(item.to_string(), "paritytech/substrate#12345");
+
+        for invalid in [
+            "paritytech/substrate",
+            "substrate#1",
+            "a/b/c#1",
+            "a/b#x",
+            "/b#1",
+        ] {
+            assert!(invalid.parse::<WatchedItem>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn unseen_events_should_work() {
+        let event = |id: Option<u64>| TimelineEvent {
+            id,
+            event: "commented".to_owned(),
+            ..Default::default()
+        };
+        let timeline = vec![event(Some(1)), event(None), event(Some(2)), event(None)];
+
+        // Nothing is new at the first time.
+        let (events, state) = unseen_events(&timeline[..2], None);
+        assert!(events.is_empty());
+        assert_eq!(
+            state,
+            WatchState {
+                last_event_id: Some(1),
+                seen_after: 1,
+                seen: 2
+            }
+        );
+
+        let (events, state) = unseen_events(&timeline, Some(&state));
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].id, Some(2));
+        assert_eq!(state.last_event_id, Some(2));
+
+        // The last seen event is deleted, fall back to how many events are seen.
+        let (events, _) = unseen_events(&timeline[1..], Some(&state));
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn poll_item_should_relay_updates_since_last_seen() {
+        let db = temporary_db();
+        let item = "paritytech/substrate#12345".parse::<WatchedItem>().unwrap();
+
+        let mock = MockGithub::start(&["github"]).await;
+        let updates = poll_item(mock.client(), db.clone(), &item).await.unwrap();
+        assert!(updates.is_empty());
+
+        let mock = MockGithub::start(&["github-watch-updated", "github"]).await;
+        let updates = poll_item(mock.client(), db.clone(), &item).await.unwrap();
+        assert_eq!(
+            updates.iter().map(|u| u.description.as_str()).collect::<Vec<_>>(),
+            vec![
+                "ggwpez commented: First step is merged.",
+                "is linked from pr Deprecate Currency traits https://github.com/paritytech/substrate/pull/12900",
+                "bkchr removed label I7-refactor",
+                "bkchr renamed it from \"Remove Currency\" to \"Remove Currency traits\"",
+                "bkchr closed it as completed",
+            ]
+        );
+        assert_eq!(
+            updates[0].url,
+            "https://github.com/paritytech/substrate/issues/12345#issuecomment-1310000002"
+        );
+        assert_eq!(
+            updates[4].url,
+            item.html_url(&crate::utils::html_root(&mock.base_url))
+        );
+
+        // Nothing new since the last poll.
+        let updates = poll_item(mock.client(), db.clone(), &item).await.unwrap();
+        assert!(updates.is_empty());
+
+        // Nor once it's watched again.
+        watch(&db, &item).unwrap();
+        unwatch(&db, &item).unwrap();
+        watch(&db, &item).unwrap();
+        let updates = poll_item(mock.client(), db.clone(), &item).await.unwrap();
+        assert!(updates.is_empty());
+    }
+
+    #[test]
+    fn watchlist_should_merge_config_and_db() {
+        let db = temporary_db();
+        let config: Config = toml::from_str(
+            r#"
+            [watchlist]
+            items = ["paritytech/substrate#12345"]
+            "#,
+        )
+        .unwrap();
+        let item = "paritytech/polkadot#6000".parse::<WatchedItem>().unwrap();
+        let configured = "paritytech/substrate#12345".parse::<WatchedItem>().unwrap();
+
+        assert!(watch(&db, &item).unwrap());
+        assert!(!watch(&db, &item).unwrap());
+        assert!(watch(&db, &configured).unwrap());
+        assert_eq!(
+            get_watchlist(&config, &db).unwrap(),
+            vec![configured.clone(), item.clone()]
+        );
+
+        assert!(unwatch(&db, &item).unwrap());
+        assert!(!unwatch(&db, &item).unwrap());
+        assert_eq!(get_watchlist(&config, &db).unwrap(), vec![configured]);
+    }
+}